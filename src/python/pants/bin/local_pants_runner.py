@@ -129,7 +129,13 @@ class LocalPantsRunner(ExceptionSink.AccessGlobalExiterMixin):
       # of the same engine functionality, but for now is separate to avoid
       # breaking functionality associated with zipkin tracing while iterating on streaming workunit reporting.
       stream_workunits = len(options.for_global_scope().streaming_workunits_handlers) != 0
-      graph_session = graph_scheduler_helper.new_session(zipkin_trace_v2, RunTracker.global_instance().run_id, v2_ui, should_report_workunits=stream_workunits)
+      graph_session = graph_scheduler_helper.new_session(
+        zipkin_trace_v2,
+        RunTracker.global_instance().run_id,
+        v2_ui,
+        should_report_workunits=stream_workunits,
+        global_options=options.for_global_scope(),
+      )
     return graph_session, graph_session.scheduler_session
 
   @staticmethod
@@ -351,6 +357,9 @@ class LocalPantsRunner(ExceptionSink.AccessGlobalExiterMixin):
     engine_workunits = self._scheduler_session.engine_workunits(metrics)
     if engine_workunits:
       self._run_tracker.report.bulk_record_workunits(engine_workunits)
+    metrics_report = self._global_options.native_engine_metrics_report
+    if metrics_report:
+      self._scheduler_session.write_node_metrics_to_file(metrics_report)
 
   def _run(self):
     global_options = self._options.for_global_scope()
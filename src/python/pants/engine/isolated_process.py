@@ -40,6 +40,12 @@ class ExecuteProcessRequest:
   unsafe_local_only_files_because_we_favor_speed_over_correctness_for_this_rule: Digest
   jdk_home: Optional[str]
   is_nailgunnable: bool
+  cache_failures: bool
+  retry_max_attempts: int
+  retryable_exit_codes: Tuple[int, ...]
+  execution_priority: Optional[int]
+  skip_cache_lookup: bool
+  do_not_cache: bool
 
   def __init__(
     self,
@@ -55,6 +61,12 @@ class ExecuteProcessRequest:
     unsafe_local_only_files_because_we_favor_speed_over_correctness_for_this_rule: Digest = EMPTY_DIRECTORY_DIGEST,
     jdk_home: Optional[str] = None,
     is_nailgunnable: bool = False,
+    cache_failures: bool = False,
+    retry_max_attempts: int = 1,
+    retryable_exit_codes: Tuple[int, ...] = (),
+    execution_priority: Optional[int] = None,
+    skip_cache_lookup: bool = False,
+    do_not_cache: bool = False,
   ) -> None:
     self.argv = argv
     self.input_files = input_files
@@ -67,6 +79,14 @@ class ExecuteProcessRequest:
     self.unsafe_local_only_files_because_we_favor_speed_over_correctness_for_this_rule = unsafe_local_only_files_because_we_favor_speed_over_correctness_for_this_rule
     self.jdk_home = jdk_home
     self.is_nailgunnable = is_nailgunnable
+    self.cache_failures = cache_failures
+    self.retry_max_attempts = retry_max_attempts
+    # Processes killed by a signal have the negated signal number as their exit code.
+    self.retryable_exit_codes = retryable_exit_codes
+    # If unset, the priority configured for the session (if any) is used.
+    self.execution_priority = execution_priority
+    self.skip_cache_lookup = skip_cache_lookup
+    self.do_not_cache = do_not_cache
 
 
 @frozen_after_init
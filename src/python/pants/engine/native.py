@@ -2,6 +2,7 @@
 # Licensed under the Apache License, Version 2.0 (see LICENSE).
 
 import importlib
+import json
 import logging
 import os
 import re
@@ -885,7 +886,18 @@ class Native(metaclass=SingletonMetaclass):
       self.lib.execution_request_create(),
       self.lib.execution_request_destroy)
 
-  def new_session(self, scheduler, should_record_zipkin_spans, should_render_ui, ui_worker_count, build_id, should_report_workunits: bool):
+  def new_session(
+    self,
+    scheduler,
+    should_record_zipkin_spans,
+    should_render_ui,
+    ui_worker_count,
+    build_id,
+    should_report_workunits: bool,
+    process_execution_priority: int,
+    process_execution_skip_cache_lookup: bool,
+    process_execution_do_not_cache: bool,
+  ):
     return self.gc(
       self.lib.session_create(
         scheduler,
@@ -894,6 +906,9 @@ class Native(metaclass=SingletonMetaclass):
         ui_worker_count,
         self.context.utf8_buf(build_id),
         should_report_workunits,
+        process_execution_priority,
+        process_execution_skip_cache_lookup,
+        process_execution_do_not_cache,
       ),
       self.lib.session_destroy
     )
@@ -976,6 +991,12 @@ class Native(metaclass=SingletonMetaclass):
         execution_options.process_execution_use_local_cache,
         self.context.utf8_dict(execution_options.remote_execution_headers),
         execution_options.process_execution_local_enable_nailgun,
+        execution_options.process_execution_failure_cache_ttl,
+        self.context.utf8_buf_buf(
+          json.dumps(backend, sort_keys=True) for backend in execution_options.remote_execution_backends
+        ),
+        execution_options.engine_graph_max_nodes,
+        execution_options.engine_graph_max_estimated_bytes,
       )
     if scheduler_result.is_throw:
       value = self.context.from_value(scheduler_result.throw_handle)
@@ -4,7 +4,7 @@
 import logging
 from abc import ABC
 from dataclasses import dataclass
-from typing import Any
+from typing import Any, Tuple
 
 
 logger = logging.getLogger(__name__)
@@ -48,7 +48,32 @@ class Return(State):
     return (self.value,)
 
 
+@dataclass(frozen=True)
+class RuleFrame:
+  """A rule that failed while computing `product` for `params`, named by `rule`."""
+  rule: str
+  product: str
+  params: str
+
+
 @dataclass(frozen=True)
 class Throw(State):
-  """Indicates that a Node should have been able to return a value, but failed."""
+  """Indicates that a Node should have been able to return a value, but failed.
+
+  The `rule_trace` lists the rules that the failure propagated through, beginning with the rule
+  that raised the exception.
+  """
   exc: Any
+  rule_trace: Tuple[RuleFrame, ...] = ()
+
+  def render(self):
+    """Renders the exception, followed by the chain of rules that led to it."""
+    lines = ['{}: {}'.format(type(self.exc).__name__, str(self.exc))]
+    for i, frame in enumerate(self.rule_trace):
+      lines.append('  {} {} for {} in {}'.format(
+        'while computing' if i == 0 else 'needed by',
+        frame.product,
+        frame.params,
+        frame.rule,
+      ))
+    return '\n'.join(lines)
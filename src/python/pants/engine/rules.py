@@ -86,7 +86,8 @@ def _make_rule(
   parameter_types: typing.Iterable[Type],
   *,
   cacheable: bool = True,
-  name: Optional[str] = None
+  name: Optional[str] = None,
+  priority: int = 0,
 ) -> Callable[[Callable], Callable]:
   """A @decorator that declares that a particular static function may be used as a TaskRule.
 
@@ -98,6 +99,9 @@ def _make_rule(
                           decorated function.
   :param cacheable: Whether the results of executing the Rule should be cached as keyed by all of
                     its inputs.
+  :param priority: When more than one Rule could compute the same product for the same Params,
+                   the Rule with the highest priority is used. Defaults to 0, which allows a plugin
+                   to override a builtin Rule by declaring a higher priority.
   """
 
   has_goal_return_type = issubclass(return_type, Goal)
@@ -164,6 +168,7 @@ def _make_rule(
         dependency_rules=dependency_rules,
         cacheable=cacheable,
         name=effective_name,
+        priority=priority,
       )
 
     return func
@@ -201,7 +206,7 @@ def _ensure_type_annotation(
   return annotation
 
 
-PUBLIC_RULE_DECORATOR_ARGUMENTS = {'name'}
+PUBLIC_RULE_DECORATOR_ARGUMENTS = {'name', 'priority'}
 # We don't want @rule-writers to use 'cacheable' as a kwarg directly, but rather
 # set it implicitly based on whether the rule annotation is @rule or @goal_rule.
 # So we leave it out of PUBLIC_RULE_DECORATOR_ARGUMENTS.
@@ -224,6 +229,7 @@ def rule_decorator(*args, **kwargs) -> Callable:
 
   cacheable: bool = kwargs['cacheable']
   name: Optional[str] = kwargs.get('name')
+  priority: int = kwargs.get('priority', 0)
 
   signature = inspect.signature(func)
   func_id = f'@rule {func.__module__}:{func.__name__}'
@@ -243,7 +249,9 @@ def rule_decorator(*args, **kwargs) -> Callable:
     for name, parameter in signature.parameters.items()
   )
   validate_parameter_types(func_id, parameter_types, cacheable)
-  return _make_rule(return_type, parameter_types, cacheable=cacheable, name=name)(func)
+  return _make_rule(
+    return_type, parameter_types, cacheable=cacheable, name=name, priority=priority
+  )(func)
 
 
 def validate_parameter_types(func_id: str, parameter_types: Tuple[Type, ...], cacheable: bool) -> None:
@@ -340,6 +348,7 @@ class TaskRule(Rule):
   _dependency_optionables: Tuple
   cacheable: bool
   name: Optional[str]
+  priority: int
 
   def __init__(
     self,
@@ -351,6 +360,7 @@ class TaskRule(Rule):
     dependency_optionables: Optional[Tuple] = None,
     cacheable: bool = True,
     name: Optional[str] = None,
+    priority: int = 0,
   ):
     self._output_type = output_type
     self.input_selectors = input_selectors
@@ -360,6 +370,7 @@ class TaskRule(Rule):
     self._dependency_optionables = dependency_optionables or ()
     self.cacheable = cacheable
     self.name = name
+    self.priority = priority
 
   def __str__(self):
     return ('(name={}, {}, {!r}, {}, gets={}, opts={})'
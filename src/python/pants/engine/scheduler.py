@@ -1,7 +1,9 @@
 # Copyright 2015 Pants project contributors (see CONTRIBUTORS.md).
 # Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+import json
 import logging
+import math
 import multiprocessing
 import os
 import sys
@@ -9,7 +11,7 @@ import time
 import traceback
 from dataclasses import dataclass
 from textwrap import dedent
-from typing import TYPE_CHECKING, Any, Dict, Tuple
+from typing import TYPE_CHECKING, Any, Dict, Iterable, Optional, Tuple
 
 from pants.base.exception_sink import ExceptionSink
 from pants.base.exiter import PANTS_FAILED_EXIT_CODE
@@ -21,7 +23,7 @@ from pants.engine.fs import (
   PathGlobsAndRoot,
 )
 from pants.engine.native import Function, TypeId
-from pants.engine.nodes import Return, Throw
+from pants.engine.nodes import Return, RuleFrame, Throw
 from pants.engine.objects import Collection, union
 from pants.engine.rules import RuleIndex, TaskRule
 from pants.engine.selectors import Params
@@ -125,9 +127,47 @@ class Scheduler:
         for line in fd.readlines():
           yield line.rstrip()
 
+  def invalidation_chains(self, execution_request):
+    return self._from_value(
+      self._native.lib.execution_invalidation_chains(self._scheduler, execution_request)
+    )
+
+  def query_graph(self, execution_request, dependents, max_depth, variants):
+    res = self._native.lib.graph_query_request(
+      self._scheduler,
+      execution_request,
+      dependents,
+      max_depth or 0,
+      self._native.context.utf8_buf_buf(variants),
+    )
+    return self._raise_or_return(res)
+
+  def query_graph_paths(self, paths, dependents, max_depth, variants):
+    res = self._native.lib.graph_query_paths(
+      self._scheduler,
+      self._native.context.utf8_buf_buf(paths),
+      dependents,
+      max_depth or 0,
+      self._native.context.utf8_buf_buf(variants),
+    )
+    return self._raise_or_return(res)
+
   def _assert_ruleset_valid(self):
     self._raise_or_return(self._native.lib.validator_run(self._scheduler))
 
+  def rule_graph_diagnostics(self):
+    """Returns a list of dicts describing each rule with errors in the rule graph, followed by
+    each rule that was shadowed by higher priority rules.
+
+    Each dict has a `rule` and a list of `diagnostics`, each of which has a `kind` (one of
+    `unfulfillable`, `unreachable`, `ambiguous` or `invalid_union_member`, or `shadowed`, which is
+    not an error), the `params` and `product` that could not be computed, a human readable `reason`
+    and `details`, and the `candidates` that might have computed the product (or, for a shadowed
+    rule, the rules that were chosen instead).
+    """
+    res = self._native.lib.validator_diagnostics_json(self._scheduler)
+    return json.loads(self._raise_or_return(res))
+
   def _to_vals_buf(self, objs):
     return self._native.context.vals_buf(tuple(self._native.context.to_value(obj) for obj in objs))
 
@@ -169,31 +209,36 @@ class Scheduler:
         registered.add(key)
 
         if type(rule) is TaskRule:
-          self._register_task(output_type, rule, rule_index.union_rules)
+          self._register_task(output_type, rule)
         else:
           raise ValueError('Unexpected Rule type: {}'.format(rule))
 
-  def _register_task(self, output_type, rule: TaskRule, union_rules):
+    for union_base, union_members in rule_index.union_rules.items():
+      for union_member in union_members:
+        self._native.lib.tasks_add_union_member(
+          self._tasks, self._to_type(union_base), self._to_type(union_member)
+        )
+
+  def _register_task(self, output_type, rule: TaskRule):
     """Register the given TaskRule with the native scheduler."""
     func = Function(self._to_key(rule.func))
-    self._native.lib.tasks_task_begin(self._tasks, func, self._to_type(output_type), rule.cacheable)
+    self._native.lib.tasks_task_begin(
+      self._tasks, func, self._to_type(output_type), rule.cacheable, rule.priority
+    )
     for selector in rule.input_selectors:
       self._native.lib.tasks_add_select(self._tasks, self._to_type(selector))
 
     if rule.name:
       self._native.lib.tasks_add_display_info(self._tasks, rule.name.encode())
 
-    def add_get_edge(product, subject):
-      self._native.lib.tasks_add_get(self._tasks, self._to_type(product), self._to_type(subject))
-
+    # NB: A Get whose subject is a union base is expanded by the rule graph into a Get for each
+    # registered member of the union.
     for the_get in rule.input_gets:
       if union.is_instance(the_get.subject_declared_type):
-        # If the registered subject type is a union, add Get edges to all registered union members.
-        for union_member in union_rules.get(the_get.subject_declared_type, []):
-          add_get_edge(the_get.product, union_member)
-      else:
-        # Otherwise, the Get subject is a "concrete" type, so add a single Get edge.
-        add_get_edge(the_get.product, the_get.subject_declared_type)
+        self._native.lib.tasks_add_union(self._tasks, self._to_type(the_get.subject_declared_type))
+      self._native.lib.tasks_add_get(
+        self._tasks, self._to_type(the_get.product), self._to_type(the_get.subject_declared_type)
+      )
 
     self._native.lib.tasks_task_end(self._tasks)
 
@@ -201,6 +246,27 @@ class Scheduler:
     res = self._native.lib.graph_visualize(self._scheduler, session, filename.encode())
     self._raise_or_return(res)
 
+  def visualize_graph_to_json_file(self, session, filename, variants, min_duration, subgraph_root):
+    # Round the duration up to whole microseconds, so that a small (but non-zero) minimum still
+    # filters out the nodes that have not run.
+    res = self._native.lib.graph_visualize_json(
+      self._scheduler,
+      session,
+      filename.encode(),
+      self._native.context.utf8_buf_buf(variants),
+      math.ceil(min_duration * 1000000) if min_duration else 0,
+      subgraph_root if subgraph_root is not None else self._native.ffi.NULL,
+    )
+    self._raise_or_return(res)
+
+  def write_trace_events_to_file(self, session, filename):
+    res = self._native.lib.graph_trace_events(self._scheduler, session, filename.encode())
+    self._raise_or_return(res)
+
+  def write_node_metrics_to_file(self, session, filename):
+    res = self._native.lib.session_node_metrics_report(self._scheduler, session, filename.encode())
+    self._raise_or_return(res)
+
   def visualize_rule_graph_to_file(self, filename):
     self._native.lib.rule_graph_visualize(
       self._scheduler,
@@ -228,6 +294,44 @@ class Scheduler:
         for line in fd.readlines():
           yield line.rstrip()
 
+  def export_rule_graph(self, graphml=False):
+    """Exports the full rule graph as JSON, or as GraphML if `graphml` is True.
+
+    The export contains the entries of the graph (roots, rules and params, with the params that each
+    consumes), the edges between them labeled with their dependency keys, and the unreachable and
+    unfulfillable rules of the graph.
+    """
+    res = self._native.lib.rule_graph_export(self._scheduler, self._root_type_ids(), graphml)
+    return self._raise_or_return(res)
+
+  def export_rule_subgraph(self, param_types, product_type, graphml=False):
+    """Exports the rule graph below the root that computes `product_type` for `param_types`.
+
+    The format is the same as for `export_rule_graph`.
+    """
+    res = self._native.lib.rule_subgraph_export(
+      self._scheduler,
+      self._to_ids_buf(param_types),
+      TypeId(self._to_id(product_type)),
+      graphml,
+    )
+    return self._raise_or_return(res)
+
+  def explain_rule_graph(self, param_types, product_type, dot=False):
+    """Explains how the rule graph would compute the given product for the given param types.
+
+    The explanation lists each rule that could compute the product, the params that it would
+    consume, and the reason it was pruned if it was not chosen, recursing into the dependencies of
+    each rule. It is rendered as text, or as a DOT graph if `dot` is True.
+    """
+    res = self._native.lib.rule_graph_explain(
+      self._scheduler,
+      self._to_ids_buf(param_types),
+      TypeId(self._to_id(product_type)),
+      dot,
+    )
+    return self._raise_or_return(res)
+
   def invalidate_files(self, direct_filenames):
     # NB: Watchman no longer triggers events when children are created/deleted under a directory,
     # so we always need to invalidate the direct parent as well.
@@ -242,6 +346,11 @@ class Scheduler:
   def graph_len(self):
     return self._native.lib.graph_len(self._scheduler)
 
+  def set_execution_timeout(self, execution_request, timeout):
+    self._native.lib.execution_set_timeout(
+      execution_request, int(timeout * 1000) if timeout else 0
+    )
+
   def add_root_selection(self, execution_request, subject_or_params, product):
     if isinstance(subject_or_params, Params):
       params = subject_or_params.params
@@ -259,6 +368,9 @@ class Scheduler:
   def _metrics(self, session):
     return self._from_value(self._native.lib.scheduler_metrics(self._scheduler, session))
 
+  def _cancel(self, session):
+    self._native.lib.session_cancel(self._scheduler, session)
+
   def poll_workunits(self, session) -> Tuple[Dict[str, Any], ...]:
     result: Tuple[Dict[str, Any], ...] = self._from_value(self._native.lib.poll_session_workunits(self._scheduler, session))
     return result
@@ -271,14 +383,62 @@ class Scheduler:
   def _run_and_return_roots(self, session, execution_request):
     raw_roots = self._native.lib.scheduler_execute(self._scheduler, session, execution_request)
     remaining_runtime_exceptions_to_capture = list(self._native.consume_cffi_extern_method_runtime_exceptions())
+    roots = self._unpack_roots(raw_roots, remaining_runtime_exceptions_to_capture)
+
+    if remaining_runtime_exceptions_to_capture:
+      raise ExecutionError('Internal logic error in scheduler: expected elements in '
+                           '`self._native._peek_cffi_extern_method_runtime_exceptions()`.')
+    return roots
+
+  def _stream_roots(self, session, execution_request, fail_fast, poll_interval):
+    """Yields an (index, state) tuple for each root of the request as it completes.
+
+    If the generator is closed before all roots have completed, the remaining roots stop waiting.
+    """
+    stream = self._native.lib.scheduler_execute_streaming(
+      self._scheduler, session, execution_request, fail_fast
+    )
+    remaining_runtime_exceptions_to_capture = []
+    try:
+      while True:
+        poll = self._native.lib.execution_stream_poll(
+          self._scheduler, stream, int(poll_interval * 1000)
+        )
+        remaining_runtime_exceptions_to_capture.extend(
+          self._native.consume_cffi_extern_method_runtime_exceptions()
+        )
+        if poll.done:
+          break
+        if poll.nodes == self._native.ffi.NULL:
+          continue
+        state, = self._unpack_roots(poll.nodes, remaining_runtime_exceptions_to_capture)
+        yield poll.index, state
+    finally:
+      self._native.lib.execution_stream_destroy(self._scheduler, stream)
+
+    if remaining_runtime_exceptions_to_capture:
+      raise ExecutionError('Internal logic error in scheduler: expected elements in '
+                           '`self._native._peek_cffi_extern_method_runtime_exceptions()`.')
+
+  def _unpack_roots(self, raw_roots, remaining_runtime_exceptions_to_capture):
+    """Unpacks and destroys the given RawNodes, returning a Return or Throw for each.
+
+    Roots which failed with uncaught exceptions within rules are matched with exceptions from the
+    front of the given list, which is extended with any newly captured exceptions.
+    """
     try:
       roots = []
-      for raw_root in self._native.unpack(raw_roots.nodes_ptr, raw_roots.nodes_len):
+      raw_rule_traces = self._native.unpack(raw_roots.rule_traces_ptr, raw_roots.nodes_len)
+      for raw_root, raw_rule_trace in zip(
+        self._native.unpack(raw_roots.nodes_ptr, raw_roots.nodes_len),
+        raw_rule_traces,
+      ):
         # Check if there were any uncaught exceptions within rules that were executed.
         remaining_runtime_exceptions_to_capture.extend(self._native.consume_cffi_extern_method_runtime_exceptions())
 
         if raw_root.is_throw:
-          state = Throw(self._from_value(raw_root.handle))
+          rule_trace = tuple(RuleFrame(*frame) for frame in self._from_value(raw_rule_trace))
+          state = Throw(self._from_value(raw_root.handle), rule_trace=rule_trace)
         elif raw_root.handle == self._native.ffi.NULL:
           # NB: We expect all NULL handles to correspond to uncaught exceptions which are collected
           # in `self._native._peek_cffi_extern_method_runtime_exceptions()`!
@@ -292,10 +452,6 @@ class Scheduler:
         roots.append(state)
     finally:
       self._native.lib.nodes_destroy(raw_roots)
-
-    if remaining_runtime_exceptions_to_capture:
-      raise ExecutionError('Internal logic error in scheduler: expected elements in '
-                           '`self._native._peek_cffi_extern_method_runtime_exceptions()`.')
     return roots
 
   def lease_files_in_graph(self):
@@ -304,11 +460,32 @@ class Scheduler:
   def garbage_collect_store(self):
     self._native.lib.garbage_collect_store(self._scheduler)
 
-  def new_session(self, zipkin_trace_v2, build_id, v2_ui=False, should_report_workunits=False):
-    """Creates a new SchedulerSession for this Scheduler."""
+  def new_session(
+    self,
+    zipkin_trace_v2,
+    build_id,
+    v2_ui=False,
+    should_report_workunits=False,
+    process_execution_priority=0,
+    process_execution_skip_cache_lookup=False,
+    process_execution_do_not_cache=False,
+  ):
+    """Creates a new SchedulerSession for this Scheduler.
+
+    The process_execution_* arguments are defaults for the processes executed in the Session: see
+    the options of the same names.
+    """
     return SchedulerSession(self, self._native.new_session(
-      self._scheduler, zipkin_trace_v2, v2_ui, multiprocessing.cpu_count(), build_id, should_report_workunits)
-    )
+      self._scheduler,
+      zipkin_trace_v2,
+      v2_ui,
+      multiprocessing.cpu_count(),
+      build_id,
+      should_report_workunits,
+      process_execution_priority,
+      process_execution_skip_cache_lookup,
+      process_execution_do_not_cache,
+    ))
 
 
 class _PathGlobsAndRootCollection(Collection[PathGlobsAndRoot]):
@@ -349,6 +526,57 @@ class SchedulerSession:
     for line in self._scheduler.graph_trace(execution_request.native):
       yield line
 
+  def invalidation_chains(self, execution_request) -> Tuple[str, ...]:
+    """Explains why the roots of the given request were most recently invalidated.
+
+    Returns one line per chain of nodes from an invalidated node (generally: a changed file),
+    through its dependents, to a root of the request. Each node is annotated with whether it
+    re-ran, or was cleaned without re-running because its dependencies had not actually changed.
+    """
+    result: Tuple[str, ...] = self._scheduler.invalidation_chains(execution_request.native)
+    return result
+
+  def query_graph(
+    self,
+    execution_request,
+    *,
+    dependents: bool = False,
+    max_depth: Optional[int] = None,
+    variants: Iterable[str] = (),
+  ) -> Tuple[Dict[str, Any], ...]:
+    """Returns the transitive dependencies (or dependents) of the roots of the given request.
+
+    Only nodes that are currently in the graph are returned. Each is described by a dict with its
+    `node` (rendered as a string), its `variant` (the kind of node, e.g. `Task` or `Scandir`), its
+    `depth` (the distance from the nearest root), and its `path` (for filesystem nodes, else None).
+
+    :param dependents: True to query for dependents rather than dependencies.
+    :param max_depth: If set, the maximum distance from the roots to query.
+    :param variants: If non-empty, only nodes of these variants are returned.
+    """
+    result: Tuple[Dict[str, Any], ...] = self._scheduler.query_graph(
+      execution_request.native, dependents, max_depth, tuple(variants)
+    )
+    return result
+
+  def query_graph_paths(
+    self,
+    paths: Iterable[str],
+    *,
+    dependents: bool = True,
+    max_depth: Optional[int] = None,
+    variants: Iterable[str] = (),
+  ) -> Tuple[Dict[str, Any], ...]:
+    """Like `query_graph`, but starting from the filesystem nodes for the given paths.
+
+    By default this returns the transitive dependents of the paths: i.e., what would be
+    invalidated if they changed.
+    """
+    result: Tuple[Dict[str, Any], ...] = self._scheduler.query_graph_paths(
+      tuple(paths), dependents, max_depth, tuple(variants)
+    )
+    return result
+
   def visualize_graph_to_file(self, filename):
     """Visualize a graph walk by writing graphviz `dot` output to a file.
 
@@ -356,16 +584,56 @@ class SchedulerSession:
     """
     self._scheduler.visualize_graph_to_file(self._session, filename)
 
+  def visualize_graph_to_json_file(
+    self,
+    filename: str,
+    *,
+    variants: Iterable[str] = (),
+    min_duration: Optional[float] = None,
+    subgraph_root: Optional[ExecutionRequest] = None,
+  ) -> None:
+    """Write the nodes and edges of a graph walk to a file as JSON.
+
+    Each node includes its id, kind (`variant`), display string, state, duration, and generation.
+    Nodes that do not match the filters are elided, with their dependents connected to their
+    nearest matching dependencies.
+
+    :param filename: The filename to output the JSON to.
+    :param variants: If non-empty, only nodes of these variants (e.g. `Task`) are included.
+    :param min_duration: If set, only nodes that ran for at least this many seconds are included.
+    :param subgraph_root: If set, a request whose roots to render the subgraph below, rather than
+      the graph below the roots of this session.
+    """
+    self._scheduler.visualize_graph_to_json_file(
+      self._session,
+      filename,
+      tuple(variants),
+      min_duration,
+      subgraph_root.native if subgraph_root is not None else None,
+    )
+
+  def write_trace_events_to_file(self, filename):
+    """Write a profile of this session in the Chrome `trace_event` format to a file.
+
+    The profile can be viewed in `about:tracing` or Perfetto, and includes the nodes that have run
+    for the session (with the critical path highlighted) and its workunits.
+
+    :param str filename: The filename to output the profile to.
+    """
+    self._scheduler.write_trace_events_to_file(self._session, filename)
+
   def visualize_rule_graph_to_file(self, filename):
     self._scheduler.visualize_rule_graph_to_file(filename)
 
-  def execution_request_literal(self, request_specs):
+  def execution_request_literal(self, request_specs, timeout: Optional[float] = None):
     native_execution_request = self._scheduler._native.new_execution_request()
     for subject, product in request_specs:
       self._scheduler.add_root_selection(native_execution_request, subject, product)
+    if timeout:
+      self._scheduler.set_execution_timeout(native_execution_request, timeout)
     return ExecutionRequest(request_specs, native_execution_request)
 
-  def execution_request(self, products, subjects):
+  def execution_request(self, products, subjects, timeout: Optional[float] = None):
     """Create and return an ExecutionRequest for the given products and subjects.
 
     The resulting ExecutionRequest object will contain keys tied to this scheduler's product Graph,
@@ -379,10 +647,12 @@ class SchedulerSession:
     :param subjects: A list of AddressSpec and/or PathGlobs objects.
     :type subject: list of :class:`pants.base.specs.AddressSpec`, `pants.build_graph.Address`, and/or
       :class:`pants.engine.fs.PathGlobs` objects.
+    :param timeout: If set, the number of seconds after which any roots of the request that have
+      not completed fail with an error naming the longest running nodes.
     :returns: An ExecutionRequest for the given products and subjects.
     """
     roots = (tuple((s, p) for s in subjects for p in products))
-    return self.execution_request_literal(roots)
+    return self.execution_request_literal(roots, timeout=timeout)
 
   def invalidate_files(self, direct_filenames):
     """Invalidates the given filenames in an internal product Graph instance."""
@@ -403,10 +673,37 @@ class SchedulerSession:
     """Returns metrics for this SchedulerSession as a dict of metric name to metric value."""
     return self._scheduler._metrics(self._session)
 
+  def cancel(self):
+    """Cancels this SchedulerSession.
+
+    Any in-progress or subsequent calls to `execute` will fail for all of their roots. May be
+    called from a thread other than the one that is blocked in `execute`. Work which another
+    SchedulerSession is also waiting for continues to run.
+    """
+    self._scheduler._cancel(self._session)
+
   @staticmethod
   def engine_workunits(metrics):
     return metrics.get("engine_workunits")
 
+  @staticmethod
+  def node_metrics(metrics):
+    """Returns the node metrics from the given metrics for this SchedulerSession.
+
+    The node metrics count the nodes that were created, cleaned without re-running, run and re-run,
+    and their total and (approximate) p95 runtimes, broken down by node variant (under "variants")
+    and by @rule (under "rules"). The results of processes are counted by whether they were hits in
+    the local or remote cache, or were executed (under "processes").
+    """
+    return metrics.get("node_metrics")
+
+  def write_node_metrics_to_file(self, filename):
+    """Write the node metrics of this session to a file as JSON.
+
+    :param str filename: The filename to output the metrics to.
+    """
+    self._scheduler.write_node_metrics_to_file(self._session, filename)
+
   def with_fork_context(self, func):
     return self._scheduler.with_fork_context(func)
 
@@ -440,7 +737,27 @@ class SchedulerSession:
     throws = tuple((root, state) for root, state in roots if type(state) is Throw)
     return returns, throws
 
-  def _trace_on_error(self, unique_exceptions, request):
+  def execute_streaming(self, execution_request, fail_fast=False, poll_interval=0.1):
+    """Invoke the engine for the given ExecutionRequest, yielding each root as it completes.
+
+    :param fail_fast: If True, the first root to fail causes any roots which have not yet completed
+      to fail as cancelled.
+    :param poll_interval: The number of seconds to wait for a root to complete before checking for
+      interrupts and waiting again.
+    :return: A generator of (root, Return) and (root, Throw) tuples, in the order that the roots
+      complete.
+    """
+    try:
+      for index, state in self._scheduler._stream_roots(
+        self._session, execution_request.native, fail_fast, poll_interval
+      ):
+        yield execution_request.roots[index], state
+    finally:
+      ExceptionSink.toggle_ignoring_sigint_v2_engine(False)
+      self._maybe_visualize()
+
+  def _trace_on_error(self, unique_throws, request):
+    unique_exceptions = tuple(t.exc for t in unique_throws)
     exception_noun = pluralize(len(unique_exceptions), 'Exception')
     if self._scheduler.include_trace_on_error:
       cumulative_trace = '\n'.join(self.trace(request))
@@ -452,7 +769,7 @@ class SchedulerSession:
       raise ExecutionError(
         '{} encountered:\n  {}'.format(
           exception_noun,
-          '\n  '.join('{}: {}'.format(type(t).__name__, str(t)) for t in unique_exceptions)),
+          '\n  '.join(t.render().replace('\n', '\n  ') for t in unique_throws)),
         unique_exceptions
       )
 
@@ -467,8 +784,7 @@ class SchedulerSession:
 
     if throws:
       _, state = throws[0]
-      exc = state.exc
-      self._trace_on_error([exc], request)
+      self._trace_on_error([state], request)
       return PANTS_FAILED_EXIT_CODE
     _, state = returns[0]
     return state.value.exit_code
@@ -530,8 +846,11 @@ class SchedulerSession:
 
     # Throw handling.
     if throws:
-      unique_exceptions = tuple({t.exc for _, t in throws})
-      self._trace_on_error(unique_exceptions, request)
+      # Deduplicate by exception, keeping the rule trace of the first root that failed with each.
+      unique_throws = {}
+      for _, t in throws:
+        unique_throws.setdefault(t.exc, t)
+      self._trace_on_error(tuple(unique_throws.values()), request)
 
     # Everything is a Return: we rely on the fact that roots are ordered to preserve subject
     # order in output lists.
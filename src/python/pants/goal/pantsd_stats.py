@@ -9,7 +9,10 @@ class PantsDaemonStats:
     self.scheduler_metrics = {}
 
   def set_scheduler_metrics(self, scheduler_metrics):
-    self.scheduler_metrics = {key: value for (key, value) in scheduler_metrics.items() if key != "engine_workunits"}
+    self.scheduler_metrics = {
+      key: value for (key, value) in scheduler_metrics.items()
+      if key not in ("engine_workunits", "node_metrics")
+    }
 
   def set_target_root_size(self, size):
     self.scheduler_metrics['target_root_size'] = size
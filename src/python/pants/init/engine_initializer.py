@@ -176,9 +176,28 @@ class LegacyGraphScheduler:
   goal_map: Any
 
   def new_session(
-    self, zipkin_trace_v2, build_id, v2_ui=False, should_report_workunits=False
+    self,
+    zipkin_trace_v2,
+    build_id,
+    v2_ui=False,
+    should_report_workunits=False,
+    global_options=None,
   ) -> "LegacyGraphSession":
-    session = self.scheduler.new_session(zipkin_trace_v2, build_id, v2_ui, should_report_workunits)
+    """Creates a new LegacyGraphSession.
+
+    :param global_options: If given, the global scope options, from which defaults for the
+                           processes executed in the session are read.
+    """
+    process_execution_defaults = {}
+    if global_options is not None:
+      process_execution_defaults = dict(
+        process_execution_priority=global_options.process_execution_priority,
+        process_execution_skip_cache_lookup=global_options.process_execution_skip_cache_lookup,
+        process_execution_do_not_cache=global_options.process_execution_do_not_cache,
+      )
+    session = self.scheduler.new_session(
+      zipkin_trace_v2, build_id, v2_ui, should_report_workunits, **process_execution_defaults
+    )
     return LegacyGraphSession(session, self.build_file_aliases, self.goal_map)
 
 
@@ -63,6 +63,10 @@ class ExecutionOptions:
   process_execution_speculation_delay: Any
   process_execution_speculation_strategy: Any
   process_execution_use_local_cache: Any
+  process_execution_failure_cache_ttl: Any
+  remote_execution_backends: Any
+  engine_graph_max_nodes: Any
+  engine_graph_max_estimated_bytes: Any
   remote_execution_process_cache_namespace: Any
   remote_instance_name: Any
   remote_ca_certs_path: Any
@@ -88,6 +92,10 @@ class ExecutionOptions:
       process_execution_speculation_delay=bootstrap_options.process_execution_speculation_delay,
       process_execution_speculation_strategy=bootstrap_options.process_execution_speculation_strategy,
       process_execution_use_local_cache=bootstrap_options.process_execution_use_local_cache,
+      process_execution_failure_cache_ttl=bootstrap_options.process_execution_failure_cache_ttl,
+      remote_execution_backends=bootstrap_options.remote_execution_backends,
+      engine_graph_max_nodes=bootstrap_options.engine_graph_max_nodes,
+      engine_graph_max_estimated_bytes=bootstrap_options.engine_graph_max_estimated_bytes,
       remote_execution_process_cache_namespace=bootstrap_options.remote_execution_process_cache_namespace,
       remote_instance_name=bootstrap_options.remote_instance_name,
       remote_ca_certs_path=bootstrap_options.remote_ca_certs_path,
@@ -113,6 +121,10 @@ DEFAULT_EXECUTION_OPTIONS = ExecutionOptions(
     process_execution_speculation_delay=1,
     process_execution_speculation_strategy='local_first',
     process_execution_use_local_cache=True,
+    process_execution_failure_cache_ttl=600,
+    remote_execution_backends=[],
+    engine_graph_max_nodes=0,
+    engine_graph_max_estimated_bytes=0,
     remote_execution_process_cache_namespace=None,
     remote_instance_name=None,
     remote_ca_certs_path=None,
@@ -350,6 +362,9 @@ class GlobalOptionsRegistrar(SubsystemClientMixin, Optionable):
     register('--native-engine-visualize-to', advanced=True, default=None, type=dir_option, daemon=False,
              help='A directory to write execution and rule graphs to as `dot` files. The contents '
                   'of the directory will be overwritten if any filenames collide.')
+    register('--native-engine-metrics-report', advanced=True, default=None, type=str, daemon=False,
+             help='A file to write a JSON report of per-node-variant and per-rule engine metrics to '
+                  'at the end of the run.')
     register('--print-exception-stacktrace', advanced=True, type=bool,
              help='Print to console the full exception stack trace if encountered.')
 
@@ -457,6 +472,13 @@ class GlobalOptionsRegistrar(SubsystemClientMixin, Optionable):
                   'Format: property=value. Multiple values should be specified as multiple '
                   'occurrences of this flag. Pants itself may add additional platform properties.',
                    type=list, default=[])
+    register('--remote-execution-backends', advanced=True, type=list, member_type=dict,
+             default=DEFAULT_EXECUTION_OPTIONS.remote_execution_backends,
+             help='Additional remote execution backends, which are used for processes which must '
+                  'run on their platform (requests for any platform use --remote-execution-server). '
+                  'Each is a dict with the keys `server`, `platform` (`linux` or `darwin`), and '
+                  'optionally `instance_name` and `extra_platform_properties` (a dict). All backends '
+                  'must share the CAS configured by --remote-store-server.')
     register('--remote-execution-headers', advanced=True,
              help='Headers to set on remote execution requests. '
                   'Format: header=value. Pants itself may add additional headers.',
@@ -486,9 +508,21 @@ class GlobalOptionsRegistrar(SubsystemClientMixin, Optionable):
              advanced=True)
     register('--process-execution-use-local-cache', type=bool, default=True, advanced=True,
              help='Whether to keep process executions in a local cache persisted to disk.')
+    register('--process-execution-failure-cache-ttl', type=int,
+             default=DEFAULT_EXECUTION_OPTIONS.process_execution_failure_cache_ttl, advanced=True,
+             help='Number of seconds for which a failed process execution is reused from the local '
+                  'cache, for processes which opt in to caching failures.')
     register('--process-execution-local-enable-nailgun', type=bool, default=DEFAULT_EXECUTION_OPTIONS.process_execution_local_enable_nailgun,
              help='Whether or not to use nailgun to run the requests that are marked as nailgunnable.',
              advanced=True)
+    register('--engine-graph-max-nodes', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.engine_graph_max_nodes,
+             help='The number of nodes beyond which the engine evicts the least recently used nodes '
+                  'that are not needed by any live session. 0 for unbounded.')
+    register('--engine-graph-max-estimated-bytes', type=int, advanced=True,
+             default=DEFAULT_EXECUTION_OPTIONS.engine_graph_max_estimated_bytes,
+             help='The estimated memory usage beyond which the engine evicts the least recently used '
+                  'nodes that are not needed by any live session. 0 for unbounded.')
 
   @classmethod
   def register_options(cls, register):
@@ -553,6 +587,15 @@ class GlobalOptionsRegistrar(SubsystemClientMixin, Optionable):
         "For instance, `--streaming-workunits-handlers=\"['pants.reporting.workunit.Workunits']\"` will "
         "register a Subsystem called Workunits defined in the module \"pants.reporting.workunit\"."
     )
+    register('--process-execution-priority', type=int, default=0, advanced=True,
+             help='The priority with which remote execution servers should run processes for this '
+                  'run, relative to other clients: lower values run sooner. 0 is the server default. '
+                  'Processes which set their own priority are unaffected.')
+    register('--process-execution-skip-cache-lookup', type=bool, default=False, advanced=True,
+             help='Ignore any cached results (local or remote) for processes run in this run, and '
+                  'run them again.')
+    register('--process-execution-do-not-cache', type=bool, default=False, advanced=True,
+             help='Do not cache the results (locally or remotely) of processes run in this run.')
 
   @classmethod
   def validate_instance(cls, opts):
@@ -187,7 +187,9 @@ class SchedulerService(PantsService):
     build_id = RunTracker.global_instance().run_id
     v2_ui = options.for_global_scope().v2_ui
     zipkin_trace_v2 = options.for_scope('reporting').zipkin_trace_v2
-    session = self._graph_helper.new_session(zipkin_trace_v2, build_id, v2_ui)
+    session = self._graph_helper.new_session(
+      zipkin_trace_v2, build_id, v2_ui, global_options=options.for_global_scope()
+    )
 
     if options.for_global_scope().loop:
       fn = self._loop
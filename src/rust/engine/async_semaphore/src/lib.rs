@@ -36,10 +36,12 @@ use parking_lot::Mutex;
 
 struct Waiter {
   id: usize,
+  priority: u64,
   task: Task,
 }
 
 struct Inner {
+  // Ordered by descending priority, and then by arrival: the front waiter is the next to acquire.
   waiters: VecDeque<Waiter>,
   available_permits: usize,
   // Used as the source of id in Waiters's because
@@ -72,13 +74,30 @@ impl AsyncSemaphore {
   /// Runs the given Future-creating function (and the Future it returns) under the semaphore.
   ///
   pub fn with_acquired<F, B, T, E>(&self, f: F) -> Box<dyn Future<Item = T, Error = E> + Send>
+  where
+    F: FnOnce() -> B + Send + 'static,
+    B: Future<Item = T, Error = E> + Send + 'static,
+  {
+    self.with_acquired_prioritized(0, f)
+  }
+
+  ///
+  /// Like `with_acquired`, but waits to acquire the semaphore with the given priority: waiters with
+  /// a higher priority acquire before waiters with a lower priority, and waiters with equal
+  /// priorities acquire in the order that they began waiting.
+  ///
+  pub fn with_acquired_prioritized<F, B, T, E>(
+    &self,
+    priority: u64,
+    f: F,
+  ) -> Box<dyn Future<Item = T, Error = E> + Send>
   where
     F: FnOnce() -> B + Send + 'static,
     B: Future<Item = T, Error = E> + Send + 'static,
   {
     Box::new(
       self
-        .acquire()
+        .acquire(priority)
         .map_err(|()| panic!("Acquisition is infalliable."))
         .and_then(|permit| {
           f().map(move |t| {
@@ -89,9 +108,10 @@ impl AsyncSemaphore {
     )
   }
 
-  fn acquire(&self) -> PermitFuture {
+  fn acquire(&self, priority: u64) -> PermitFuture {
     PermitFuture {
       inner: self.inner.clone(),
+      priority,
       waiter_id: None,
     }
   }
@@ -114,6 +134,7 @@ impl Drop for Permit {
 #[derive(Clone)]
 pub struct PermitFuture {
   inner: Arc<Mutex<Inner>>,
+  priority: u64,
   waiter_id: Option<usize>,
 }
 
@@ -145,11 +166,18 @@ impl Future for PermitFuture {
         let waiter_id = inner.next_waiter_id;
         let this_waiter = Waiter {
           id: waiter_id,
+          priority: self.priority,
           task: task::current(),
         };
         self.waiter_id = Some(waiter_id);
         inner.next_waiter_id += 1;
-        inner.waiters.push_back(this_waiter);
+        // Queue behind all waiters with an equal or higher priority.
+        let position = inner
+          .waiters
+          .iter()
+          .position(|waiter| waiter.priority < self.priority)
+          .unwrap_or_else(|| inner.waiters.len());
+        inner.waiters.insert(position, this_waiter);
       }
       if inner.available_permits == 0 {
         false
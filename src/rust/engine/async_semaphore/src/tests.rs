@@ -66,6 +66,41 @@ fn at_most_n_acquisitions() {
     .expect("thread2 didn't acquire.");
 }
 
+#[test]
+fn higher_priorities_acquire_first() {
+  let runtime = tokio::runtime::Runtime::new().unwrap();
+  let sema = AsyncSemaphore::new(1);
+  let (tx_acquired, acquired) = mpsc::channel();
+
+  // Hold the only permit while queueing waiters with a mix of priorities.
+  let permit = sema.acquire(0).wait().unwrap();
+  for &(name, priority) in &[("low", 1), ("high", 3), ("medium", 2), ("other high", 3)] {
+    let tx_acquired = tx_acquired.clone();
+    runtime
+      .executor()
+      .spawn(sema.with_acquired_prioritized(priority, move || {
+        tx_acquired.send(name).unwrap();
+        future::ok::<_, ()>(())
+      }));
+  }
+  let deadline = Instant::now() + Duration::from_secs(5);
+  while sema.num_waiters() < 4 {
+    assert!(Instant::now() < deadline, "Waiters didn't queue up.");
+    thread::sleep(Duration::from_millis(10));
+  }
+
+  // Release the permit, and confirm that the waiters acquire in priority order.
+  drop(permit);
+  let order: Vec<_> = (0..4)
+    .map(|_| {
+      acquired
+        .recv_timeout(Duration::from_secs(5))
+        .expect("waiter didn't acquire.")
+    })
+    .collect();
+  assert_eq!(order, vec!["high", "other high", "medium", "low"]);
+}
+
 #[test]
 fn drop_while_waiting() {
   // This tests that a task in the waiters queue of the semaphore is removed
@@ -113,7 +148,7 @@ fn drop_while_waiting() {
 
   // thread2 will wait for a little while, but then drop its PermitFuture to give up on waiting.
   runtime.spawn(future::lazy(move || {
-    let permit_future = handle2.acquire();
+    let permit_future = handle2.acquire(0);
     let delay_future = Delay::new(Instant::now() + Duration::from_millis(10));
     delay_future
       .select2(permit_future)
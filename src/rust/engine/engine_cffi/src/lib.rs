@@ -41,15 +41,19 @@ mod cffi_externs;
 
 use engine::externs::*;
 use engine::{
-  externs, nodes, Core, ExecutionRequest, Function, Handle, Key, Params, RootResult, Rule,
-  Scheduler, Session, Tasks, TypeId, Types, Value,
+  externs, nodes, Core, ExecutionRequest, ExecutionStream, Function, GraphFilter, Handle, Key,
+  Params, QueryRoots, RemoteExecutionBackend, RootResult, Rule, Scheduler, Session, StreamPoll,
+  Tasks, TypeId, Types, Value,
 };
 use futures::Future;
+use graph::EvictionBudget;
 use hashing::{Digest, EMPTY_DIGEST};
 use log::{error, warn, Log};
 use logging::logger::LOGGER;
 use logging::{Destination, Logger};
-use rule_graph::{GraphMaker, RuleGraph};
+use process_execution::CachePolicy;
+use rule_graph::{ExportedEntry, GraphMaker, RuleError, RuleGraph};
+use serde_json::json;
 use std::any::Any;
 use std::borrow::Borrow;
 use std::ffi::CStr;
@@ -71,24 +75,50 @@ mod tests;
 pub struct RawNodes {
   nodes_ptr: *const PyResult,
   nodes_len: u64,
+  // For each node, a tuple of (rule, product, params) tuples for the rules that its failure
+  // propagated through, or an empty tuple if it did not fail.
+  rule_traces_ptr: *const Handle,
   nodes: Vec<PyResult>,
+  rule_traces: Vec<Handle>,
 }
 
 impl RawNodes {
   fn create(node_states: Vec<RootResult>) -> Box<RawNodes> {
+    let rule_traces = node_states.iter().map(rule_trace_handle).collect();
     let nodes = node_states.into_iter().map(PyResult::from).collect();
     let mut raw_nodes = Box::new(RawNodes {
       nodes_ptr: Vec::new().as_ptr(),
       nodes_len: 0,
+      rule_traces_ptr: Vec::new().as_ptr(),
       nodes: nodes,
+      rule_traces: rule_traces,
     });
     // Creates a pointer into the struct itself, which is not possible to do in safe rust.
     raw_nodes.nodes_ptr = raw_nodes.nodes.as_ptr();
     raw_nodes.nodes_len = raw_nodes.nodes.len() as u64;
+    raw_nodes.rule_traces_ptr = raw_nodes.rule_traces.as_ptr();
     raw_nodes
   }
 }
 
+fn rule_trace_handle(node_state: &RootResult) -> Handle {
+  let frames = match node_state {
+    Err(failure) => failure.rule_trace(),
+    Ok(_) => &[],
+  };
+  let frames = frames
+    .iter()
+    .map(|frame| {
+      externs::store_tuple(&[
+        externs::store_utf8(&frame.rule),
+        externs::store_utf8(&frame.product),
+        externs::store_utf8(&frame.params),
+      ])
+    })
+    .collect::<Vec<_>>();
+  externs::store_tuple(&frames).into()
+}
+
 #[no_mangle]
 pub extern "C" fn externs_set(
   context: *const ExternContext,
@@ -152,9 +182,13 @@ pub extern "C" fn externs_set(
   });
 }
 
+///
+/// Python holds the Keys that it is given indefinitely (as the Functions of rules, for example), so
+/// they are pinned rather than collected.
+///
 #[no_mangle]
 pub extern "C" fn key_for(value: Handle) -> Key {
-  externs::key_for(value.into())
+  externs::pinned_key_for(value.into())
 }
 
 #[no_mangle]
@@ -207,7 +241,17 @@ pub extern "C" fn scheduler_create(
   process_execution_use_local_cache: bool,
   remote_execution_headers_buf: BufferBuffer,
   process_execution_local_enable_nailgun: bool,
+  process_execution_failure_cache_ttl: u64,
+  remote_execution_backends_buf: BufferBuffer,
+  graph_max_nodes: u64,
+  graph_max_estimated_bytes: u64,
 ) -> RawResult {
+  // Zero values are unbounded.
+  let graph_eviction_budget = EvictionBudget {
+    max_nodes: Some(graph_max_nodes as usize).filter(|max_nodes| *max_nodes > 0),
+    max_estimated_bytes: Some(graph_max_estimated_bytes as usize)
+      .filter(|max_estimated_bytes| *max_estimated_bytes > 0),
+  };
   match make_core(
     tasks_ptr,
     types,
@@ -236,10 +280,13 @@ pub extern "C" fn scheduler_create(
     process_execution_use_local_cache,
     remote_execution_headers_buf,
     process_execution_local_enable_nailgun,
+    process_execution_failure_cache_ttl,
+    remote_execution_backends_buf,
   ) {
     Ok(core) => RawResult {
       is_throw: false,
-      raw_pointer: Box::into_raw(Box::new(Scheduler::new(core))) as *const raw::c_void,
+      raw_pointer: Box::into_raw(Box::new(Scheduler::new(core, graph_eviction_budget)))
+        as *const raw::c_void,
       throw_handle: Handle(std::ptr::null()),
     },
     Err(err) => RawResult {
@@ -278,6 +325,8 @@ fn make_core(
   process_execution_use_local_cache: bool,
   remote_execution_headers_buf: BufferBuffer,
   process_execution_local_enable_nailgun: bool,
+  process_execution_failure_cache_ttl: u64,
+  remote_execution_backends_buf: BufferBuffer,
 ) -> Result<Core, String> {
   let root_type_ids = root_type_ids.to_vec();
   let ignore_patterns = ignore_patterns_buf
@@ -344,6 +393,7 @@ fn make_core(
     })?;
 
   let remote_execution_headers = remote_execution_headers_buf.to_map("remote-execution-headers")?;
+  let remote_execution_backends = parse_remote_execution_backends(remote_execution_backends_buf)?;
   Core::new(
     root_type_ids.clone(),
     tasks,
@@ -386,9 +436,74 @@ fn make_core(
     process_execution_use_local_cache,
     remote_execution_headers,
     process_execution_local_enable_nailgun,
+    Duration::from_secs(process_execution_failure_cache_ttl),
+    remote_execution_backends,
   )
 }
 
+///
+/// Parses remote execution backends, each of which is encoded as a JSON object with the keys
+/// `server`, `platform`, and optionally `instance_name` and `extra_platform_properties` (an
+/// object of property names to values).
+///
+fn parse_remote_execution_backends(
+  remote_execution_backends_buf: BufferBuffer,
+) -> Result<Vec<RemoteExecutionBackend>, String> {
+  remote_execution_backends_buf
+    .to_strings()
+    .map_err(|err| format!("Failed to decode remote_execution_backends: {}", err))?
+    .into_iter()
+    .map(|backend_json| {
+      let backend: serde_json::Value = serde_json::from_str(&backend_json)
+        .map_err(|err| format!("Invalid remote execution backend {}: {}", backend_json, err))?;
+      let field = |name: &str| {
+        backend
+          .get(name)
+          .and_then(serde_json::Value::as_str)
+          .map(str::to_owned)
+      };
+      let required_field = |name: &str| {
+        field(name).ok_or_else(|| {
+          format!(
+            "Remote execution backend {} must set a string `{}`.",
+            backend_json, name
+          )
+        })
+      };
+      let extra_platform_properties = match backend.get("extra_platform_properties") {
+        Some(properties) => properties
+          .as_object()
+          .ok_or_else(|| {
+            format!(
+              "The extra_platform_properties of remote execution backend {} must be an object.",
+              backend_json
+            )
+          })?
+          .iter()
+          .map(|(key, value)| {
+            value
+              .as_str()
+              .map(|value| (key.clone(), value.to_owned()))
+              .ok_or_else(|| {
+                format!(
+                  "Platform property {} of remote execution backend {} must be a string.",
+                  key, backend_json
+                )
+              })
+          })
+          .collect::<Result<Vec<_>, _>>()?,
+        None => vec![],
+      };
+      RemoteExecutionBackend::new(
+        required_field("server")?,
+        &required_field("platform")?,
+        field("instance_name"),
+        extra_platform_properties,
+      )
+    })
+    .collect()
+}
+
 fn workunits_to_py_tuple_value<'a>(workunits: impl Iterator<Item = &'a WorkUnit>) -> Value {
   let workunit_values = workunits
     .map(|workunit: &WorkUnit| {
@@ -463,6 +578,10 @@ pub extern "C" fn scheduler_metrics(
         .into_iter()
         .map(|(metric, value)| (externs::store_utf8(metric), externs::store_i64(value)))
         .collect::<Vec<_>>();
+      values.push((
+        externs::store_utf8("node_metrics"),
+        json_to_py_value(&session.node_metrics().to_json()),
+      ));
       if session.should_record_zipkin_spans() {
         let workunits = session.workunit_store().get_workunits();
         let locked = workunits.lock();
@@ -475,6 +594,48 @@ pub extern "C" fn scheduler_metrics(
   })
 }
 
+///
+/// Converts the given JSON value into the equivalent python value.
+///
+fn json_to_py_value(json: &serde_json::Value) -> Value {
+  match json {
+    serde_json::Value::Null => externs::none().into(),
+    serde_json::Value::Bool(b) => externs::store_bool(*b),
+    serde_json::Value::Number(n) => match n.as_i64() {
+      Some(i) => externs::store_i64(i),
+      None => externs::store_f64(n.as_f64().unwrap_or(std::f64::NAN)),
+    },
+    serde_json::Value::String(s) => externs::store_utf8(s),
+    serde_json::Value::Array(items) => {
+      externs::store_tuple(&items.iter().map(json_to_py_value).collect::<Vec<_>>())
+    }
+    serde_json::Value::Object(fields) => externs::store_dict(
+      &fields
+        .iter()
+        .map(|(k, v)| (externs::store_utf8(k), json_to_py_value(v)))
+        .collect::<Vec<_>>(),
+    ),
+  }
+}
+
+///
+/// Writes the Node metrics of the given Session to the given path as JSON.
+///
+#[no_mangle]
+pub extern "C" fn session_node_metrics_report(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+  path_ptr: *const raw::c_char,
+) -> PyResult {
+  let path_str = unsafe { CStr::from_ptr(path_ptr).to_string_lossy().into_owned() };
+  let path = PathBuf::from(path_str);
+  with_scheduler(scheduler_ptr, |scheduler| {
+    with_session(session_ptr, |session| {
+      scheduler.write_node_metrics(session, path.as_path()).into()
+    })
+  })
+}
+
 ///
 /// Prepares to fork by shutting down any background threads used for execution, and then
 /// calling the given callback function (which should execute the fork) while holding exclusive
@@ -509,6 +670,91 @@ pub extern "C" fn scheduler_execute(
   })
 }
 
+///
+/// The outcome of polling an ExecutionStream (see `StreamPoll`).
+///
+#[repr(C)]
+pub struct RawStreamPoll {
+  // True if every root has completed, and its result has been returned by a previous poll.
+  done: bool,
+  // The index in the ExecutionRequest of the root that completed, if `nodes` is not null.
+  index: u64,
+  // The result of the root that completed, or null if no root completed while waiting.
+  nodes: *const RawNodes,
+}
+
+///
+/// Starts executing the roots of the given ExecutionRequest, returning a stream which must be
+/// polled for their results with `execution_stream_poll`, and then destroyed with
+/// `execution_stream_destroy`.
+///
+#[no_mangle]
+pub extern "C" fn scheduler_execute_streaming(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+  execution_request_ptr: *mut ExecutionRequest,
+  fail_fast: bool,
+) -> *const ExecutionStream {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    with_execution_request(execution_request_ptr, |execution_request| {
+      with_session(session_ptr, |session| {
+        Box::into_raw(Box::new(scheduler.execute_streaming(
+          execution_request,
+          session,
+          fail_fast,
+        )))
+      })
+    })
+  })
+}
+
+///
+/// Waits up to `wait_ms` for the next root of the given stream to complete. The `nodes` of a
+/// returned poll must be destroyed with `nodes_destroy`.
+///
+#[no_mangle]
+pub extern "C" fn execution_stream_poll(
+  scheduler_ptr: *mut Scheduler,
+  execution_stream_ptr: *mut ExecutionStream,
+  wait_ms: u64,
+) -> RawStreamPoll {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    with_execution_stream(execution_stream_ptr, |execution_stream| {
+      match scheduler.poll_stream(execution_stream, Duration::from_millis(wait_ms)) {
+        StreamPoll::Completed(index, result) => RawStreamPoll {
+          done: false,
+          index: index as u64,
+          nodes: Box::into_raw(RawNodes::create(vec![result])),
+        },
+        StreamPoll::Pending => RawStreamPoll {
+          done: false,
+          index: 0,
+          nodes: std::ptr::null(),
+        },
+        StreamPoll::Done => RawStreamPoll {
+          done: true,
+          index: 0,
+          nodes: std::ptr::null(),
+        },
+      }
+    })
+  })
+}
+
+///
+/// Stops waiting for any roots of the given stream which have not completed, and destroys it.
+///
+#[no_mangle]
+pub extern "C" fn execution_stream_destroy(
+  scheduler_ptr: *mut Scheduler,
+  execution_stream_ptr: *mut ExecutionStream,
+) {
+  let execution_stream = unsafe { Box::from_raw(execution_stream_ptr) };
+  with_scheduler(scheduler_ptr, |scheduler| {
+    scheduler.finish_stream(*execution_stream)
+  })
+}
+
 #[no_mangle]
 pub extern "C" fn scheduler_destroy(scheduler_ptr: *mut Scheduler) {
   // convert the raw pointer back to a Box (without `forget`ing it) in order to cause it
@@ -525,13 +771,39 @@ pub extern "C" fn execution_add_root_select(
 ) -> PyResult {
   with_scheduler(scheduler_ptr, |scheduler| {
     with_execution_request(execution_request_ptr, |execution_request| {
-      Params::new(param_vals.to_vec().into_iter().map(externs::key_for))
+      // The Keys are held by the request until it is destroyed, because another Session might
+      // collect interned Keys before the request executes.
+      let keys: Vec<Key> = param_vals
+        .to_vec()
+        .into_iter()
+        .map(externs::held_key_for)
+        .collect();
+      execution_request.hold_keys(keys.clone());
+      Params::new(keys)
         .and_then(|params| scheduler.add_root_select(execution_request, params, product))
         .into()
     })
   })
 }
 
+///
+/// Sets a timeout for the given request, after which its outstanding roots fail. A `timeout_ms` of
+/// 0 disables the timeout.
+///
+#[no_mangle]
+pub extern "C" fn execution_set_timeout(
+  execution_request_ptr: *mut ExecutionRequest,
+  timeout_ms: u64,
+) {
+  with_execution_request(execution_request_ptr, |execution_request| {
+    execution_request.timeout = if timeout_ms == 0 {
+      None
+    } else {
+      Some(Duration::from_millis(timeout_ms))
+    };
+  })
+}
+
 #[no_mangle]
 pub extern "C" fn tasks_create() -> *const Tasks {
   // Allocate on the heap via `Box` and return a raw pointer to the boxed value.
@@ -544,9 +816,10 @@ pub extern "C" fn tasks_task_begin(
   func: Function,
   output_type: TypeId,
   cacheable: bool,
+  priority: i32,
 ) {
   with_tasks(tasks_ptr, |tasks| {
-    tasks.task_begin(func, output_type, cacheable);
+    tasks.task_begin(func, output_type, cacheable, priority);
   })
 }
 
@@ -557,6 +830,24 @@ pub extern "C" fn tasks_add_get(tasks_ptr: *mut Tasks, product: TypeId, subject:
   })
 }
 
+#[no_mangle]
+pub extern "C" fn tasks_add_union(tasks_ptr: *mut Tasks, union_base: TypeId) {
+  with_tasks(tasks_ptr, |tasks| {
+    tasks.add_union(union_base);
+  })
+}
+
+#[no_mangle]
+pub extern "C" fn tasks_add_union_member(
+  tasks_ptr: *mut Tasks,
+  union_base: TypeId,
+  member: TypeId,
+) {
+  with_tasks(tasks_ptr, |tasks| {
+    tasks.add_union_member(union_base, member);
+  })
+}
+
 #[no_mangle]
 pub extern "C" fn tasks_add_select(tasks_ptr: *mut Tasks, product: TypeId) {
   with_tasks(tasks_ptr, |tasks| {
@@ -656,6 +947,50 @@ pub extern "C" fn graph_visualize(
   })
 }
 
+///
+/// Writes the Graph below the roots of the given Session to the given path as JSON. An empty
+/// `variants_buf` matches all kinds of Node, a `min_duration_micros` of 0 matches Nodes that have
+/// not run, and a null `subgraph_request_ptr` starts from the roots of the Session rather than
+/// from the roots of that request.
+///
+#[no_mangle]
+pub extern "C" fn graph_visualize_json(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+  path_ptr: *const raw::c_char,
+  variants_buf: BufferBuffer,
+  min_duration_micros: u64,
+  subgraph_request_ptr: *mut ExecutionRequest,
+) -> PyResult {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    with_session(session_ptr, |session| {
+      let path_str = unsafe { CStr::from_ptr(path_ptr).to_string_lossy().into_owned() };
+      let path = PathBuf::from(path_str);
+      let subgraph_request = if subgraph_request_ptr.is_null() {
+        None
+      } else {
+        Some(unsafe { &*subgraph_request_ptr })
+      };
+      let filter = variants_buf
+        .to_strings()
+        .map(|variants| GraphFilter {
+          variants: variants.into_iter().collect(),
+          min_duration: if min_duration_micros == 0 {
+            None
+          } else {
+            Some(Duration::from_micros(min_duration_micros))
+          },
+        })
+        .map_err(|e| format!("Failed to decode graph filter: {:?}", e));
+      filter
+        .and_then(|filter| {
+          scheduler.write_graph_json(session, subgraph_request, &filter, path.as_path())
+        })
+        .into()
+    })
+  })
+}
+
 #[no_mangle]
 pub extern "C" fn graph_trace(
   scheduler_ptr: *mut Scheduler,
@@ -675,6 +1010,142 @@ pub extern "C" fn graph_trace(
   });
 }
 
+#[no_mangle]
+pub extern "C" fn graph_trace_events(
+  scheduler_ptr: *mut Scheduler,
+  session_ptr: *mut Session,
+  path_ptr: *const raw::c_char,
+) -> PyResult {
+  let path_str = unsafe { CStr::from_ptr(path_ptr).to_string_lossy().into_owned() };
+  let path = PathBuf::from(path_str);
+  with_scheduler(scheduler_ptr, |scheduler| {
+    with_session(session_ptr, |session| {
+      scheduler.write_trace_events(session, path.as_path()).into()
+    })
+  })
+}
+
+#[no_mangle]
+pub extern "C" fn execution_invalidation_chains(
+  scheduler_ptr: *mut Scheduler,
+  execution_request_ptr: *mut ExecutionRequest,
+) -> Handle {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    with_execution_request(execution_request_ptr, |execution_request| {
+      let chains = scheduler
+        .invalidation_chains(execution_request)
+        .iter()
+        .map(|chain| externs::store_utf8(chain))
+        .collect::<Vec<_>>();
+      externs::store_tuple(&chains).into()
+    })
+  })
+}
+
+///
+/// Returns the transitive dependencies (or dependents) of the roots of the given request as a
+/// tuple of dicts. A `max_depth` of 0 is unbounded, and an empty `variants_buf` matches all
+/// kinds of Node.
+///
+#[no_mangle]
+pub extern "C" fn graph_query_request(
+  scheduler_ptr: *mut Scheduler,
+  execution_request_ptr: *mut ExecutionRequest,
+  dependents: bool,
+  max_depth: u64,
+  variants_buf: BufferBuffer,
+) -> PyResult {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    with_execution_request(execution_request_ptr, |execution_request| {
+      graph_query(
+        scheduler,
+        QueryRoots::Request(execution_request),
+        dependents,
+        max_depth,
+        &variants_buf,
+      )
+    })
+  })
+}
+
+///
+/// Like `graph_query_request`, but starting from the Nodes which operate on the given paths.
+///
+#[no_mangle]
+pub extern "C" fn graph_query_paths(
+  scheduler_ptr: *mut Scheduler,
+  paths_buf: BufferBuffer,
+  dependents: bool,
+  max_depth: u64,
+  variants_buf: BufferBuffer,
+) -> PyResult {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    let paths = paths_buf
+      .to_os_strings()
+      .into_iter()
+      .map(PathBuf::from)
+      .collect();
+    graph_query(
+      scheduler,
+      QueryRoots::Paths(&paths),
+      dependents,
+      max_depth,
+      &variants_buf,
+    )
+  })
+}
+
+fn graph_query(
+  scheduler: &Scheduler,
+  roots: QueryRoots<'_>,
+  dependents: bool,
+  max_depth: u64,
+  variants_buf: &BufferBuffer,
+) -> PyResult {
+  let max_depth = if max_depth == 0 {
+    None
+  } else {
+    Some(max_depth as usize)
+  };
+  let result: Result<Value, String> = variants_buf
+    .to_strings()
+    .map_err(|e| format!("Failed to decode node variants: {:?}", e))
+    .map(|variants| {
+      let matches = scheduler
+        .query_graph(
+          roots,
+          dependents,
+          max_depth,
+          &variants.into_iter().collect(),
+        )
+        .into_iter()
+        .map(|(node, depth)| {
+          let path = node
+            .fs_subject()
+            .map(|path| externs::store_utf8_osstr(path.as_os_str()))
+            .unwrap_or_else(|| externs::none().into());
+          externs::store_dict(&[
+            (
+              externs::store_utf8("node"),
+              externs::store_utf8(&node.to_string()),
+            ),
+            (
+              externs::store_utf8("variant"),
+              externs::store_utf8(node.variant_name()),
+            ),
+            (
+              externs::store_utf8("depth"),
+              externs::store_i64(depth as i64),
+            ),
+            (externs::store_utf8("path"), path),
+          ])
+        })
+        .collect::<Vec<_>>();
+      externs::store_tuple(&matches)
+    });
+  result.into()
+}
+
 #[no_mangle]
 pub extern "C" fn nodes_destroy(raw_nodes_ptr: *mut RawNodes) {
   let _ = unsafe { Box::from_raw(raw_nodes_ptr) };
@@ -688,6 +1159,9 @@ pub extern "C" fn session_create(
   ui_worker_count: u64,
   build_id: Buffer,
   should_report_workunits: bool,
+  process_execution_priority: i32,
+  process_execution_skip_cache_lookup: bool,
+  process_execution_do_not_cache: bool,
 ) -> *const Session {
   let build_id = build_id
     .to_string()
@@ -700,10 +1174,31 @@ pub extern "C" fn session_create(
       ui_worker_count as usize,
       build_id,
       should_report_workunits,
+      // Zero is the default priority of the remote execution API.
+      if process_execution_priority == 0 {
+        None
+      } else {
+        Some(process_execution_priority)
+      },
+      CachePolicy {
+        skip_cache_lookup: process_execution_skip_cache_lookup,
+        do_not_cache: process_execution_do_not_cache,
+      },
     )))
   })
 }
 
+///
+/// Cancels the given Session: see `Scheduler::cancel`. This is safe to call from a thread other
+/// than the one that is blocked in `scheduler_execute`.
+///
+#[no_mangle]
+pub extern "C" fn session_cancel(scheduler_ptr: *mut Scheduler, session_ptr: *mut Session) {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    with_session(session_ptr, |session| scheduler.cancel(session))
+  })
+}
+
 #[no_mangle]
 pub extern "C" fn session_destroy(ptr: *mut Session) {
   let _ = unsafe { Box::from_raw(ptr) };
@@ -722,7 +1217,62 @@ pub extern "C" fn execution_request_destroy(ptr: *mut ExecutionRequest) {
 #[no_mangle]
 pub extern "C" fn validator_run(scheduler_ptr: *mut Scheduler) -> PyResult {
   with_scheduler(scheduler_ptr, |scheduler| {
-    scheduler.core.rule_graph.validate().into()
+    scheduler
+      .core
+      .rule_graph
+      .validate()
+      .map_err(|e| e.to_string())
+      .into()
+  })
+}
+
+///
+/// Returns the diagnostics for the RuleGraph as a JSON string: a list of the rules with errors,
+/// followed by the rules which were shadowed by higher priority rules (which are not errors).
+///
+#[no_mangle]
+pub extern "C" fn validator_diagnostics_json(scheduler_ptr: *mut Scheduler) -> PyResult {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    let rule_graph = &scheduler.core.rule_graph;
+    let rule_errors = match rule_graph.validate() {
+      Ok(()) => vec![],
+      Err(e) => e.rule_errors,
+    };
+    let shadowed_rules = rule_graph
+      .shadowed_rules()
+      .into_iter()
+      .map(|shadowed_rule| RuleError {
+        diagnostics: vec![shadowed_rule.diagnostic()],
+        rule: shadowed_rule.rule,
+      });
+    let rule_errors_json: Vec<_> = rule_errors
+      .into_iter()
+      .chain(shadowed_rules)
+      .map(|rule_error| {
+        let diagnostics: Vec<_> = rule_error
+          .diagnostics
+          .into_iter()
+          .map(|d| {
+            json!({
+              "kind": d.kind.as_str(),
+              "params": d.params.iter().map(|p| p.to_string()).collect::<Vec<_>>(),
+              "product": d.product.map(|p| p.to_string()),
+              "reason": d.reason,
+              "details": d.details,
+              "candidates": d.candidates,
+            })
+          })
+          .collect();
+        json!({
+          "rule": rule_error.rule.to_string(),
+          "diagnostics": diagnostics,
+        })
+      })
+      .collect();
+    let result: Result<Value, String> = serde_json::to_string(&rule_errors_json)
+      .map(|s| externs::store_utf8(&s))
+      .map_err(|e| format!("Failed to render rule graph diagnostics: {}", e));
+    result.into()
   })
 }
 
@@ -736,7 +1286,6 @@ pub extern "C" fn rule_graph_visualize(
     let path_str = unsafe { CStr::from_ptr(path_ptr).to_string_lossy().into_owned() };
     let path = PathBuf::from(path_str);
 
-    // TODO(#7117): we want to represent union types in the graph visualizer somehow!!!
     let graph = graph_full(scheduler, subject_types.to_vec());
     write_to_file(path.as_path(), &graph).unwrap_or_else(|e| {
       println!("Failed to visualize to {}: {:?}", path.display(), e);
@@ -755,14 +1304,81 @@ pub extern "C" fn rule_subgraph_visualize(
     let path_str = unsafe { CStr::from_ptr(path_ptr).to_string_lossy().into_owned() };
     let path = PathBuf::from(path_str);
 
-    // TODO(#7117): we want to represent union types in the graph visualizer somehow!!!
-    let graph = graph_sub(scheduler, subject_type, product_type);
+    let graph = graph_sub(scheduler, vec![subject_type], product_type);
     write_to_file(path.as_path(), &graph).unwrap_or_else(|e| {
       println!("Failed to visualize to {}: {:?}", path.display(), e);
     });
   })
 }
 
+///
+/// Exports the full rule graph for the given root subject types as JSON, or as GraphML if
+/// `graphml` is true.
+///
+#[no_mangle]
+pub extern "C" fn rule_graph_export(
+  scheduler_ptr: *mut Scheduler,
+  subject_types: TypeIdBuffer,
+  graphml: bool,
+) -> PyResult {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    let graph = graph_full(scheduler, subject_types.to_vec());
+    let result: Result<Value, String> =
+      graph_export_str(&graph, graphml).map(|s| externs::store_utf8(&s));
+    result.into()
+  })
+}
+
+///
+/// Exports the rule graph below the root that computes the given product for the given param
+/// types as JSON, or as GraphML if `graphml` is true.
+///
+#[no_mangle]
+pub extern "C" fn rule_subgraph_export(
+  scheduler_ptr: *mut Scheduler,
+  param_types: TypeIdBuffer,
+  product_type: TypeId,
+  graphml: bool,
+) -> PyResult {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    let graph = graph_sub(scheduler, param_types.to_vec(), product_type);
+    let result: Result<Value, String> =
+      graph_export_str(&graph, graphml).map(|s| externs::store_utf8(&s));
+    result.into()
+  })
+}
+
+#[no_mangle]
+pub extern "C" fn rule_graph_explain(
+  scheduler_ptr: *mut Scheduler,
+  param_types: TypeIdBuffer,
+  product_type: TypeId,
+  dot: bool,
+) -> PyResult {
+  with_scheduler(scheduler_ptr, |scheduler| {
+    let param_types = param_types.to_vec();
+    let graph_maker = GraphMaker::new(
+      scheduler.core.tasks.as_map(),
+      scheduler.core.tasks.unions(),
+      param_types.clone(),
+    );
+    let explanation = graph_maker.explain(param_types, product_type);
+    let result: Result<Value, String> = if dot {
+      let mut buf = Vec::new();
+      explanation
+        .visualize(&mut buf)
+        .map_err(|e| format!("Failed to render explanation: {}", e))
+        .and_then(|()| {
+          String::from_utf8(buf).map_err(|e| format!("Failed to render explanation: {}", e))
+        })
+        .map(|s| externs::store_utf8(&s))
+    } else {
+      Ok(externs::store_utf8(&explanation.to_string()))
+    };
+    result.into()
+  })
+}
+
 fn generate_panic_string(payload: &(dyn Any + Send)) -> String {
   match payload
     .downcast_ref::<String>()
@@ -795,6 +1411,13 @@ pub extern "C" fn set_panic_handler() {
 #[no_mangle]
 pub extern "C" fn garbage_collect_store(scheduler_ptr: *mut Scheduler) {
   with_scheduler(scheduler_ptr, |scheduler| {
+    match scheduler
+      .core
+      .garbage_collect_process_cache(store::ShrinkBehavior::Fast)
+    {
+      Ok(_) => {}
+      Err(err) => error!("{}", err),
+    }
     match scheduler.core.store().garbage_collect(
       store::DEFAULT_LOCAL_STORE_GC_TARGET_BYTES,
       store::ShrinkBehavior::Fast,
@@ -1169,13 +1792,78 @@ pub extern "C" fn override_thread_logging_destination(destination: Destination)
 }
 
 fn graph_full(scheduler: &Scheduler, subject_types: Vec<TypeId>) -> RuleGraph<Rule> {
-  let graph_maker = GraphMaker::new(scheduler.core.tasks.as_map(), subject_types);
+  let graph_maker = GraphMaker::new(
+    scheduler.core.tasks.as_map(),
+    scheduler.core.tasks.unions(),
+    subject_types,
+  );
   graph_maker.full_graph()
 }
 
-fn graph_sub(scheduler: &Scheduler, subject_type: TypeId, product_type: TypeId) -> RuleGraph<Rule> {
-  let graph_maker = GraphMaker::new(scheduler.core.tasks.as_map(), vec![subject_type]);
-  graph_maker.sub_graph(subject_type, product_type)
+fn graph_sub(
+  scheduler: &Scheduler,
+  param_types: Vec<TypeId>,
+  product_type: TypeId,
+) -> RuleGraph<Rule> {
+  let graph_maker = GraphMaker::new(
+    scheduler.core.tasks.as_map(),
+    scheduler.core.tasks.unions(),
+    param_types.clone(),
+  );
+  graph_maker.sub_graph(param_types, product_type)
+}
+
+fn graph_export_str(graph: &RuleGraph<Rule>, graphml: bool) -> Result<String, String> {
+  let export = graph.export();
+  if graphml {
+    let mut buf = Vec::new();
+    export
+      .graphml(&mut buf)
+      .map_err(|e| format!("Failed to export rule graph: {}", e))?;
+    return String::from_utf8(buf).map_err(|e| format!("Failed to export rule graph: {}", e));
+  }
+
+  let entry_json = |entry: &ExportedEntry| {
+    json!({
+      "id": entry.id,
+      "kind": entry.kind.as_str(),
+      "label": entry.label,
+      "rule": entry.rule,
+      "product": entry.product,
+      "params": entry.params,
+    })
+  };
+  let entries: Vec<_> = export.entries.iter().map(entry_json).collect();
+  let edges: Vec<_> = export
+    .edges
+    .iter()
+    .map(|edge| {
+      json!({
+        "source": edge.source,
+        "target": edge.target,
+        "dependency_key": edge.dependency_key,
+        "provided_param": edge.provided_param,
+        "union": edge.union,
+      })
+    })
+    .collect();
+  let unfulfillable: Vec<_> = export
+    .unfulfillable
+    .iter()
+    .map(|unfulfillable| {
+      let mut entry = entry_json(&unfulfillable.entry);
+      entry["diagnostics"] = json!(unfulfillable.diagnostics);
+      entry
+    })
+    .collect();
+  serde_json::to_string(&json!({
+    "root_param_types": export.root_param_types,
+    "entries": entries,
+    "edges": edges,
+    "unreachable_rules": export.unreachable_rules,
+    "unfulfillable": unfulfillable,
+  }))
+  .map_err(|e| format!("Failed to export rule graph: {}", e))
 }
 
 fn write_to_file(path: &Path, graph: &RuleGraph<Rule>) -> io::Result<()> {
@@ -1212,6 +1900,19 @@ where
   t
 }
 
+///
+/// See `with_scheduler`.
+///
+fn with_execution_stream<F, T>(execution_stream_ptr: *mut ExecutionStream, f: F) -> T
+where
+  F: FnOnce(&mut ExecutionStream) -> T,
+{
+  let mut execution_stream = unsafe { Box::from_raw(execution_stream_ptr) };
+  let t = f(&mut execution_stream);
+  mem::forget(execution_stream);
+  t
+}
+
 ///
 /// See `with_scheduler`.
 ///
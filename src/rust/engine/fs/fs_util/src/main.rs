@@ -191,6 +191,13 @@ to this directory.",
                     .long("target-size-bytes")
                     .required(true),
               )
+              .arg(
+                Arg::with_name("process-cache-target-size-bytes")
+                    .takes_value(true)
+                    .long("process-cache-target-size-bytes")
+                    .required(false)
+                    .help("The size to shrink the local process execution cache to. Defaults to 512MiB."),
+              )
         )
       .arg(
         Arg::with_name("local-store-path")
@@ -590,6 +597,19 @@ fn execute(top_match: &clap::ArgMatches<'_>) -> Result<(), ExitError> {
     ("gc", Some(args)) => {
       let target_size_bytes = value_t!(args.value_of("target-size-bytes"), usize)
         .expect("--target-size-bytes must be passed as a non-negative integer");
+      let process_cache_target_size_bytes = if args.is_present("process-cache-target-size-bytes") {
+        value_t!(args.value_of("process-cache-target-size-bytes"), usize)
+          .expect("--process-cache-target-size-bytes must be passed as a non-negative integer")
+      } else {
+        process_execution::cache::DEFAULT_GC_TARGET_BYTES
+      };
+      let process_execution_store =
+        process_execution::cache::open_store(&store_dir, runtime.clone())?;
+      process_execution::cache::garbage_collect(
+        &process_execution_store,
+        process_cache_target_size_bytes,
+        store::ShrinkBehavior::Compact,
+      )?;
       store.garbage_collect(target_size_bytes, store::ShrinkBehavior::Compact)?;
       Ok(())
     }
@@ -612,7 +612,53 @@ impl Store {
   }
 
   pub fn lease_all<'a, Ds: Iterator<Item = &'a Digest>>(&self, digests: Ds) -> Result<(), String> {
-    self.local.lease_all(digests)
+    self
+      .local
+      .lease_all(digests.map(|digest| (*digest, EntryType::File)))
+  }
+
+  ///
+  /// Leases the given digests in the local store, along with the transitive contents of any of
+  /// them which are Directories.
+  ///
+  /// Fails if any of the digests (or their contents) are not present in the local store.
+  ///
+  pub fn lease_all_recursively(
+    &self,
+    digests: Vec<Digest>,
+    workunit_store: WorkUnitStore,
+  ) -> BoxFuture<(), String> {
+    let mut expanding_futures = Vec::new();
+
+    let mut expanded_digests = HashMap::new();
+    for digest in digests {
+      match self.local.entry_type(&digest.0) {
+        Ok(Some(EntryType::File)) => {
+          expanded_digests.insert(digest, EntryType::File);
+        }
+        Ok(Some(EntryType::Directory)) => {
+          expanding_futures.push(self.expand_directory(digest, workunit_store.clone()));
+        }
+        Ok(None) => {
+          return future::err(format!("Failed to lease digest {:?}: Not found", digest)).to_boxed();
+        }
+        Err(err) => {
+          return future::err(format!("Failed to lease digest {:?}: {:?}", digest, err)).to_boxed();
+        }
+      };
+    }
+
+    let local = self.local.clone();
+    future::join_all(expanding_futures)
+      .and_then(move |futures| {
+        for mut digests in futures {
+          for (digest, entry_type) in digests.drain() {
+            expanded_digests.insert(digest, entry_type);
+          }
+        }
+        local.lease_all(expanded_digests.into_iter())
+      })
+      .to_boxed()
   }
 
   pub fn garbage_collect(
@@ -97,13 +97,23 @@ impl ByteStore {
     Ok(None)
   }
 
-  pub fn lease_all<'a, Ds: Iterator<Item = &'a Digest>>(&self, digests: Ds) -> Result<(), String> {
+  pub fn lease_all<Ds: Iterator<Item = (Digest, EntryType)>>(
+    &self,
+    digests: Ds,
+  ) -> Result<(), String> {
     let until = Self::default_lease_until_secs_since_epoch();
-    for digest in digests {
-      let (env, _, lease_database) = self.inner.file_dbs.clone()?.get(&digest.0);
+    for (digest, entry_type) in digests {
+      let dbs = match entry_type {
+        EntryType::File => self.inner.file_dbs.clone(),
+        EntryType::Directory => self.inner.directory_dbs.clone(),
+      };
+      let (env, _, lease_database) = dbs?.get(&digest.0);
       env
         .begin_rw_txn()
-        .and_then(|mut txn| self.lease(lease_database, &digest.0, until, &mut txn))
+        .and_then(|mut txn| {
+          self.lease(lease_database, &digest.0, until, &mut txn)?;
+          txn.commit()
+        })
         .map_err(|err| format!("Error leasing digest {:?}: {}", digest, err))?;
     }
     Ok(())
@@ -171,24 +181,8 @@ impl ByteStore {
         EntryType::File => self.inner.file_dbs.clone(),
         EntryType::Directory => self.inner.directory_dbs.clone(),
       };
-      let (env, database, lease_database) = lmdbs.clone()?.get(&aged_fingerprint.fingerprint);
-      {
-        env
-          .begin_rw_txn()
-          .and_then(|mut txn| {
-            txn.del(database, &aged_fingerprint.fingerprint.as_ref(), None)?;
-
-            txn
-              .del(lease_database, &aged_fingerprint.fingerprint.as_ref(), None)
-              .or_else(|err| match err {
-                NotFound => Ok(()),
-                err => Err(err),
-              })?;
-            used_bytes -= aged_fingerprint.size_bytes;
-            txn.commit()
-          })
-          .map_err(|err| format!("Error garbage collecting: {}", err))?;
-      }
+      lmdbs?.remove(&aged_fingerprint.fingerprint)?;
+      used_bytes -= aged_fingerprint.size_bytes;
     }
 
     if shrink_behavior == ShrinkBehavior::Compact {
@@ -209,47 +203,14 @@ impl ByteStore {
       EntryType::Directory => self.inner.directory_dbs.clone(),
     };
 
-    for &(ref env, ref database, ref lease_database) in &database?.all_lmdbs() {
-      let txn = env
-        .begin_ro_txn()
-        .map_err(|err| format!("Error beginning transaction to garbage collect: {}", err))?;
-      let mut cursor = txn
-        .open_ro_cursor(*database)
-        .map_err(|err| format!("Failed to open lmdb read cursor: {}", err))?;
-      for (key, bytes) in cursor.iter() {
-        *used_bytes += bytes.len();
-
-        // Random access into the lease_database is slower than iterating, but hopefully garbage
-        // collection is rare enough that we can get away with this, rather than do two passes
-        // here (either to populate leases into pre-populated AgedFingerprints, or to read sizes
-        // when we delete from lmdb to track how much we've freed).
-        let lease_until_unix_timestamp = txn
-          .get(*lease_database, &key)
-          .map(|b| {
-            let mut array = [0_u8; 8];
-            array.copy_from_slice(b);
-            u64::from_le_bytes(array)
-          })
-          .unwrap_or_else(|e| match e {
-            NotFound => 0,
-            e => panic!("Error reading lease, probable lmdb corruption: {:?}", e),
-          });
-
-        let leased_until = time::UNIX_EPOCH + time::Duration::from_secs(lease_until_unix_timestamp);
-
-        let expired_seconds_ago = time::SystemTime::now()
-          .duration_since(leased_until)
-          .map(|t| t.as_secs())
-          // 0 indicates unleased.
-          .unwrap_or(0);
-
-        fingerprints_by_expired_ago.push(AgedFingerprint {
-          expired_seconds_ago: expired_seconds_ago,
-          fingerprint: Fingerprint::from_bytes_unsafe(key),
-          size_bytes: bytes.len(),
-          entry_type: entry_type,
-        });
-      }
+    for aged_fingerprint in database?.aged_fingerprints()? {
+      *used_bytes += aged_fingerprint.size_bytes;
+      fingerprints_by_expired_ago.push(AgedFingerprint {
+        expired_seconds_ago: aged_fingerprint.expired_seconds_ago,
+        fingerprint: aged_fingerprint.fingerprint,
+        size_bytes: aged_fingerprint.size_bytes,
+        entry_type: entry_type,
+      });
     }
     Ok(())
   }
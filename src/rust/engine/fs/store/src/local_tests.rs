@@ -127,7 +127,7 @@ fn garbage_collect_nothing_to_do_with_lease() {
   .unwrap();
   let file_digest = Digest(file_fingerprint, 10);
   store
-    .lease_all(vec![file_digest].iter())
+    .lease_all(vec![(file_digest, EntryType::File)].into_iter())
     .expect("Error leasing");
   store
     .shrink(10, ShrinkBehavior::Fast)
@@ -138,6 +138,25 @@ fn garbage_collect_nothing_to_do_with_lease() {
   );
 }
 
+#[test]
+fn garbage_collect_nothing_to_do_with_directory_lease() {
+  let dir = TempDir::new().unwrap();
+  let store = new_store(dir.path());
+  let testdir = TestDirectory::containing_roland();
+  block_on(store.store_bytes(EntryType::Directory, testdir.bytes(), false)).expect("Error storing");
+  store
+    .lease_all(vec![(testdir.digest(), EntryType::Directory)].into_iter())
+    .expect("Error leasing");
+  store
+    .shrink(0, ShrinkBehavior::Fast)
+    .expect("Error shrinking");
+  assert_eq!(
+    load_bytes(&store, EntryType::Directory, testdir.digest()),
+    Ok(Some(testdir.bytes())),
+    "Directory was missing despite lease"
+  );
+}
+
 #[test]
 fn garbage_collect_remove_one_of_two_files_no_leases() {
   let dir = TempDir::new().unwrap();
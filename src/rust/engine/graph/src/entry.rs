@@ -2,10 +2,12 @@ use std::mem;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::node::{EntryId, Node, NodeContext, NodeError};
+use crate::node::{EntryId, Node, NodeContext, NodeError, NodeEvent};
+use crate::EntryStatus;
 
 use futures::future::{self, Future};
 use futures::sync::oneshot;
+use futures::{Async, Poll};
 use log::{self, trace};
 use parking_lot::Mutex;
 
@@ -50,6 +52,10 @@ impl Generation {
   fn next(self) -> Generation {
     Generation(self.0 + 1)
   }
+
+  pub(crate) fn value(self) -> u32 {
+    self.0
+  }
 }
 
 ///
@@ -117,22 +123,30 @@ pub(crate) enum EntryState<N: Node> {
   // completing.
   //
   // The `previous_result` value for a Running node is not a valid value. See NotStarted.
+  //
+  // Dropping the `abort` Sender (which happens whenever the node leaves the Running state)
+  // cancels the work for the run, if it has not already completed.
   Running {
     run_token: RunToken,
     generation: Generation,
     start_time: Instant,
     waiters: Vec<oneshot::Sender<Result<(N::Item, Generation), N::Error>>>,
+    abort: oneshot::Sender<()>,
     previous_result: Option<EntryResult<N>>,
     dirty: bool,
   },
   // A node that has completed, and then possibly been marked dirty. Because marking a node
   // dirty does not eagerly re-execute any logic, it will stay this way until a caller moves it
   // back to Running.
+  //
+  // The `start_time` and `duration` are those of the run which completed the node.
   Completed {
     run_token: RunToken,
     generation: Generation,
     result: EntryResult<N>,
     dep_generations: Vec<Generation>,
+    start_time: Instant,
+    duration: Duration,
   },
 }
 
@@ -157,6 +171,10 @@ pub struct Entry<N: Node> {
   node: N,
 
   state: Arc<Mutex<EntryState<N>>>,
+
+  // When this Entry was last requested. Only the copy of the Entry held by the Graph is updated
+  // (under the Graph lock), and it is used to choose Entries to evict.
+  last_used: Instant,
 }
 
 impl<N: Node> Entry<N> {
@@ -169,6 +187,7 @@ impl<N: Node> Entry<N> {
     Entry {
       node: node,
       state: Arc::new(Mutex::new(EntryState::initial())),
+      last_used: Instant::now(),
     }
   }
 
@@ -176,6 +195,14 @@ impl<N: Node> Entry<N> {
     &self.node
   }
 
+  pub(crate) fn last_used(&self) -> Instant {
+    self.last_used
+  }
+
+  pub(crate) fn mark_used(&mut self) {
+    self.last_used = Instant::now();
+  }
+
   ///
   /// If the Future for this Node has already completed, returns a clone of its result.
   ///
@@ -210,32 +237,34 @@ impl<N: Node> Entry<N> {
     let run_token = run_token.next();
     let context = context_factory.clone_for(entry_id);
     let node = node.clone();
+    let (abort, aborted) = oneshot::channel();
 
-    context_factory.spawn(future::lazy(move || {
+    let run = future::lazy(move || {
       // If we have previous result generations, compare them to all current dependency
       // generations (which, if they are dirty, will cause recursive cleaning). If they
       // match, we can consider the previous result value to be clean for reuse.
-      let was_clean = if let Some(previous_dep_generations) = previous_dep_generations {
-        let context2 = context.clone();
-        context
-          .graph()
-          .dep_generations(entry_id, &context)
-          .then(move |generation_res| match generation_res {
-            Ok(ref dep_generations) if dep_generations == &previous_dep_generations => {
-              // Dependencies have not changed: Node is clean.
-              Ok(true)
-            }
-            _ => {
-              // If dependency generations mismatched or failed to fetch, clear its
-              // dependencies and indicate that it should re-run.
-              context2.graph().clear_deps(entry_id, run_token);
-              Ok(false)
-            }
-          })
-          .to_boxed()
-      } else {
-        future::ok(false).to_boxed()
-      };
+      let was_clean: BoxFuture<bool, ()> =
+        if let Some(previous_dep_generations) = previous_dep_generations {
+          let context2 = context.clone();
+          context
+            .graph()
+            .dep_generations(entry_id, &context)
+            .then(move |generation_res| match generation_res {
+              Ok(ref dep_generations) if dep_generations == &previous_dep_generations => {
+                // Dependencies have not changed: Node is clean.
+                Ok(true)
+              }
+              _ => {
+                // If dependency generations mismatched or failed to fetch, clear its
+                // dependencies and indicate that it should re-run.
+                context2.graph().clear_deps(entry_id, run_token);
+                Ok(false)
+              }
+            })
+            .to_boxed()
+        } else {
+          future::ok(false).to_boxed()
+        };
 
       // If the Node was clean, complete it. Otherwise, re-run.
       was_clean.and_then(move |was_clean| {
@@ -260,10 +289,13 @@ impl<N: Node> Entry<N> {
             .to_boxed()
         }
       })
-    }));
+    });
+    // If the run is aborted before completing, drop it.
+    context_factory.spawn(run.select2(aborted).then(|_| Ok(())));
 
     EntryState::Running {
       waiters: Vec::new(),
+      abort,
       start_time: Instant::now(),
       run_token,
       generation,
@@ -295,15 +327,19 @@ impl<N: Node> Entry<N> {
       // cases we don't swap the state of the Node.
       match &mut *state {
         &mut EntryState::Running {
-          ref mut waiters, ..
+          ref mut waiters,
+          run_token,
+          ..
         } => {
           let (send, recv) = oneshot::channel();
           waiters.push(send);
           trace!("Adding waiter on {:?}", self.node);
-          return recv
-            .map_err(|_| N::Error::invalidated())
-            .flatten()
-            .to_boxed();
+          return Waiter {
+            entry: self.clone(),
+            run_token,
+            receiver: Some(recv),
+          }
+          .to_boxed();
         }
         &mut EntryState::Completed {
           ref result,
@@ -339,6 +375,7 @@ impl<N: Node> Entry<N> {
           generation,
           mut result,
           dep_generations,
+          ..
         } => {
           trace!(
             "Re-starting node {:?}. It was: previous_result={:?}, cacheable={}",
@@ -387,6 +424,50 @@ impl<N: Node> Entry<N> {
     self.get(context, entry_id)
   }
 
+  ///
+  /// Called when a Waiter for the given run of this Node is dropped. If the Node is still running
+  /// and none of its waiters remain, the run is cancelled, and the Node is reset to NotStarted
+  /// (preserving its previous result) so that a later request will restart it.
+  ///
+  fn cancel_if_abandoned(&self, waiter_run_token: RunToken) {
+    let mut state = self.state.lock();
+
+    let abandoned = match *state {
+      EntryState::Running {
+        run_token,
+        ref waiters,
+        ..
+      } => run_token == waiter_run_token && waiters.iter().all(oneshot::Sender::is_canceled),
+      _ => false,
+    };
+    if !abandoned {
+      return;
+    }
+
+    trace!(
+      "Cancelling node {:?} because it no longer has any waiters.",
+      self.node
+    );
+    *state = match mem::replace(&mut *state, EntryState::initial()) {
+      EntryState::Running {
+        run_token,
+        generation,
+        abort,
+        previous_result,
+        ..
+      } => {
+        // Dropping the abort Sender cancels the run.
+        drop(abort);
+        EntryState::NotStarted {
+          run_token: run_token.next(),
+          generation,
+          previous_result,
+        }
+      }
+      s => s,
+    };
+  }
+
   ///
   /// Called from the Executor when a Node completes.
   ///
@@ -430,16 +511,19 @@ impl<N: Node> Entry<N> {
         waiters,
         run_token,
         generation,
+        start_time,
         mut previous_result,
         dirty,
         ..
       } => {
-        if result == Some(Err(N::Error::invalidated())) {
-          // Because it is always ephemeral, invalidation is the only type of Err that we do not
-          // persist in the Graph. Instead, swap the Node to NotStarted to drop all waiters,
-          // causing them to also experience invalidation (transitively).
+        if result == Some(Err(N::Error::invalidated()))
+          || result == Some(Err(N::Error::cancelled()))
+        {
+          // Because they are always ephemeral, invalidation and cancellation are the only types of
+          // Err that we do not persist in the Graph. Instead, swap the Node to NotStarted to drop
+          // all waiters, causing them to experience invalidation (transitively).
           trace!(
-            "Not completing node {:?} because it was invalidated before completing.",
+            "Not completing node {:?} because it was invalidated or cancelled before completing.",
             self.node
           );
           if let Some(previous_result) = previous_result.as_mut() {
@@ -470,6 +554,22 @@ impl<N: Node> Entry<N> {
             previous_result,
           )
         } else {
+          // Record the completion before notifying waiters, so that it is visible to them. Only
+          // runs which actually complete the Node are reported: a Node which was dirtied while
+          // running will have been started again.
+          let duration = Self::duration_since(start_time, Instant::now());
+          context.record_event(
+            &self.node,
+            if result.is_some() {
+              NodeEvent::Ran {
+                reran: previous_result.is_some(),
+                duration,
+              }
+            } else {
+              NodeEvent::Cleaned
+            },
+          );
+
           // If the new result does not match the previous result, the generation increments.
           let (generation, next_result) = if let Some(result) = result {
             if Some(&result) == previous_result.as_ref().map(EntryResult::as_ref) {
@@ -503,6 +603,8 @@ impl<N: Node> Entry<N> {
             dep_generations,
             run_token,
             generation,
+            start_time,
+            duration,
           }
         }
       }
@@ -543,20 +645,49 @@ impl<N: Node> Entry<N> {
   ///
   pub(crate) fn current_running_duration(&self, now: Instant) -> Option<Duration> {
     match *self.state.lock() {
-      EntryState::Running { start_time, .. } =>
-      // NB: `Instant::duration_since` panics if the end time is before the start time, which can
-      // happen when starting a Node races against a caller creating their Instant.
-      {
-        Some(if start_time < now {
-          now.duration_since(start_time)
-        } else {
-          Duration::from_secs(0)
-        })
-      }
+      EntryState::Running { start_time, .. } => Some(Self::duration_since(start_time, now)),
       _ => None,
     }
   }
 
+  ///
+  /// Summarizes the state of this Node.
+  ///
+  pub(crate) fn status(&self) -> EntryStatus {
+    match *self.state.lock() {
+      EntryState::NotStarted { .. } => EntryStatus::NotStarted,
+      EntryState::Running { .. } => EntryStatus::Running,
+      EntryState::Completed { ref result, .. } if result.is_dirty() => EntryStatus::Dirty,
+      EntryState::Completed { ref result, .. } if result.as_ref().is_err() => EntryStatus::Failed,
+      EntryState::Completed { .. } => EntryStatus::Completed,
+    }
+  }
+
+  ///
+  /// If the Node has completed, returns when the run which completed it started, and how long it
+  /// took.
+  ///
+  pub fn completed_span(&self) -> Option<(Instant, Duration)> {
+    match *self.state.lock() {
+      EntryState::Completed {
+        start_time,
+        duration,
+        ..
+      } => Some((start_time, duration)),
+      _ => None,
+    }
+  }
+
+  fn duration_since(start_time: Instant, now: Instant) -> Duration {
+    // NB: `Instant::duration_since` panics if the end time is before the start time, which can
+    // happen when starting a Node races against a caller creating their Instant.
+    if start_time < now {
+      now.duration_since(start_time)
+    } else {
+      Duration::from_secs(0)
+    }
+  }
+
   ///
   /// Clears the state of this Node, forcing it to be recomputed.
   ///
@@ -657,3 +788,52 @@ impl<N: Node> Entry<N> {
     format!("{} == {}", self.node, state).replace("\"", "\\\"")
   }
 }
+
+///
+/// A Future for the result of one run of a running Entry. When the last Waiter for a run is dropped
+/// before the run has completed, the run is cancelled (see `Entry::cancel_if_abandoned`).
+///
+struct Waiter<N: Node> {
+  entry: Entry<N>,
+  run_token: RunToken,
+  receiver: Option<oneshot::Receiver<WaiterResult<N>>>,
+}
+
+type WaiterResult<N> = Result<(<N as Node>::Item, Generation), <N as Node>::Error>;
+
+impl<N: Node> Future for Waiter<N> {
+  type Item = (N::Item, Generation);
+  type Error = N::Error;
+
+  fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    let res = match self.receiver {
+      Some(ref mut receiver) => receiver.poll(),
+      None => panic!("A Waiter should not be polled after it has completed."),
+    };
+    match res {
+      Ok(Async::NotReady) => Ok(Async::NotReady),
+      Ok(Async::Ready(Ok(item))) => {
+        self.receiver = None;
+        Ok(Async::Ready(item))
+      }
+      Ok(Async::Ready(Err(err))) => {
+        self.receiver = None;
+        Err(err)
+      }
+      Err(_) => {
+        // The run was cleared or cancelled before completing.
+        self.receiver = None;
+        Err(N::Error::invalidated())
+      }
+    }
+  }
+}
+
+impl<N: Node> Drop for Waiter<N> {
+  fn drop(&mut self) {
+    // Drop the receiver before checking whether any waiters remain for the run.
+    if self.receiver.take().is_some() {
+      self.entry.cancel_if_abandoned(self.run_token);
+    }
+  }
+}
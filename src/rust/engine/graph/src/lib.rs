@@ -34,13 +34,15 @@ mod entry;
 mod node;
 
 pub use crate::entry::Entry;
-use crate::entry::{Generation, RunToken};
+use crate::entry::{EntryState, Generation, RunToken};
 
+use std::cmp;
 use std::collections::binary_heap::BinaryHeap;
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::{File, OpenOptions};
 use std::hash::BuildHasherDefault;
 use std::io::{self, BufWriter, Write};
+use std::mem;
 use std::path::Path;
 use std::time::{Duration, Instant};
 
@@ -48,18 +50,28 @@ use fnv::FnvHasher;
 
 use futures::future::{self, Future};
 use indexmap::IndexSet;
-use log::{debug, trace, warn};
+use log::{debug, info, trace, warn};
 use parking_lot::Mutex;
-use petgraph::graph::DiGraph;
-use petgraph::visit::EdgeRef;
+use petgraph::stable_graph::StableDiGraph;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences};
 use petgraph::Direction;
 
-pub use crate::node::{EntryId, Node, NodeContext, NodeError, NodeTracer, NodeVisualizer};
+pub use crate::node::{
+  EntryId, Node, NodeContext, NodeError, NodeEvent, NodeTracer, NodeVisualizer,
+};
 use boxfuture::{BoxFuture, Boxable};
 
 type FNV = BuildHasherDefault<FnvHasher>;
 
-type PGraph<N> = DiGraph<Entry<N>, f32, u32>;
+// The maximum number of distinct cycles to report when a dependency would create a cycle.
+const MAX_REPORTED_CYCLES: usize = 10;
+
+// The maximum number of edges to traverse while searching for cycles to report. The number of
+// simple paths in a dense graph is exponential in its size, and the search holds the Graph lock.
+const MAX_CYCLE_SEARCH_STEPS: usize = 10_000;
+
+// A StableGraph is used so that EntryIds remain valid when other Entries are evicted.
+type PGraph<N> = StableDiGraph<Entry<N>, f32, u32>;
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct InvalidationResult {
@@ -67,6 +79,104 @@ pub struct InvalidationResult {
   pub dirtied: usize,
 }
 
+///
+/// Limits on the size of a Graph, beyond which `Graph::evict` will remove Entries that are not
+/// reachable from any live roots. A limit of None is unbounded.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct EvictionBudget {
+  pub max_nodes: Option<usize>,
+  pub max_estimated_bytes: Option<usize>,
+}
+
+///
+/// The state of a Node since the invalidation which most recently affected it.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InvalidationState {
+  /// The Node was invalidated directly (for example: because a file that it reads changed).
+  Cleared,
+  /// The Node was dirtied because one of its transitive dependencies was cleared, and has not
+  /// been requested since.
+  Dirtied,
+  /// The Node was dirtied, but was cleaned without re-running because none of its dependencies
+  /// had actually changed.
+  Cleaned,
+  /// The Node re-ran.
+  Reran,
+}
+
+///
+/// A Node in a chain of invalidation, from a cleared Node to one which was dirtied by it.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InvalidatedNode<N: Node> {
+  pub node: N,
+  pub state: InvalidationState,
+}
+
+///
+/// Records why an Entry was invalidated, and what happened to it afterward.
+///
+struct Invalidation {
+  // The call to `invalidate_from_roots` that invalidated the Entry.
+  round: usize,
+  // The dependencies that were invalidated in the same round, causing this Entry to be dirtied.
+  // Empty for Entries that were cleared.
+  invalidated_by: Vec<EntryId>,
+  state: InvalidationState,
+}
+
+///
+/// When a completed Node ran, and the Nodes that it depends on.
+///
+#[derive(Clone, Debug)]
+pub struct EntryTiming<N: Node> {
+  pub node: N,
+  pub start_time: Instant,
+  pub duration: Duration,
+  pub dependencies: Vec<N>,
+}
+
+///
+/// The state of an Entry, as reported by `Graph::snapshot`.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EntryStatus {
+  NotStarted,
+  Running,
+  /// The Node completed successfully, and has not been dirtied since.
+  Completed,
+  /// The Node completed with an error, and has not been dirtied since.
+  Failed,
+  /// The Node completed, but one of its dependencies has since been invalidated.
+  Dirty,
+}
+
+///
+/// A point-in-time description of an Entry and its dependencies.
+///
+#[derive(Clone, Debug)]
+pub struct EntrySnapshot<N: Node> {
+  /// An id for the Entry, which is stable for as long as the Entry remains in the Graph.
+  pub id: usize,
+  pub node: N,
+  pub status: EntryStatus,
+  /// For a completed Node the duration of the run which completed it, and for a running Node the
+  /// time it has been running.
+  pub duration: Option<Duration>,
+  /// Incremented each time the output of the Node changes.
+  pub generation: u32,
+  /// The ids of the dependencies of the Node.
+  pub dependencies: Vec<usize>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct EvictionResult {
+  pub evicted: usize,
+  pub remaining: usize,
+}
+
 type Nodes<N> = HashMap<N, EntryId>;
 
 struct InnerGraph<N: Node> {
@@ -76,6 +186,12 @@ struct InnerGraph<N: Node> {
   /// while draining, any Nodes that exist in the Graph will continue to run until/unless they
   /// attempt to get/create new Nodes.
   draining: bool,
+  /// The total number of Entries that have been evicted from the Graph.
+  evicted: usize,
+  /// The most recent invalidation of each Entry which has been invalidated, and the number of
+  /// invalidations that have been recorded.
+  invalidations: HashMap<EntryId, Invalidation, FNV>,
+  invalidation_rounds: usize,
 }
 
 impl<N: Node> InnerGraph<N> {
@@ -83,7 +199,6 @@ impl<N: Node> InnerGraph<N> {
     self.nodes.get(node)
   }
 
-  // TODO: Now that we never delete Entries, we should consider making this infalliable.
   fn entry_for_id(&self, id: EntryId) -> Option<&Entry<N>> {
     self.pg.node_weight(id)
   }
@@ -99,19 +214,25 @@ impl<N: Node> InnerGraph<N> {
       .expect("The unsafe_entry_for_id method should only be used in read-only methods!")
   }
 
-  fn ensure_entry(&mut self, node: N) -> EntryId {
+  ///
+  /// Returns the EntryId for the given Node, and true if the Entry was created by this call.
+  ///
+  fn ensure_entry(&mut self, node: N) -> (EntryId, bool) {
     InnerGraph::ensure_entry_internal(&mut self.pg, &mut self.nodes, node)
   }
 
-  fn ensure_entry_internal(pg: &mut PGraph<N>, nodes: &mut Nodes<N>, node: N) -> EntryId {
+  fn ensure_entry_internal(pg: &mut PGraph<N>, nodes: &mut Nodes<N>, node: N) -> (EntryId, bool) {
     if let Some(&id) = nodes.get(&node) {
-      return id;
+      if let Some(entry) = pg.node_weight_mut(id) {
+        entry.mark_used();
+      }
+      return (id, false);
     }
 
     // New entry.
     let id = pg.add_node(Entry::new(node.clone()));
     nodes.insert(node, id);
-    id
+    (id, true)
   }
 
   ///
@@ -141,6 +262,92 @@ impl<N: Node> InnerGraph<N> {
     })
   }
 
+  ///
+  /// Returns up to `limit` distinct cycles which adding an edge from src to dst would create, as
+  /// paths which begin and end with dst.
+  ///
+  /// This enumerates simple paths from dst to src depth first, and so should only be called once a
+  /// cycle is known to exist. The search stops at the first `limit` cycles that it finds (or after
+  /// traversing `max_steps` edges, in which case it may find none), which are then sorted shortest
+  /// first: they are not necessarily the shortest `limit` cycles in the graph.
+  ///
+  fn find_cycles(
+    &self,
+    src_id: EntryId,
+    dst_id: EntryId,
+    limit: usize,
+    max_steps: usize,
+  ) -> Vec<Vec<Entry<N>>> {
+    if src_id == dst_id {
+      let entry = self.unsafe_entry_for_id(src_id);
+      return vec![vec![entry.clone(), entry.clone()]];
+    }
+
+    // Only Entries which can reach the src can be on a path from the dst to the src.
+    let mut roots = VecDeque::new();
+    roots.push_back(src_id);
+    let reaches_src: HashSet<EntryId, FNV> = self.walk(roots, Direction::Incoming).collect();
+    let candidates = |id: EntryId| -> Vec<EntryId> {
+      self
+        .pg
+        .neighbors_directed(id, Direction::Outgoing)
+        .filter(|dep_id| reaches_src.contains(dep_id))
+        .collect()
+    };
+
+    // Depth-first search for simple paths, using an explicit stack of the remaining candidates at
+    // each step of the current path.
+    let mut cycles = Vec::new();
+    let mut path = vec![dst_id];
+    let mut on_path: HashSet<EntryId, FNV> = HashSet::default();
+    on_path.insert(dst_id);
+    let mut stack = vec![candidates(dst_id)];
+    let mut steps = 0;
+    while let Some(remaining) = stack.last_mut() {
+      let next = remaining.pop();
+      if next.is_some() {
+        steps += 1;
+        if steps > max_steps {
+          break;
+        }
+      }
+      match next {
+        Some(next) if next == src_id => {
+          let mut cycle = path.clone();
+          cycle.push(src_id);
+          cycle.push(dst_id);
+          cycles.push(cycle);
+          if cycles.len() >= limit {
+            break;
+          }
+        }
+        Some(next) => {
+          if on_path.insert(next) {
+            path.push(next);
+            stack.push(candidates(next));
+          }
+        }
+        None => {
+          stack.pop();
+          if let Some(id) = path.pop() {
+            on_path.remove(&id);
+          }
+        }
+      }
+    }
+
+    cycles.sort_by_key(Vec::len);
+    cycles
+      .into_iter()
+      .map(|cycle| {
+        cycle
+          .into_iter()
+          .map(|id| self.unsafe_entry_for_id(id).clone())
+          .collect()
+      })
+      .collect()
+  }
+
   ///
   /// Detect whether adding an edge from src to dst would create a cycle.
   ///
@@ -212,7 +419,7 @@ impl<N: Node> InnerGraph<N> {
     let mut graph = self.pg.filter_map(
       |_node_idx, node_weight| Some(Some(node_weight)),
       |edge_idx, _edge_weight| {
-        let target_node = self.pg.raw_edges()[edge_idx.index()].target();
+        let (_, target_node) = self.pg.edge_endpoints(edge_idx).unwrap();
         self
           .pg
           .node_weight(target_node)
@@ -248,17 +455,13 @@ impl<N: Node> InnerGraph<N> {
       petgraph::algo::bellman_ford(&graph, src).expect("The graph must be acyclic");
     if let Some((index, total_duration)) = weights
       .iter()
-      // INFINITY is used for missing entries. We don't want for this to interfere with our max_by.
-      // Use NEG_INFINITY instead, which has to be the minimum duration.
-      .map(|weight| {
-        if *weight == std::f64::INFINITY {
-          std::f64::NEG_INFINITY
-        } else {
-          *weight
-        }
-      })
-      .map(|weight| Duration::from_nanos(-weight as u64))
       .enumerate()
+      // INFINITY is used for entries which are not reachable from the roots, which cannot be on
+      // the critical path. The indexes of evicted entries are skipped as well.
+      .filter(|(index, weight)| {
+        weight.is_finite() && graph.contains_node(petgraph::graph::NodeIndex::new(*index))
+      })
+      .map(|(index, weight)| (index, Duration::from_nanos(-weight as u64)))
       .max_by(|(_, left_duration), (_, right_duration)| left_duration.cmp(&right_duration))
     {
       let critical_path = {
@@ -301,6 +504,9 @@ impl<N: Node> InnerGraph<N> {
         entry.clear(true);
       }
     }
+    // Every Entry has been cleared, so the recorded reasons for earlier invalidations no longer
+    // describe their state.
+    self.invalidations.clear();
   }
 
   ///
@@ -309,7 +515,14 @@ impl<N: Node> InnerGraph<N> {
   /// An "invalidation root" is a Node in the graph which can be invalidated for a reason other
   /// than having had its dependencies changed.
   ///
-  fn invalidate_from_roots<P: Fn(&N) -> bool>(&mut self, predicate: P) -> InvalidationResult {
+  /// If `record` is true, records which invalidated dependencies caused each entry to be dirtied,
+  /// for use in `invalidation_chains`.
+  ///
+  fn invalidate_from_roots<P: Fn(&N) -> bool>(
+    &mut self,
+    predicate: P,
+    record: bool,
+  ) -> InvalidationResult {
     // Collect all entries that will be cleared.
     let root_ids: HashSet<_, FNV> = self
       .nodes
@@ -333,6 +546,10 @@ impl<N: Node> InnerGraph<N> {
       dirtied: transitive_ids.len(),
     };
 
+    if record {
+      self.record_invalidation(&root_ids, &transitive_ids);
+    }
+
     // Clear roots and remove their outbound edges.
     for id in &root_ids {
       if let Some(entry) = self.pg.node_weight_mut(*id) {
@@ -359,6 +576,110 @@ impl<N: Node> InnerGraph<N> {
     invalidation_result
   }
 
+  fn record_invalidation(&mut self, root_ids: &HashSet<EntryId, FNV>, transitive_ids: &[EntryId]) {
+    self.invalidation_rounds += 1;
+    let round = self.invalidation_rounds;
+    for id in root_ids {
+      self.invalidations.insert(
+        *id,
+        Invalidation {
+          round,
+          invalidated_by: vec![],
+          state: InvalidationState::Cleared,
+        },
+      );
+    }
+    let transitive_id_set: HashSet<_, FNV> = transitive_ids.iter().cloned().collect();
+    for id in transitive_ids {
+      let invalidated_by = self
+        .pg
+        .neighbors_directed(*id, Direction::Outgoing)
+        .filter(|dep_id| root_ids.contains(dep_id) || transitive_id_set.contains(dep_id))
+        .collect();
+      self.invalidations.insert(
+        *id,
+        Invalidation {
+          round,
+          invalidated_by,
+          state: InvalidationState::Dirtied,
+        },
+      );
+    }
+  }
+
+  ///
+  /// Records the outcome of (re)running an Entry which was invalidated.
+  ///
+  fn record_completion(&mut self, entry_id: EntryId, reran: bool) {
+    if let Some(invalidation) = self.invalidations.get_mut(&entry_id) {
+      invalidation.state = match (invalidation.state, reran) {
+        (InvalidationState::Dirtied, false) => InvalidationState::Cleaned,
+        (InvalidationState::Cleared, _) | (InvalidationState::Dirtied, true) => {
+          InvalidationState::Reran
+        }
+        (state, _) => state,
+      };
+    }
+  }
+
+  ///
+  /// For the invalidation that most recently affected the given Node, returns one chain for each
+  /// cleared Node that caused it, from the cleared Node through its dependents to the given Node.
+  ///
+  /// Returns no chains if the Node has never been invalidated. If some of the Nodes in a chain
+  /// have since been invalidated again (or evicted), the chain cannot be followed and is omitted.
+  ///
+  fn invalidation_chains(&self, node: &N) -> Vec<Vec<InvalidatedNode<N>>> {
+    let root_id = match self.entry_id(node) {
+      Some(&id) => id,
+      None => return vec![],
+    };
+    let round = match self.invalidations.get(&root_id) {
+      Some(invalidation) => invalidation.round,
+      None => return vec![],
+    };
+
+    // Breadth first search down to the cleared Nodes, recording the dependent that each Node was
+    // reached from.
+    let mut queue = VecDeque::new();
+    queue.push_back(root_id);
+    let mut dependents: HashMap<EntryId, EntryId, FNV> = HashMap::default();
+    let mut cleared_ids = Vec::new();
+    while let Some(id) = queue.pop_front() {
+      let invalidation = &self.invalidations[&id];
+      if invalidation.invalidated_by.is_empty() {
+        cleared_ids.push(id);
+        continue;
+      }
+      for &dep_id in &invalidation.invalidated_by {
+        let same_round = self
+          .invalidations
+          .get(&dep_id)
+          .map_or(false, |invalidation| invalidation.round == round);
+        if same_round && dep_id != root_id && !dependents.contains_key(&dep_id) {
+          dependents.insert(dep_id, id);
+          queue.push_back(dep_id);
+        }
+      }
+    }
+
+    cleared_ids
+      .into_iter()
+      .filter_map(|cleared_id| {
+        let mut chain = Vec::new();
+        let mut next = Some(cleared_id);
+        while let Some(id) = next {
+          chain.push(InvalidatedNode {
+            node: self.entry_for_id(id)?.node().clone(),
+            state: self.invalidations[&id].state,
+          });
+          next = dependents.get(&id).cloned();
+        }
+        Some(chain)
+      })
+      .collect()
+  }
+
   fn visualize<V: NodeVisualizer<N>>(
     &self,
     mut visualizer: V,
@@ -442,15 +763,14 @@ impl<N: Node> InnerGraph<N> {
       bottom_nodes
     };
 
-    // Invert the graph into a evenly-weighted dependent graph by cloning it and stripping out
-    // the Nodes (to avoid cloning them), adding equal edge weights, and then reversing it.
-    // Because we do not remove any Nodes or edges, all EntryIds remain stable.
+    // Invert the graph into a evenly-weighted dependent graph by copying its structure without
+    // the Nodes (to avoid cloning them), and then adding reversed edges with equal weights.
+    // Because we do not remove any Nodes, all EntryIds remain stable.
     let dependent_graph = {
-      let mut dg = self
-        .pg
-        .filter_map(|_, _| Some(()), |_, _| Some(1.0))
-        .clone();
-      dg.reverse();
+      let mut dg = self.pg.filter_map(|_, _| Some(()), |_, _| None);
+      for edge in self.pg.edge_references() {
+        dg.add_edge(edge.target(), edge.source(), 1.0);
+      }
       dg
     };
 
@@ -588,6 +908,288 @@ impl<N: Node> InnerGraph<N> {
     res
   }
 
+  ///
+  /// An estimate of the memory used by the Graph, which accounts for the fixed size of each
+  /// Entry and edge, but not for anything that their Nodes or results own on the heap.
+  ///
+  fn estimated_size_bytes(&self) -> usize {
+    let entry_size = 2 * mem::size_of::<N>()
+      + mem::size_of::<EntryId>()
+      + mem::size_of::<Entry<N>>()
+      + mem::size_of::<EntryState<N>>();
+    let edge_size = mem::size_of::<petgraph::graph::Edge<f32, u32>>();
+    self.nodes.len() * entry_size + self.pg.edge_count() * edge_size
+  }
+
+  fn within_budget(&self, budget: &EvictionBudget) -> bool {
+    budget
+      .max_nodes
+      .map_or(true, |max_nodes| self.nodes.len() <= max_nodes)
+      && budget
+        .max_estimated_bytes
+        .map_or(true, |max_bytes| self.estimated_size_bytes() <= max_bytes)
+  }
+
+  ///
+  /// Computes when each Entry or any of its transitive dependents was last used. Requesting a
+  /// Node which has already completed does not touch its dependencies, so this (rather than the
+  /// Entry's own last use) is how recently the Entry was actually needed.
+  ///
+  fn transitive_last_used(&self) -> HashMap<EntryId, Instant, FNV> {
+    let ids = petgraph::algo::toposort(&self.pg, None).unwrap_or_else(|_| {
+      warn!("Graph contained a cycle: evicting without considering dependents.");
+      self.pg.node_indices().collect()
+    });
+    let mut last_used: HashMap<EntryId, Instant, FNV> = HashMap::default();
+    // Dependents are ordered before their dependencies, so they will already have been visited.
+    for id in ids {
+      let id_last_used = self
+        .pg
+        .neighbors_directed(id, Direction::Incoming)
+        .filter_map(|dependent_id| last_used.get(&dependent_id))
+        .fold(self.unsafe_entry_for_id(id).last_used(), |a, &b| {
+          cmp::max(a, b)
+        });
+      last_used.insert(id, id_last_used);
+    }
+    last_used
+  }
+
+  ///
+  /// Evicts Entries which are not reachable from the given roots, least recently used first,
+  /// until the Graph is within the given budget.
+  ///
+  /// An Entry is always evicted along with its transitive dependents, since those would otherwise
+  /// not be invalidated when the evicted Entry should have been. Because the Entry is unreachable
+  /// from the roots, so are all of its dependents. Entries which are running (or which have
+  /// running dependents) are never evicted.
+  ///
+  fn evict(&mut self, roots: &[N], budget: &EvictionBudget) -> EvictionResult {
+    if self.within_budget(budget) {
+      return EvictionResult {
+        evicted: 0,
+        remaining: self.nodes.len(),
+      };
+    }
+
+    let root_ids = roots
+      .iter()
+      .filter_map(|node| self.entry_id(node))
+      .cloned()
+      .collect();
+    let reachable: HashSet<EntryId, FNV> = self.walk(root_ids, Direction::Outgoing).collect();
+    let last_used = self.transitive_last_used();
+    let mut candidates: Vec<_> = self
+      .pg
+      .node_indices()
+      .filter(|id| !reachable.contains(id))
+      .map(|id| (last_used[&id], id))
+      .collect();
+    candidates.sort();
+
+    let now = Instant::now();
+    let mut evicted = 0;
+    for (_, candidate_id) in candidates {
+      if self.within_budget(budget) {
+        break;
+      }
+      if !self.pg.contains_node(candidate_id) {
+        // Already evicted as the dependent of an earlier candidate.
+        continue;
+      }
+      let ids: Vec<_> = self
+        .walk(vec![candidate_id].into(), Direction::Incoming)
+        .collect();
+      let any_running = ids.iter().any(|id| {
+        self
+          .unsafe_entry_for_id(*id)
+          .current_running_duration(now)
+          .is_some()
+      });
+      if any_running {
+        continue;
+      }
+      for id in ids {
+        if let Some(entry) = self.pg.remove_node(id) {
+          self.nodes.remove(entry.node());
+          self.invalidations.remove(&id);
+          evicted += 1;
+        }
+      }
+    }
+
+    self.evicted += evicted;
+    EvictionResult {
+      evicted,
+      remaining: self.nodes.len(),
+    }
+  }
+
+  fn completed_timings(&self, roots: &[N], direction: Direction) -> Vec<EntryTiming<N>> {
+    let root_ids = roots
+      .iter()
+      .filter_map(|node| self.entry_id(node))
+      .cloned()
+      .collect();
+    self
+      .walk(root_ids, direction)
+      .filter_map(|id| {
+        let entry = self.unsafe_entry_for_id(id);
+        let (start_time, duration) = entry.completed_span()?;
+        Some(EntryTiming {
+          node: entry.node().clone(),
+          start_time,
+          duration,
+          dependencies: self
+            .pg
+            .neighbors_directed(id, Direction::Outgoing)
+            .map(|dep_id| self.unsafe_entry_for_id(dep_id).node().clone())
+            .collect(),
+        })
+      })
+      .collect()
+  }
+
+  ///
+  /// Returns the Nodes which are reachable in the given direction from the given roots, along with
+  /// the length of the shortest path to each of them. Walks at most `max_depth` edges from the
+  /// roots, which are not themselves included. Roots which are not in the Graph are ignored.
+  ///
+  fn transitive_neighbors(
+    &self,
+    roots: &[N],
+    direction: Direction,
+    max_depth: Option<usize>,
+  ) -> Vec<(N, usize)> {
+    let mut depths: HashMap<EntryId, usize, FNV> = roots
+      .iter()
+      .filter_map(|node| self.entry_id(node))
+      .map(|&entry_id| (entry_id, 0))
+      .collect();
+    let mut deque: VecDeque<_> = depths.keys().cloned().collect();
+    let mut neighbors = Vec::new();
+    while let Some(id) = deque.pop_front() {
+      let depth = depths[&id] + 1;
+      if max_depth
+        .map(|max_depth| depth > max_depth)
+        .unwrap_or(false)
+      {
+        continue;
+      }
+      for neighbor_id in self.pg.neighbors_directed(id, direction) {
+        if depths.contains_key(&neighbor_id) {
+          continue;
+        }
+        depths.insert(neighbor_id, depth);
+        deque.push_back(neighbor_id);
+        neighbors.push((self.unsafe_entry_for_id(neighbor_id).node().clone(), depth));
+      }
+    }
+    neighbors
+  }
+
+  ///
+  /// Snapshots the Entries reachable from the given roots which match the given predicate.
+  ///
+  /// When an Entry is filtered out, its dependents are instead connected to its nearest
+  /// transitive dependencies which match the predicate, so that the shape of the Graph is
+  /// preserved.
+  ///
+  fn snapshot<P>(&self, roots: &[N], predicate: P) -> Vec<EntrySnapshot<N>>
+  where
+    P: Fn(&EntrySnapshot<N>) -> bool,
+  {
+    let root_ids = roots
+      .iter()
+      .filter_map(|node| self.entry_id(node))
+      .cloned()
+      .collect();
+    let now = Instant::now();
+    let snapshots: Vec<_> = self
+      .walk(root_ids, Direction::Outgoing)
+      .map(|id| {
+        let entry = self.unsafe_entry_for_id(id);
+        EntrySnapshot {
+          id: id.index(),
+          node: entry.node().clone(),
+          status: entry.status(),
+          duration: entry
+            .completed_span()
+            .map(|(_, duration)| duration)
+            .or_else(|| entry.current_running_duration(now)),
+          generation: entry.generation().value(),
+          dependencies: self
+            .pg
+            .neighbors_directed(id, Direction::Outgoing)
+            .map(EntryId::index)
+            .collect(),
+        }
+      })
+      .collect();
+    let matching: HashSet<usize, FNV> = snapshots
+      .iter()
+      .filter(|snapshot| predicate(snapshot))
+      .map(|snapshot| snapshot.id)
+      .collect();
+    if matching.len() == snapshots.len() {
+      return snapshots;
+    }
+
+    // The nearest matching transitive dependencies of each filtered out Entry, computed once per
+    // Entry by a single depth-first walk which visits dependencies before their dependents.
+    let dependencies: HashMap<usize, &[usize], FNV> = snapshots
+      .iter()
+      .map(|snapshot| (snapshot.id, snapshot.dependencies.as_slice()))
+      .collect();
+    let nearest_matching = |ids: &[usize], nearest: &HashMap<usize, Vec<usize>, FNV>| {
+      let mut seen: HashSet<usize, FNV> = HashSet::default();
+      ids
+        .iter()
+        .flat_map(|id| {
+          if matching.contains(id) {
+            vec![*id]
+          } else {
+            nearest.get(id).cloned().unwrap_or_default()
+          }
+        })
+        .filter(|id| seen.insert(*id))
+        .collect::<Vec<_>>()
+    };
+    let mut nearest: HashMap<usize, Vec<usize>, FNV> = HashMap::default();
+    for snapshot in &snapshots {
+      if matching.contains(&snapshot.id) {
+        continue;
+      }
+      let mut stack = vec![(snapshot.id, false)];
+      while let Some((id, expanded)) = stack.pop() {
+        if nearest.contains_key(&id) {
+          continue;
+        }
+        if expanded {
+          let found = nearest_matching(dependencies[&id], &nearest);
+          nearest.insert(id, found);
+        } else {
+          stack.push((id, true));
+          stack.extend(
+            dependencies[&id]
+              .iter()
+              .filter(|dep| !matching.contains(dep) && !nearest.contains_key(dep))
+              .map(|&dep| (dep, false)),
+          );
+        }
+      }
+    }
+
+    snapshots
+      .iter()
+      .filter(|snapshot| matching.contains(&snapshot.id))
+      .map(|snapshot| EntrySnapshot {
+        dependencies: nearest_matching(&snapshot.dependencies, &nearest),
+        ..snapshot.clone()
+      })
+      .collect()
+  }
+
   fn reachable_digest_count(&self, roots: &[N]) -> usize {
     let root_ids = roots
       .iter()
@@ -631,7 +1233,10 @@ impl<N: Node> Graph<N> {
     let inner = InnerGraph {
       draining: false,
       nodes: HashMap::default(),
-      pg: DiGraph::new(),
+      pg: StableDiGraph::new(),
+      evicted: 0,
+      invalidations: HashMap::default(),
+      invalidation_rounds: 0,
     };
     Graph {
       inner: Mutex::new(inner),
@@ -643,6 +1248,18 @@ impl<N: Node> Graph<N> {
     inner.nodes.len()
   }
 
+  ///
+  /// The number of Entries which have requested the given Entry, and are waiting for (or have
+  /// consumed) its value.
+  ///
+  pub fn dependent_count(&self, entry_id: EntryId) -> usize {
+    let inner = self.inner.lock();
+    inner
+      .pg
+      .neighbors_directed(entry_id, Direction::Incoming)
+      .count()
+  }
+
   ///
   /// In the context of the given src Node, declare a dependency on the given dst Node and
   /// begin its execution if it has not already started.
@@ -657,16 +1274,20 @@ impl<N: Node> Graph<N> {
       if inner.draining {
         None
       } else {
-        let dst_id = {
+        let (dst_id, created) = {
           // TODO: doing cycle detection under the lock... unfortunate, but probably unavoidable
           // without a much more complicated algorithm.
-          let potential_dst_id = inner.ensure_entry(dst_node.clone());
-          if let Some(cycle_path) = Self::report_cycle(src_id, potential_dst_id, &mut inner) {
+          let (potential_dst_id, created) = inner.ensure_entry(dst_node.clone());
+          if let Some(cycles) = Self::report_cycle(src_id, potential_dst_id, &mut inner) {
             // Cyclic dependency: render an error.
-            let path_strs = cycle_path
-              .into_iter()
-              .map(|e| e.node().to_string())
+            let path_strs = cycles
+              .iter()
+              .map(|cycle| Self::render_cycle(cycle))
               .collect();
+            mem::drop(inner);
+            if created {
+              context.record_event(&dst_node, NodeEvent::Created);
+            }
             return futures::future::err(N::Error::cyclic(path_strs)).to_boxed();
           } else {
             // Valid dependency.
@@ -675,7 +1296,7 @@ impl<N: Node> Graph<N> {
               inner.entry_for_id(src_id).unwrap().node(),
               inner.entry_for_id(potential_dst_id).unwrap().node()
             );
-            potential_dst_id
+            (potential_dst_id, created)
           }
         };
         // All edges get a weight of 1.0 so that we can Bellman-Ford over the graph, treating each
@@ -684,23 +1305,51 @@ impl<N: Node> Graph<N> {
         inner
           .entry_for_id(dst_id)
           .cloned()
-          .map(|entry| (entry, dst_id))
+          .map(|entry| (entry, dst_id, created))
       }
     };
 
     // Declare the dep, and return the state of the destination.
-    if let Some((mut entry, entry_id)) = maybe_entry_and_id {
+    if let Some((mut entry, entry_id, created)) = maybe_entry_and_id {
+      if created {
+        context.record_event(entry.node(), NodeEvent::Created);
+      }
       entry.get(context, entry_id).map(|(res, _)| res).to_boxed()
     } else {
       future::err(N::Error::invalidated()).to_boxed()
     }
   }
 
+  ///
+  /// Renders each Node in a cycle, along with why it is depended on by the previous Node.
+  ///
+  fn render_cycle(cycle: &[Entry<N>]) -> Vec<String> {
+    cycle
+      .iter()
+      .enumerate()
+      .map(|(index, entry)| {
+        let description = if index > 0 {
+          cycle[index - 1].node().describe_dependency(entry.node())
+        } else {
+          None
+        };
+        match description {
+          Some(description) => format!("{} ({})", entry.node(), description),
+          None => entry.node().to_string(),
+        }
+      })
+      .collect()
+  }
+
+  ///
+  /// If adding an edge from src to dst would create a cycle (after clearing dirty Nodes which
+  /// might have obsolete edges), returns the distinct cycles that it would create.
+  ///
   fn report_cycle(
     src_id: EntryId,
     potential_dst_id: EntryId,
     inner: &mut InnerGraph<N>,
-  ) -> Option<Vec<Entry<N>>> {
+  ) -> Option<Vec<Vec<Entry<N>>>> {
     let mut counter = 0;
     loop {
       // Find one cycle if any cycles exist.
@@ -721,7 +1370,22 @@ impl<N: Node> Graph<N> {
             inner.entry_for_id(potential_dst_id).unwrap(),
             cycle_path
           );
-          return Some(cycle_path);
+          // Report the cycles that do not contain dirty nodes, or this one if none of them qualify.
+          let cycles = inner
+            .find_cycles(
+              src_id,
+              potential_dst_id,
+              MAX_REPORTED_CYCLES,
+              MAX_CYCLE_SEARCH_STEPS,
+            )
+            .into_iter()
+            .filter(|cycle| !cycle.iter().any(Entry::may_have_dirty_edges))
+            .collect::<Vec<_>>();
+          return Some(if cycles.is_empty() {
+            vec![cycle_path]
+          } else {
+            cycles
+          });
         }
         counter += 1;
         // Obsolete edges from a dirty node may cause fake cycles to be detected if there was a
@@ -740,10 +1404,10 @@ impl<N: Node> Graph<N> {
             "Couldn't remove cycle containing dirty nodes after {} attempts; nodes in cycle: {:?}",
             counter, cycle_path
           );
-          return Some(cycle_path);
+          return Some(vec![cycle_path]);
         }
         // Clear the dirty nodes, removing the edges from them, and try again.
-        inner.invalidate_from_roots(|node| dirty_nodes.contains(node));
+        inner.invalidate_from_roots(|node| dirty_nodes.contains(node), false);
       } else {
         return None;
       }
@@ -773,11 +1437,17 @@ impl<N: Node> Graph<N> {
       if inner.draining {
         None
       } else {
-        let id = inner.ensure_entry(node);
-        inner.entry_for_id(id).cloned().map(|entry| (entry, id))
+        let (id, created) = inner.ensure_entry(node);
+        inner
+          .entry_for_id(id)
+          .cloned()
+          .map(|entry| (entry, id, created))
       }
     };
-    if let Some((mut entry, entry_id)) = maybe_entry_and_id {
+    if let Some((mut entry, entry_id, created)) = maybe_entry_and_id {
+      if created {
+        context.record_event(entry.node(), NodeEvent::Created);
+      }
       entry.get(context, entry_id).map(|(res, _)| res).to_boxed()
     } else {
       future::err(N::Error::invalidated()).to_boxed()
@@ -809,6 +1479,7 @@ impl<N: Node> Graph<N> {
           let entry = inner
             .entry_for_id_mut(dep_id)
             .unwrap_or_else(|| panic!("Dependency not present in Graph."));
+          entry.mark_used();
           entry
             .get(context, dep_id)
             .map(|(_, generation)| generation)
@@ -901,7 +1572,15 @@ impl<N: Node> Graph<N> {
       )
     };
     if let Some(mut entry) = entry {
+      // Whether the Node re-ran (rather than being cleaned), for the purposes of invalidation
+      // provenance. Ephemeral failures don't complete the Node, so aren't recorded.
+      let reran = match &result {
+        None => Some(false),
+        Some(Err(e)) if *e == N::Error::invalidated() || *e == N::Error::cancelled() => None,
+        Some(_) => Some(true),
+      };
       let mut inner = self.inner.lock();
+      let is_current_run = entry.run_token() == run_token;
       entry.complete(
         context,
         entry_id,
@@ -910,6 +1589,9 @@ impl<N: Node> Graph<N> {
         result,
         &mut inner,
       );
+      if let (true, Some(reran)) = (is_current_run, reran) {
+        inner.record_completion(entry_id, reran);
+      }
     }
   }
 
@@ -921,9 +1603,22 @@ impl<N: Node> Graph<N> {
     inner.clear()
   }
 
+  ///
+  /// Clears the Nodes matching the predicate, and dirties their transitive dependents.
+  ///
+  /// The cause of each invalidation is recorded, and can be queried with `invalidation_chains`.
+  ///
   pub fn invalidate_from_roots<P: Fn(&N) -> bool>(&self, predicate: P) -> InvalidationResult {
     let mut inner = self.inner.lock();
-    inner.invalidate_from_roots(predicate)
+    inner.invalidate_from_roots(predicate, true)
+  }
+
+  ///
+  /// Explains the most recent invalidation of the given Node: see `InnerGraph::invalidation_chains`.
+  ///
+  pub fn invalidation_chains(&self, node: &N) -> Vec<Vec<InvalidatedNode<N>>> {
+    let inner = self.inner.lock();
+    inner.invalidation_chains(node)
   }
 
   pub fn trace<T: NodeTracer<N>>(&self, roots: &[N], path: &Path) -> Result<(), String> {
@@ -941,11 +1636,93 @@ impl<N: Node> Graph<N> {
     inner.visualize(visualizer, roots, path)
   }
 
+  ///
+  /// If the given Node has completed (and has not since been dirtied), returns its result.
+  ///
+  pub fn peek(&self, node: &N) -> Option<Result<N::Item, N::Error>> {
+    let inner = self.inner.lock();
+    inner
+      .entry_id(node)
+      .and_then(|&id| inner.entry_for_id(id))
+      .and_then(Entry::peek)
+  }
+
   pub fn heavy_hitters(&self, roots: &[N], k: usize) -> HashMap<String, Duration> {
     let inner = self.inner.lock();
     inner.heavy_hitters(roots, k)
   }
 
+  ///
+  /// If the Graph has grown beyond the given budget, evicts Entries that are not reachable from
+  /// the given (live) roots, least recently used first, until it fits within the budget.
+  ///
+  pub fn evict(&self, roots: &[N], budget: &EvictionBudget) -> EvictionResult {
+    let mut inner = self.inner.lock();
+    let result = inner.evict(roots, budget);
+    if result.evicted > 0 {
+      info!(
+        "eviction: evicted {} nodes, leaving {} nodes.",
+        result.evicted, result.remaining
+      );
+    }
+    result
+  }
+
+  ///
+  /// The total number of Entries that have been evicted from this Graph.
+  ///
+  pub fn evicted_count(&self) -> usize {
+    let inner = self.inner.lock();
+    inner.evicted
+  }
+
+  ///
+  /// Returns when each of the completed Nodes reachable from the given roots ran.
+  ///
+  pub fn completed_timings(&self, roots: &[N]) -> Vec<EntryTiming<N>> {
+    let inner = self.inner.lock();
+    inner.completed_timings(roots, Direction::Outgoing)
+  }
+
+  ///
+  /// Returns when each of the completed Nodes which are (or transitively depend on) the given Nodes
+  /// ran.
+  ///
+  pub fn completed_dependent_timings(&self, nodes: &[N]) -> Vec<EntryTiming<N>> {
+    let inner = self.inner.lock();
+    inner.completed_timings(nodes, Direction::Incoming)
+  }
+
+  ///
+  /// Returns the transitive dependencies of the given roots, up to `max_depth` edges away, along
+  /// with their distance from the nearest root.
+  ///
+  pub fn transitive_dependencies(&self, roots: &[N], max_depth: Option<usize>) -> Vec<(N, usize)> {
+    let inner = self.inner.lock();
+    inner.transitive_neighbors(roots, Direction::Outgoing, max_depth)
+  }
+
+  ///
+  /// Returns the transitive dependents of the given roots, up to `max_depth` edges away, along
+  /// with their distance from the nearest root.
+  ///
+  pub fn transitive_dependents(&self, roots: &[N], max_depth: Option<usize>) -> Vec<(N, usize)> {
+    let inner = self.inner.lock();
+    inner.transitive_neighbors(roots, Direction::Incoming, max_depth)
+  }
+
+  ///
+  /// Snapshots the Entries reachable from the given roots which match the given predicate: see
+  /// `InnerGraph::snapshot`.
+  ///
+  pub fn snapshot<P>(&self, roots: &[N], predicate: P) -> Vec<EntrySnapshot<N>>
+  where
+    P: Fn(&EntrySnapshot<N>) -> bool,
+  {
+    let inner = self.inner.lock();
+    inner.snapshot(roots, predicate)
+  }
+
   pub fn reachable_digest_count(&self, roots: &[N]) -> usize {
     let inner = self.inner.lock();
     inner.reachable_digest_count(roots)
@@ -956,6 +1733,16 @@ impl<N: Node> Graph<N> {
     inner.all_digests()
   }
 
+  ///
+  /// Calls the given function for each Node in the Graph, while holding the Graph's lock.
+  ///
+  pub fn visit_nodes<F: FnMut(&N)>(&self, mut f: F) {
+    let inner = self.inner.lock();
+    for node in inner.nodes.keys() {
+      f(node);
+    }
+  }
+
   ///
   /// Executes an operation while all access to the Graph is prevented (by acquiring the Graph's
   /// lock).
@@ -3,6 +3,7 @@
 
 use std::fmt::{Debug, Display};
 use std::hash::Hash;
+use std::time::Duration;
 
 use boxfuture::BoxFuture;
 use hashing::Digest;
@@ -47,6 +48,14 @@ pub trait Node: Clone + Debug + Display + Eq + Hash + Send + 'static {
   fn user_facing_name(&self) -> Option<String> {
     None
   }
+
+  ///
+  /// Describes why this Node depends on the given dependency, for use when rendering dependency
+  /// cycles. Nodes which have nothing to add to the rendering of the dependency itself return None.
+  ///
+  fn describe_dependency(&self, _dependency: &Self) -> Option<String> {
+    None
+  }
 }
 
 pub trait NodeError: Clone + Debug + Eq + Send {
@@ -57,9 +66,31 @@ pub trait NodeError: Clone + Debug + Eq + Send {
   fn invalidated() -> Self;
 
   ///
-  /// Creates an instance that represents that a Node dependency was cyclic along the given path.
+  /// Creates an instance that represents that a Node was cancelled (generally because the
+  /// request that caused it to run was cancelled).
+  ///
+  fn cancelled() -> Self;
+
+  ///
+  /// Creates an instance that represents that a Node dependency was cyclic along each of the given
+  /// paths. Each path begins and ends with the same Node, and each element of a path after the
+  /// first describes a dependency of the previous element.
   ///
-  fn cyclic(path: Vec<String>) -> Self;
+  fn cyclic(paths: Vec<Vec<String>>) -> Self;
+}
+
+///
+/// An event in the lifecycle of a Node, as reported to `NodeContext::record_event`.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum NodeEvent {
+  /// An Entry was created for the Node.
+  Created,
+  /// The Node was dirtied, but completed without re-running because none of its dependencies had
+  /// changed.
+  Cleaned,
+  /// The Node ran to completion. `reran` is true if the Node had completed before.
+  Ran { reran: bool, duration: Duration },
 }
 
 ///
@@ -127,4 +158,10 @@ pub trait NodeContext: Clone + Send + 'static {
   fn spawn<F>(&self, future: F)
   where
     F: Future<Item = (), Error = ()> + Send + 'static;
+
+  ///
+  /// Records an event in the lifecycle of the given Node, for the purposes of metrics. Called
+  /// outside of the Graph lock. Events are ignored by default.
+  ///
+  fn record_event(&self, _node: &Self::Node, _event: NodeEvent) {}
 }
@@ -14,7 +14,10 @@ use parking_lot::Mutex;
 
 use rand::Rng;
 
-use crate::{EntryId, Graph, InvalidationResult, Node, NodeContext, NodeError};
+use crate::{
+  EntryId, EntrySnapshot, EntryStatus, EntryTiming, EvictionBudget, EvictionResult, Graph,
+  InvalidatedNode, InvalidationResult, InvalidationState, Node, NodeContext, NodeError, NodeEvent,
+};
 
 #[test]
 fn create() {
@@ -26,6 +29,58 @@ fn create() {
   );
 }
 
+#[test]
+fn completed_timings() {
+  let graph = Arc::new(Graph::new());
+  let delay = Duration::from_millis(100);
+  let context = TContext::new_with_delays(
+    0,
+    vec![(TNode(1), delay)].into_iter().collect(),
+    graph.clone(),
+  );
+  assert_eq!(
+    graph.create(TNode(2), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+  );
+
+  let mut timings = graph.completed_timings(&[TNode(1)]);
+  timings.sort_by_key(|timing| timing.node.0);
+  let (zero, one) = match timings.as_slice() {
+    [zero, one] => (zero, one),
+    _ => panic!("Expected timings for two nodes, got: {:?}", timings),
+  };
+  let span = |timing: &EntryTiming<TNode>| (timing.start_time, timing.start_time + timing.duration);
+
+  // TNode(1) waited for the delay before requesting TNode(0), and so encloses it.
+  assert_eq!(one.node, TNode(1));
+  assert_eq!(one.dependencies, vec![TNode(0)]);
+  assert!(one.duration >= delay);
+  assert_eq!(zero.node, TNode(0));
+  assert!(zero.dependencies.is_empty());
+  assert!(span(one).0 <= span(zero).0 && span(zero).1 <= span(one).1);
+}
+
+#[test]
+fn completed_dependent_timings() {
+  let graph = Arc::new(Graph::new());
+  let context = TContext::new(0, graph.clone());
+  assert_eq!(
+    graph.create(TNode(2), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+  );
+
+  // Only the given Node and its dependents are included, with all of their dependencies.
+  let mut timings = graph.completed_dependent_timings(&[TNode(1)]);
+  timings.sort_by_key(|timing| timing.node.0);
+  assert_eq!(
+    timings
+      .into_iter()
+      .map(|timing| (timing.node, timing.dependencies))
+      .collect::<Vec<_>>(),
+    vec![(TNode(1), vec![TNode(0)]), (TNode(2), vec![TNode(1)])]
+  );
+}
+
 #[test]
 fn invalidate_and_clean() {
   let graph = Arc::new(Graph::new());
@@ -55,6 +110,325 @@ fn invalidate_and_clean() {
   assert_eq!(context.runs(), vec![TNode(2), TNode(1), TNode(0), TNode(1)]);
 }
 
+#[test]
+fn record_events() {
+  let graph = Arc::new(Graph::new());
+  let context = TContext::new(0, graph.clone());
+
+  // Create three nodes, each of which runs once.
+  assert_eq!(
+    graph.create(TNode(2), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+  );
+  assert_eq!(
+    context.events(),
+    vec![
+      (TNode(0), "created"),
+      (TNode(0), "ran"),
+      (TNode(1), "created"),
+      (TNode(1), "ran"),
+      (TNode(2), "created"),
+      (TNode(2), "ran"),
+    ]
+  );
+  context.events.lock().clear();
+
+  // Clear the middle Node: it re-runs, and the upper node is cleaned.
+  graph.invalidate_from_roots(|&TNode(n)| n == 1);
+  assert_eq!(
+    graph.create(TNode(2), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+  );
+  assert_eq!(
+    context.events(),
+    vec![(TNode(1), "reran"), (TNode(2), "cleaned")]
+  );
+}
+
+#[test]
+fn evict_unreachable() {
+  let graph = Arc::new(Graph::new());
+  let context = TContext::new(0, graph.clone());
+
+  // Create five nodes, of which only three are reachable from the live root.
+  assert_eq!(
+    graph.create(TNode(4), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0), T(3, 0), T(4, 0)])
+  );
+  let roots = vec![TNode(2)];
+
+  // Within budget: nothing is evicted.
+  let budget = EvictionBudget {
+    max_nodes: Some(5),
+    max_estimated_bytes: None,
+  };
+  assert_eq!(
+    graph.evict(&roots, &budget),
+    EvictionResult {
+      evicted: 0,
+      remaining: 5
+    }
+  );
+
+  // Over budget: the unreachable nodes are evicted, but the reachable ones are kept even though
+  // that leaves the graph over budget.
+  let budget = EvictionBudget {
+    max_nodes: Some(1),
+    max_estimated_bytes: None,
+  };
+  assert_eq!(
+    graph.evict(&roots, &budget),
+    EvictionResult {
+      evicted: 2,
+      remaining: 3
+    }
+  );
+  assert_eq!(graph.len(), 3);
+  assert_eq!(graph.evicted_count(), 2);
+  let mut remaining = Vec::new();
+  graph.visit_nodes(|&TNode(n)| remaining.push(n));
+  remaining.sort();
+  assert_eq!(remaining, vec![0, 1, 2]);
+
+  // Invalidation still reaches the remaining nodes, and the evicted nodes re-run when requested.
+  assert_eq!(
+    graph.invalidate_from_roots(|&TNode(n)| n == 0),
+    InvalidationResult {
+      cleared: 1,
+      dirtied: 2
+    }
+  );
+  assert_eq!(
+    graph.create(TNode(4), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0), T(3, 0), T(4, 0)])
+  );
+  assert_eq!(
+    context.runs(),
+    vec![
+      TNode(4),
+      TNode(3),
+      TNode(2),
+      TNode(1),
+      TNode(0),
+      TNode(4),
+      TNode(3),
+      TNode(0)
+    ]
+  );
+}
+
+#[test]
+fn evict_least_recently_used_first() {
+  let graph = Arc::new(Graph::new());
+  let context = TContext::new_with_dependencies(
+    0,
+    vec![(TNode(10), None)].into_iter().collect(),
+    graph.clone(),
+  );
+
+  // Create two independent chains of nodes, and then use the first one again.
+  let first = TNode(1);
+  let second = TNode(11);
+  graph.create(first.clone(), &context).wait().unwrap();
+  graph.create(TNode(10), &context).wait().unwrap();
+  graph.create(second.clone(), &context).wait().unwrap();
+  graph.create(first.clone(), &context).wait().unwrap();
+  let len = graph.len();
+
+  // With no live roots, evicting a single node evicts from the least recently used chain (even
+  // though the dependency of the first chain was not itself used again).
+  let budget = EvictionBudget {
+    max_nodes: Some(len - 1),
+    max_estimated_bytes: None,
+  };
+  let result = graph.evict(&[], &budget);
+  assert!(result.evicted > 0);
+  let inner = graph.inner.lock();
+  assert!(inner.entry_id(&first).is_some());
+  assert!(inner.entry_id(&second).is_none());
+}
+
+#[test]
+fn invalidation_chains() {
+  let graph = Arc::new(Graph::new());
+  let context = TContext::new(0, graph.clone());
+  let chain = |states: Vec<InvalidationState>| {
+    states
+      .into_iter()
+      .enumerate()
+      .map(|(i, state)| InvalidatedNode {
+        node: TNode(i),
+        state,
+      })
+      .collect::<Vec<_>>()
+  };
+
+  // Create three nodes, which have never been invalidated.
+  assert_eq!(
+    graph.create(TNode(2), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+  );
+  assert!(graph.invalidation_chains(&TNode(2)).is_empty());
+
+  // Clear the bottom node, which dirties the others.
+  graph.invalidate_from_roots(|&TNode(n)| n == 0);
+  assert_eq!(
+    graph.invalidation_chains(&TNode(2)),
+    vec![chain(vec![
+      InvalidationState::Cleared,
+      InvalidationState::Dirtied,
+      InvalidationState::Dirtied
+    ])]
+  );
+
+  // The bottom node re-runs with the same value, so the others are cleaned.
+  assert_eq!(
+    graph.create(TNode(2), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+  );
+  assert_eq!(
+    graph.invalidation_chains(&TNode(2)),
+    vec![chain(vec![
+      InvalidationState::Reran,
+      InvalidationState::Cleaned,
+      InvalidationState::Cleaned
+    ])]
+  );
+
+  // With a new context, the bottom node's value changes, so everything re-runs.
+  let context = TContext::new(1, graph.clone());
+  graph.invalidate_from_roots(|&TNode(n)| n == 0);
+  assert_eq!(
+    graph.create(TNode(2), &context).wait(),
+    Ok(vec![T(0, 1), T(1, 1), T(2, 1)])
+  );
+  assert_eq!(
+    graph.invalidation_chains(&TNode(2)),
+    vec![chain(vec![
+      InvalidationState::Reran,
+      InvalidationState::Reran,
+      InvalidationState::Reran
+    ])]
+  );
+}
+
+#[test]
+fn invalidations_forgotten_on_clear_and_evict() {
+  let graph = Arc::new(Graph::new());
+  let context = TContext::new(0, graph.clone());
+  let invalidations = || graph.inner.lock().invalidations.len();
+
+  // Invalidating records the cause for each of the three nodes, and clearing forgets them.
+  graph.create(TNode(2), &context).wait().unwrap();
+  graph.invalidate_from_roots(|&TNode(n)| n == 0);
+  assert_eq!(invalidations(), 3);
+  graph.clear();
+  assert_eq!(invalidations(), 0);
+  assert!(graph.invalidation_chains(&TNode(2)).is_empty());
+
+  // Evicting the nodes forgets their causes as well.
+  graph.create(TNode(2), &context).wait().unwrap();
+  graph.invalidate_from_roots(|&TNode(n)| n == 0);
+  assert_eq!(invalidations(), 3);
+  let budget = EvictionBudget {
+    max_nodes: Some(0),
+    max_estimated_bytes: None,
+  };
+  assert_eq!(
+    graph.evict(&[], &budget),
+    EvictionResult {
+      evicted: 3,
+      remaining: 0
+    }
+  );
+  assert_eq!(invalidations(), 0);
+}
+
+#[test]
+fn transitive_neighbors() {
+  let graph = Arc::new(Graph::new());
+  let context = TContext::new(0, graph.clone());
+  assert_eq!(
+    graph.create(TNode(3), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0), T(3, 0)])
+  );
+  let sorted = |mut neighbors: Vec<(TNode, usize)>| {
+    neighbors.sort_by_key(|&(TNode(n), _)| n);
+    neighbors
+  };
+
+  assert_eq!(
+    sorted(graph.transitive_dependencies(&[TNode(3)], None)),
+    vec![(TNode(0), 3), (TNode(1), 2), (TNode(2), 1)]
+  );
+  assert_eq!(
+    sorted(graph.transitive_dependencies(&[TNode(3)], Some(2))),
+    vec![(TNode(1), 2), (TNode(2), 1)]
+  );
+  assert_eq!(
+    sorted(graph.transitive_dependents(&[TNode(0)], Some(1))),
+    vec![(TNode(1), 1)]
+  );
+  // Depths are measured from the nearest root, and roots are excluded.
+  assert_eq!(
+    sorted(graph.transitive_dependents(&[TNode(0), TNode(2)], None)),
+    vec![(TNode(1), 1), (TNode(3), 1)]
+  );
+  assert!(graph.transitive_dependents(&[TNode(3)], None).is_empty());
+  // Roots which are not in the Graph are ignored.
+  assert_eq!(
+    sorted(graph.transitive_dependents(&[TNode(2), TNode(4)], None)),
+    vec![(TNode(3), 1)]
+  );
+}
+
+#[test]
+fn snapshot() {
+  let graph = Arc::new(Graph::new());
+  let context = TContext::new(0, graph.clone());
+  assert_eq!(
+    graph.create(TNode(3), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0), T(3, 0)])
+  );
+  let summarize = |snapshots: Vec<EntrySnapshot<TNode>>| {
+    let nodes: HashMap<_, _> = snapshots.iter().map(|s| (s.id, s.node.clone())).collect();
+    let mut summaries: Vec<_> = snapshots
+      .iter()
+      .map(|s| {
+        let deps: Vec<_> = s.dependencies.iter().map(|id| nodes[id].clone()).collect();
+        (s.node.clone(), s.status, deps)
+      })
+      .collect();
+    summaries.sort_by_key(|(TNode(n), _, _)| *n);
+    summaries
+  };
+
+  // All nodes reachable from the root.
+  let all = graph.snapshot(&[TNode(3)], |_| true);
+  assert!(all
+    .iter()
+    .all(|s| s.generation == 1 && s.duration.is_some()));
+  assert_eq!(
+    summarize(all),
+    vec![
+      (TNode(0), EntryStatus::Completed, vec![]),
+      (TNode(1), EntryStatus::Completed, vec![TNode(0)]),
+      (TNode(2), EntryStatus::Completed, vec![TNode(1)]),
+      (TNode(3), EntryStatus::Completed, vec![TNode(2)]),
+    ]
+  );
+
+  // A subgraph, filtered to exclude a node: its dependent is connected to its dependency instead.
+  graph.invalidate_from_roots(|&TNode(n)| n == 0);
+  assert_eq!(
+    summarize(graph.snapshot(&[TNode(2)], |s| s.node != TNode(1))),
+    vec![
+      (TNode(0), EntryStatus::NotStarted, vec![]),
+      (TNode(2), EntryStatus::Dirty, vec![TNode(0)]),
+    ]
+  );
+}
+
 #[test]
 fn invalidate_and_rerun() {
   let graph = Arc::new(Graph::new());
@@ -194,6 +568,84 @@ fn invalidate_randomly() {
   );
 }
 
+#[test]
+fn cancellation_is_not_memoized() {
+  let graph = Arc::new(Graph::new());
+
+  // A cancelled Node is not persisted, and its waiters experience invalidation.
+  let context =
+    TContext::new_with_cancellations(0, vec![TNode(1)].into_iter().collect(), graph.clone());
+  assert_eq!(
+    graph.create(TNode(2), &context).wait(),
+    Err(TError::Invalidated)
+  );
+  assert_eq!(context.runs(), vec![TNode(2), TNode(1)]);
+
+  // A subsequent request re-runs the cancelled Node and its dependents.
+  let context = TContext::new(1, graph.clone());
+  assert_eq!(
+    graph.create(TNode(2), &context).wait(),
+    Ok(vec![T(0, 1), T(1, 1), T(2, 1)])
+  );
+  assert_eq!(context.runs(), vec![TNode(2), TNode(1), TNode(0)]);
+}
+
+#[test]
+fn abandoned_node_is_cancelled() {
+  let graph = Arc::new(Graph::new());
+  let delay = Duration::from_millis(200);
+  let context = TContext::new_with_delays(
+    0,
+    vec![(TNode(2), delay)].into_iter().collect(),
+    graph.clone(),
+  );
+
+  // Request a Node, and then drop the request before the Node can complete.
+  drop(graph.create(TNode(2), &context));
+  thread::sleep(delay * 2);
+
+  // The Node was cancelled rather than completing, and so a subsequent request re-runs it.
+  assert_eq!(
+    graph.create(TNode(2), &context).wait(),
+    Ok(vec![T(0, 0), T(1, 0), T(2, 0)])
+  );
+  assert_eq!(
+    context
+      .runs()
+      .into_iter()
+      .filter(|node| *node == TNode(2))
+      .count(),
+    2
+  );
+}
+
+#[test]
+fn shared_node_is_not_cancelled() {
+  let graph = Arc::new(Graph::new());
+  let delay = Duration::from_millis(200);
+  let context = TContext::new_with_delays(
+    0,
+    vec![(TNode(2), delay)].into_iter().collect(),
+    graph.clone(),
+  );
+
+  // Request a Node twice, and then drop one of the requests before the Node can complete.
+  let first = graph.create(TNode(2), &context);
+  let second = graph.create(TNode(2), &context);
+  drop(first);
+
+  // The Node keeps running for the remaining request, and so runs only once.
+  assert_eq!(second.wait(), Ok(vec![T(0, 0), T(1, 0), T(2, 0)]));
+  assert_eq!(
+    context
+      .runs()
+      .into_iter()
+      .filter(|node| *node == TNode(2))
+      .count(),
+    1
+  );
+}
+
 #[test]
 fn drain_and_resume() {
   // Confirms that after draining a Graph that has running work, we are able to resume the work
@@ -253,6 +705,53 @@ fn cyclic_failure() {
   assert_eq!(graph.create(TNode(2), &context).wait(), Err(TError::Cyclic));
 }
 
+#[test]
+fn cyclic_failure_reports_all_cycles() {
+  // Confirms that all of the cycles that an edge would create are found, shortest first.
+  let graph: Graph<TNode> = Graph::new();
+  let mut inner = graph.inner.lock();
+  let ids: Vec<_> = (0..4).map(|n| inner.ensure_entry(TNode(n)).0).collect();
+  for &(src, dst) in &[(3, 2), (2, 1), (1, 0), (3, 1), (2, 0)] {
+    inner.pg.add_edge(ids[src], ids[dst], 1.0);
+  }
+
+  let cycles: Vec<Vec<usize>> = inner
+    .find_cycles(ids[0], ids[3], 10, 100)
+    .into_iter()
+    .map(|cycle| cycle.into_iter().map(|entry| entry.node().0).collect())
+    .collect();
+  assert_eq!(cycles.len(), 3);
+  assert!(cycles.contains(&vec![3, 1, 0, 3]));
+  assert!(cycles.contains(&vec![3, 2, 0, 3]));
+  assert_eq!(cycles[2], vec![3, 2, 1, 0, 3]);
+
+  // The number of cycles reported is limited.
+  assert_eq!(inner.find_cycles(ids[0], ids[3], 2, 100).len(), 2);
+}
+
+#[test]
+fn cyclic_failure_search_is_bounded() {
+  // Confirms that the search for cycles in a dense graph (in which there are 2^38 paths from the
+  // top to the bottom) stops after the given number of steps, whether or not it has found as many
+  // cycles as were requested.
+  let graph: Graph<TNode> = Graph::new();
+  let mut inner = graph.inner.lock();
+  let ids: Vec<_> = (0..40).map(|n| inner.ensure_entry(TNode(n)).0).collect();
+  for src in 1..40 {
+    for dst in 0..src {
+      inner.pg.add_edge(ids[src], ids[dst], 1.0);
+    }
+  }
+
+  let cycles = inner.find_cycles(ids[0], ids[39], usize::max_value(), 1000);
+  assert!(!cycles.is_empty());
+  assert!(cycles.len() <= 1000);
+  for cycle in cycles {
+    assert_eq!(cycle.first().unwrap().node(), &TNode(39));
+    assert_eq!(cycle.last().unwrap().node(), &TNode(39));
+  }
+}
+
 #[test]
 fn cyclic_dirtying() {
   // Confirms that a dirtied path between two nodes is able to reverse direction while being
@@ -407,6 +906,9 @@ impl Node for TNode {
 
   fn run(self, context: TContext) -> BoxFuture<Vec<T>, TError> {
     context.ran(self.clone());
+    if context.cancels(&self) {
+      return future::err(TError::Cancelled).to_boxed();
+    }
     let token = T(self.0, context.id());
     if let Some(dep) = context.dependency_of(&self) {
       context.maybe_delay(&self);
@@ -496,8 +998,11 @@ struct TContext {
   // dependencies.
   edges: Arc<HashMap<TNode, Option<TNode>>>,
   delays: HashMap<TNode, Duration>,
+  // Nodes which will fail as if they had been cancelled when run.
+  cancellations: Arc<HashSet<TNode>>,
   graph: Arc<Graph<TNode>>,
   runs: Arc<Mutex<Vec<TNode>>>,
+  events: Arc<Mutex<Vec<(TNode, NodeEvent)>>>,
   entry_id: Option<EntryId>,
 }
 impl NodeContext for TContext {
@@ -507,8 +1012,10 @@ impl NodeContext for TContext {
       id: self.id,
       edges: self.edges.clone(),
       delays: self.delays.clone(),
+      cancellations: self.cancellations.clone(),
       graph: self.graph.clone(),
       runs: self.runs.clone(),
+      events: self.events.clone(),
       entry_id: Some(entry_id),
     }
   }
@@ -526,6 +1033,10 @@ impl NodeContext for TContext {
       future.wait().unwrap();
     });
   }
+
+  fn record_event(&self, node: &TNode, event: NodeEvent) {
+    self.events.lock().push((node.clone(), event));
+  }
 }
 
 impl TContext {
@@ -534,8 +1045,10 @@ impl TContext {
       id,
       edges: Arc::default(),
       delays: HashMap::default(),
+      cancellations: Arc::default(),
       graph,
       runs: Arc::new(Mutex::new(Vec::new())),
+      events: Arc::new(Mutex::new(Vec::new())),
       entry_id: None,
     }
   }
@@ -549,8 +1062,10 @@ impl TContext {
       id,
       edges: Arc::new(edges),
       delays: HashMap::default(),
+      cancellations: Arc::default(),
       graph,
       runs: Arc::new(Mutex::new(Vec::new())),
+      events: Arc::new(Mutex::new(Vec::new())),
       entry_id: None,
     }
   }
@@ -564,8 +1079,27 @@ impl TContext {
       id,
       edges: Arc::default(),
       delays,
+      cancellations: Arc::default(),
       graph,
       runs: Arc::new(Mutex::new(Vec::new())),
+      events: Arc::new(Mutex::new(Vec::new())),
+      entry_id: None,
+    }
+  }
+
+  fn new_with_cancellations(
+    id: usize,
+    cancellations: HashSet<TNode>,
+    graph: Arc<Graph<TNode>>,
+  ) -> TContext {
+    TContext {
+      id,
+      edges: Arc::default(),
+      delays: HashMap::default(),
+      cancellations: Arc::new(cancellations),
+      graph,
+      runs: Arc::new(Mutex::new(Vec::new())),
+      events: Arc::new(Mutex::new(Vec::new())),
       entry_id: None,
     }
   }
@@ -574,6 +1108,10 @@ impl TContext {
     self.id
   }
 
+  fn cancels(&self, node: &TNode) -> bool {
+    self.cancellations.contains(node)
+  }
+
   fn get(&self, dst: TNode) -> BoxFuture<Vec<T>, TError> {
     self.graph.get(self.entry_id.unwrap(), self, dst)
   }
@@ -604,10 +1142,34 @@ impl TContext {
   fn runs(&self) -> Vec<TNode> {
     self.runs.lock().clone()
   }
+
+  ///
+  /// Returns the recorded events without their durations, sorted by Node, and then in the order in
+  /// which they were recorded.
+  ///
+  fn events(&self) -> Vec<(TNode, &'static str)> {
+    let mut events = self
+      .events
+      .lock()
+      .iter()
+      .map(|(node, event)| {
+        let event = match event {
+          NodeEvent::Created => "created",
+          NodeEvent::Cleaned => "cleaned",
+          NodeEvent::Ran { reran: false, .. } => "ran",
+          NodeEvent::Ran { reran: true, .. } => "reran",
+        };
+        (node.clone(), event)
+      })
+      .collect::<Vec<_>>();
+    events.sort_by_key(|(node, _)| node.0);
+    events
+  }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum TError {
+  Cancelled,
   Cyclic,
   Invalidated,
 }
@@ -616,7 +1178,11 @@ impl NodeError for TError {
     TError::Invalidated
   }
 
-  fn cyclic(_path: Vec<String>) -> Self {
+  fn cancelled() -> Self {
+    TError::Cancelled
+  }
+
+  fn cyclic(_paths: Vec<Vec<String>>) -> Self {
     TError::Cyclic
   }
 }
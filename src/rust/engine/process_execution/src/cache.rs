@@ -1,18 +1,70 @@
 use crate::{
-  Context, ExecuteProcessRequest, ExecuteProcessRequestMetadata, FallibleExecuteProcessResult,
-  MultiPlatformExecuteProcessRequest,
+  CachePolicy, Context, ExecuteProcessRequest, ExecuteProcessRequestMetadata, ExecutionStats,
+  FallibleExecuteProcessResult, MultiPlatformExecuteProcessRequest,
 };
+use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use bytes::Bytes;
 use futures::Future;
 use log::{debug, warn};
 use protobuf::Message;
 
-use boxfuture::{BoxFuture, Boxable};
-use hashing::Fingerprint;
+use boxfuture::{try_future, BoxFuture, Boxable};
+use concrete_time::TimeSpan;
+use hashing::{Digest, Fingerprint};
 use sharded_lmdb::ShardedLmdb;
-use store::Store;
+use store::{ShrinkBehavior, Store};
+use workunit_store::{get_parent_id, WorkUnit, WorkUnitStore};
+
+const MEGABYTES: usize = 1024 * 1024;
+const GIGABYTES: usize = 1024 * MEGABYTES;
+
+// The maximum size of the local process execution cache. The cache may grow up to this size
+// between garbage collections.
+pub const MAX_SIZE_BYTES: usize = 5 * GIGABYTES;
+
+// The size that the local process execution cache is shrunk to by default when garbage collected.
+// Entries are small (they reference their outputs in the file Store), so this is comparatively
+// modest.
+pub const DEFAULT_GC_TARGET_BYTES: usize = 512 * MEGABYTES;
+
+///
+/// Opens the local process execution cache, which lives alongside the file Store in the given
+/// local store directory.
+///
+pub fn open_store(
+  local_store_dir: &Path,
+  executor: task_executor::Executor,
+) -> Result<ShardedLmdb, String> {
+  ShardedLmdb::new(local_store_dir.join("processes"), MAX_SIZE_BYTES, executor)
+}
+
+///
+/// Shrinks the given local process execution cache to target_size_bytes by removing the entries
+/// whose leases expired longest ago. Entries which are still leased are never removed.
+///
+pub fn garbage_collect(
+  process_execution_store: &ShardedLmdb,
+  target_size_bytes: usize,
+  shrink_behavior: ShrinkBehavior,
+) -> Result<(), String> {
+  let size = process_execution_store
+    .shrink(target_size_bytes)
+    .map_err(|err| format!("Process cache garbage collection failed: {:?}", err))?;
+  if shrink_behavior == ShrinkBehavior::Compact {
+    process_execution_store.compact()?;
+  }
+  if size > target_size_bytes {
+    Err(format!(
+      "Process cache garbage collection attempted to target {} bytes but could only shrink to {} bytes",
+      target_size_bytes, size
+    ))
+  } else {
+    Ok(())
+  }
+}
 
 #[derive(Clone)]
 pub struct CommandRunner {
@@ -20,6 +72,8 @@ pub struct CommandRunner {
   pub process_execution_store: ShardedLmdb,
   pub file_store: Store,
   pub metadata: ExecuteProcessRequestMetadata,
+  // How long a failed result is reused for, for requests which opt in to caching failures.
+  pub failure_ttl: Duration,
 }
 
 impl crate::CommandRunner for CommandRunner {
@@ -38,10 +92,47 @@ impl crate::CommandRunner for CommandRunner {
   ) -> BoxFuture<FallibleExecuteProcessResult, String> {
     let digest = crate::digest(req.clone(), &self.metadata);
     let key = digest.0;
+    let failure_key = Self::failure_key(key);
+    let (cache_failures, cache_policy, description) = match self.extract_compatible_request(&req) {
+      Some(compatible_req) => (
+        compatible_req.cache_failures,
+        compatible_req.cache_policy,
+        compatible_req.description,
+      ),
+      None => (false, CachePolicy::default(), String::new()),
+    };
+
+    let lookup = if cache_policy.skip_cache_lookup {
+      futures::future::ok(None).to_boxed()
+    } else if cache_failures {
+      let command_runner = self.clone();
+      let context = context.clone();
+      let start_time = SystemTime::now();
+      self
+        .lookup(key, false, context.clone())
+        .and_then(move |maybe_result| match maybe_result {
+          Some(result) => futures::future::ok(Some(result)).to_boxed(),
+          None => command_runner
+            .lookup(failure_key, true, context.clone())
+            .map(move |maybe_result| {
+              if maybe_result.is_some() {
+                context.workunit_store.add_workunit(WorkUnit::new(
+                  format!("{} (cached failure)", description),
+                  TimeSpan::since(&start_time),
+                  get_parent_id(),
+                ));
+              }
+              maybe_result
+            })
+            .to_boxed(),
+        })
+        .to_boxed()
+    } else {
+      self.lookup(key, false, context.clone()).to_boxed()
+    };
 
     let command_runner = self.clone();
-    self
-      .lookup(key, context.clone())
+    lookup
       .then(move |maybe_result| {
         match maybe_result {
           Ok(Some(result)) => return futures::future::ok(result).to_boxed(),
@@ -53,22 +144,27 @@ impl crate::CommandRunner for CommandRunner {
             // Falling through to execute.
           },
         }
+        let workunit_store = context.workunit_store.clone();
         command_runner
           .underlying
           .run(req, context)
           .and_then(move |result| {
-            if result.exit_code == 0 {
-              command_runner
-                .store(key, &result)
-                .then(|store_result| {
-                  if let Err(err) = store_result {
-                    debug!("Error storing process execution result to local cache: {} - ignoring and continuing", err);
-                  }
-                  Ok(result)
-                }).to_boxed()
-            } else {
-              futures::future::ok(result).to_boxed()
+            if cache_policy.do_not_cache || (result.exit_code != 0 && !cache_failures) {
+              return futures::future::ok(result).to_boxed();
             }
+            let store_key = if result.exit_code == 0 {
+              key
+            } else {
+              failure_key
+            };
+            command_runner
+              .store(store_key, result.exit_code != 0, &result, workunit_store)
+              .then(|store_result| {
+                if let Err(err) = store_result {
+                  debug!("Error storing process execution result to local cache: {} - ignoring and continuing", err);
+                }
+                Ok(result)
+              }).to_boxed()
           })
           .to_boxed()
 
@@ -78,44 +174,136 @@ impl crate::CommandRunner for CommandRunner {
 }
 
 impl CommandRunner {
+  ///
+  /// Failed results are stored under a different key than successful results, so that they can
+  /// be looked up (and expire) independently.
+  ///
+  fn failure_key(fingerprint: Fingerprint) -> Fingerprint {
+    let mut bytes = b"failure:".to_vec();
+    bytes.extend_from_slice(fingerprint.as_ref());
+    Digest::of_bytes(&bytes).0
+  }
+
+  ///
+  /// Looks up a cached result. Successful results have their leases extended on every hit, while
+  /// failed results are only used until their initial lease (the failure TTL) expires.
+  ///
   fn lookup(
     &self,
     fingerprint: Fingerprint,
+    is_failure: bool,
     context: Context,
-  ) -> impl Future<Item = Option<FallibleExecuteProcessResult>, Error = String> {
+  ) -> BoxFuture<Option<FallibleExecuteProcessResult>, String> {
     let file_store = self.file_store.clone();
-    self
-      .process_execution_store
-      .load_bytes_with(fingerprint, |bytes| {
-        let mut execute_response = bazel_protos::remote_execution::ExecuteResponse::new();
-        execute_response
-          .merge_from_bytes(&bytes)
-          .map_err(|e| format!("Invalid ExecuteResponse: {:?}", e))?;
-        Ok(execute_response)
-      })
+    let process_execution_store = self.process_execution_store.clone();
+    let decode = |bytes: Bytes| -> Result<_, String> {
+      let mut execute_response = bazel_protos::remote_execution::ExecuteResponse::new();
+      execute_response
+        .merge_from_bytes(&bytes)
+        .map_err(|e| format!("Invalid ExecuteResponse: {:?}", e))?;
+      Ok(execute_response)
+    };
+    let maybe_execute_response = if is_failure {
+      self
+        .process_execution_store
+        .load_leased_bytes_with(fingerprint, decode)
+        .to_boxed()
+    } else {
+      self
+        .process_execution_store
+        .load_bytes_with(fingerprint, decode)
+        .to_boxed()
+    };
+    maybe_execute_response
       .and_then(move |maybe_execute_response| {
         if let Some(execute_response) = maybe_execute_response {
-          crate::remote::populate_fallible_execution_result(
-            file_store,
-            execute_response,
-            vec![],
-            context.workunit_store,
-          )
-          .map(Some)
-          .to_boxed()
+          // Only results which were produced after retrying are stored with a message: see `store`.
+          let was_retried = !execute_response.get_message().is_empty();
+          if was_retried {
+            debug!(
+              "Using local process cache entry {}: {}",
+              fingerprint,
+              execute_response.get_message()
+            );
+          }
+          // Extend the leases on the entry and on everything that it references, so that a hit
+          // never refers to outputs which have been garbage collected from the file Store. If
+          // the outputs are already gone, the entry is treated as a miss.
+          let output_digests = try_future!(Self::output_digests(&execute_response));
+          let lease_entry = if is_failure {
+            futures::future::ok(()).to_boxed()
+          } else {
+            process_execution_store.lease(fingerprint).to_boxed()
+          };
+          lease_entry
+            .join(file_store.lease_all_recursively(output_digests, context.workunit_store.clone()))
+            .then(move |lease_result| match lease_result {
+              Ok(_) => crate::remote::populate_fallible_execution_result(
+                file_store,
+                execute_response,
+                vec![ExecutionStats::local_cache_hit(was_retried)],
+                context.workunit_store,
+              )
+              .map(Some)
+              .to_boxed(),
+              Err(err) => {
+                debug!(
+                  "Outputs of local process cache entry {} were not available: {} - treating as a miss",
+                  fingerprint, err
+                );
+                futures::future::ok(None).to_boxed()
+              }
+            })
+            .to_boxed()
         } else {
           futures::future::ok(None).to_boxed()
         }
       })
+      .to_boxed()
+  }
+
+  fn output_digests(
+    execute_response: &bazel_protos::remote_execution::ExecuteResponse,
+  ) -> Result<Vec<Digest>, String> {
+    let action_result = execute_response.get_result();
+    let mut digests = vec![
+      action_result.get_stdout_digest(),
+      action_result.get_stderr_digest(),
+    ];
+    digests.extend(
+      action_result
+        .get_output_directories()
+        .iter()
+        .map(|directory| directory.get_tree_digest()),
+    );
+    digests
+      .into_iter()
+      .map(Result::<Digest, String>::from)
+      .collect()
   }
 
   fn store(
     &self,
     fingerprint: Fingerprint,
+    is_failure: bool,
     result: &FallibleExecuteProcessResult,
+    workunit_store: WorkUnitStore,
   ) -> impl Future<Item = (), Error = String> {
     let mut execute_response = bazel_protos::remote_execution::ExecuteResponse::new();
     execute_response.set_cached_result(true);
+    let retried_attempts = result
+      .execution_attempts
+      .iter()
+      .filter(|attempt| attempt.retried_exit_code().is_some())
+      .count();
+    if retried_attempts > 0 {
+      // Mark results which were only produced after retrying, since they may have been affected by
+      // whatever flakiness caused the retries: hits on them are marked `was_retried`.
+      execute_response.set_message(format!(
+        "Produced after {} retried attempt(s).",
+        retried_attempts
+      ));
+    }
     let action_result = execute_response.mut_result();
     action_result.set_exit_code(result.exit_code);
     action_result.mut_output_directories().push({
@@ -125,9 +313,8 @@ impl CommandRunner {
       directory
     });
     let process_execution_store = self.process_execution_store.clone();
+    let failure_ttl = self.failure_ttl;
     // TODO: Should probably have a configurable lease time which is larger than default.
-    // (This isn't super urgent because we don't ever actually GC this store. So also...)
-    // TODO: GC the local process execution cache.
     self
       .file_store
       .store_file_bytes(result.stdout.clone(), true)
@@ -136,7 +323,12 @@ impl CommandRunner {
           .file_store
           .store_file_bytes(result.stderr.clone(), true),
       )
-      .and_then(move |(stdout_digest, stderr_digest)| {
+      .join(
+        self
+          .file_store
+          .lease_all_recursively(vec![result.output_directory], workunit_store),
+      )
+      .and_then(move |((stdout_digest, stderr_digest), ())| {
         let action_result = execute_response.mut_result();
         action_result.set_stdout_digest((&stdout_digest).into());
         action_result.set_stderr_digest((&stderr_digest).into());
@@ -145,6 +337,16 @@ impl CommandRunner {
           .map(Bytes::from)
           .map_err(|err| format!("Error serializing execute process result to cache: {}", err))
       })
-      .and_then(move |bytes| process_execution_store.store_bytes(fingerprint, bytes, false))
+      .and_then(move |bytes| {
+        if is_failure {
+          process_execution_store
+            .store_bytes_with_lease(fingerprint, bytes, failure_ttl)
+            .to_boxed()
+        } else {
+          process_execution_store
+            .store_bytes(fingerprint, bytes, true)
+            .to_boxed()
+        }
+      })
   }
 }
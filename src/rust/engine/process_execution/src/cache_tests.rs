@@ -1,15 +1,16 @@
 use crate::{
-  CommandRunner as CommandRunnerTrait, Context, ExecuteProcessRequest,
-  ExecuteProcessRequestMetadata, FallibleExecuteProcessResult, Platform,
+  CachePolicy, CommandRunner as CommandRunnerTrait, Context, ExecuteProcessRequest,
+  ExecuteProcessRequestMetadata, FallibleExecuteProcessResult, Platform, ProcessResultSource,
+  RetryPolicy,
 };
 use hashing::EMPTY_DIGEST;
 use sharded_lmdb::ShardedLmdb;
 use std::collections::{BTreeMap, BTreeSet};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use store::Store;
+use store::{ShrinkBehavior, Store};
 use tempfile::TempDir;
 use testutil::data::TestData;
 
@@ -18,7 +19,12 @@ struct RoundtripResults {
   maybe_cached: Result<FallibleExecuteProcessResult, String>,
 }
 
-fn run_roundtrip(script_exit_code: i8) -> RoundtripResults {
+fn run_roundtrip(
+  script_exit_code: i8,
+  cache_failures: bool,
+  failure_ttl: Duration,
+  cache_policy: CachePolicy,
+) -> RoundtripResults {
   let runtime = task_executor::Executor::new();
   let work_dir = TempDir::new().unwrap();
   let store_dir = TempDir::new().unwrap();
@@ -60,26 +66,22 @@ fn run_roundtrip(script_exit_code: i8) -> RoundtripResults {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy,
   };
 
   let local_result = runtime.block_on(local.run(request.clone().into(), Context::default()));
 
   let cache_dir = TempDir::new().unwrap();
-  let caching = crate::cache::CommandRunner {
-    underlying: Arc::new(local),
-    file_store: store.clone(),
-    process_execution_store: ShardedLmdb::new(
-      cache_dir.path().to_owned(),
-      50 * 1024 * 1024,
-      runtime.clone(),
-    )
-    .unwrap(),
-    metadata: ExecuteProcessRequestMetadata {
-      instance_name: None,
-      cache_key_gen_version: None,
-      platform_properties: vec![],
-    },
-  };
+  let caching = caching_runner(
+    Arc::new(local),
+    store,
+    runtime.clone(),
+    cache_dir.path(),
+    failure_ttl,
+  );
 
   let uncached_result = runtime.block_on(caching.run(request.clone().into(), Context::default()));
 
@@ -97,16 +99,204 @@ fn run_roundtrip(script_exit_code: i8) -> RoundtripResults {
   }
 }
 
+fn caching_runner(
+  underlying: Arc<dyn CommandRunnerTrait>,
+  store: Store,
+  runtime: task_executor::Executor,
+  cache_dir: &Path,
+  failure_ttl: Duration,
+) -> crate::cache::CommandRunner {
+  crate::cache::CommandRunner {
+    underlying,
+    file_store: store,
+    process_execution_store: ShardedLmdb::new(cache_dir.to_owned(), 50 * 1024 * 1024, runtime)
+      .unwrap(),
+    metadata: ExecuteProcessRequestMetadata {
+      instance_name: None,
+      cache_key_gen_version: None,
+      platform_properties: vec![],
+    },
+    failure_ttl,
+  }
+}
+
+fn assert_cache_hit(results: RoundtripResults) {
+  let uncached = results.uncached.unwrap();
+  let cached = results.maybe_cached.unwrap();
+  assert_eq!(uncached.source(), ProcessResultSource::Executed);
+  assert_eq!(cached.source(), ProcessResultSource::LocalCache);
+  assert_eq!(uncached, cached.without_execution_attempts());
+}
+
 #[test]
 fn cache_success() {
-  let results = run_roundtrip(0);
-  assert_eq!(results.uncached, results.maybe_cached);
+  let results = run_roundtrip(0, false, Duration::from_secs(60), CachePolicy::default());
+  assert_cache_hit(results);
 }
 
 #[test]
 fn failures_not_cached() {
-  let results = run_roundtrip(1);
+  let results = run_roundtrip(1, false, Duration::from_secs(60), CachePolicy::default());
   assert_ne!(results.uncached, results.maybe_cached);
   assert_eq!(results.uncached.unwrap().exit_code, 1);
   assert_eq!(results.maybe_cached.unwrap().exit_code, 127); // aka the return code for file not found
 }
+
+#[test]
+fn failures_cached_when_requested() {
+  let results = run_roundtrip(1, true, Duration::from_secs(60), CachePolicy::default());
+  assert_eq!(results.maybe_cached.as_ref().unwrap().exit_code, 1);
+  assert_cache_hit(results);
+}
+
+#[test]
+fn cached_failures_expire() {
+  let results = run_roundtrip(1, true, Duration::from_secs(0), CachePolicy::default());
+  assert_ne!(results.uncached, results.maybe_cached);
+  assert_eq!(results.maybe_cached.unwrap().exit_code, 127);
+}
+
+#[test]
+fn skip_cache_lookup() {
+  let results = run_roundtrip(
+    0,
+    false,
+    Duration::from_secs(60),
+    CachePolicy {
+      skip_cache_lookup: true,
+      do_not_cache: false,
+    },
+  );
+  assert_ne!(results.uncached, results.maybe_cached);
+  assert_eq!(results.maybe_cached.unwrap().exit_code, 127);
+}
+
+#[test]
+fn do_not_cache() {
+  let results = run_roundtrip(
+    0,
+    false,
+    Duration::from_secs(60),
+    CachePolicy {
+      skip_cache_lookup: false,
+      do_not_cache: true,
+    },
+  );
+  assert_ne!(results.uncached, results.maybe_cached);
+  assert_eq!(results.maybe_cached.unwrap().exit_code, 127);
+}
+
+#[test]
+fn cache_hit_on_retried_result_is_marked() {
+  let runtime = task_executor::Executor::new();
+  let work_dir = TempDir::new().unwrap();
+  let store_dir = TempDir::new().unwrap();
+  let store = Store::local_only(runtime.clone(), store_dir.path()).unwrap();
+  let local = crate::local::CommandRunner::new(
+    store.clone(),
+    runtime.clone(),
+    work_dir.path().to_owned(),
+    true,
+  );
+
+  // A script which fails the first time that it runs, and succeeds thereafter.
+  let script_dir = TempDir::new().unwrap();
+  let script_path = script_dir.path().join("script");
+  let marker_path = script_dir.path().join("marker");
+  std::fs::File::create(&script_path)
+    .and_then(|mut file| {
+      writeln!(
+        file,
+        "if [ ! -e {} ]; then touch {}; exit 1; fi; echo Hello",
+        marker_path.display(),
+        marker_path.display()
+      )
+    })
+    .unwrap();
+
+  let request = ExecuteProcessRequest {
+    argv: vec![
+      testutil::path::find_bash(),
+      format!("{}", script_path.display()),
+    ],
+    env: BTreeMap::new(),
+    working_directory: None,
+    input_files: EMPTY_DIGEST,
+    output_files: BTreeSet::new(),
+    output_directories: BTreeSet::new(),
+    timeout: Duration::from_millis(1000),
+    description: "bash".to_string(),
+    unsafe_local_only_files_because_we_favor_speed_over_correctness_for_this_rule:
+      hashing::EMPTY_DIGEST,
+    jdk_home: None,
+    target_platform: Platform::None,
+    is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: Some(RetryPolicy {
+      max_attempts: 2,
+      retryable_exit_codes: vec![1].into_iter().collect(),
+    }),
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
+  };
+
+  let cache_dir = TempDir::new().unwrap();
+  let caching = caching_runner(
+    Arc::new(crate::retry::CommandRunner::new(Box::new(local))),
+    store,
+    runtime.clone(),
+    cache_dir.path(),
+    Duration::from_secs(60),
+  );
+
+  let uncached = runtime
+    .block_on(caching.run(request.clone().into(), Context::default()))
+    .unwrap();
+  assert_eq!(uncached.exit_code, 0);
+  assert!(uncached
+    .execution_attempts
+    .iter()
+    .any(|attempt| attempt.retried_exit_code() == Some(1)));
+
+  std::fs::remove_file(&script_path).unwrap();
+  let cached = runtime
+    .block_on(caching.run(request.into(), Context::default()))
+    .unwrap();
+  assert_eq!(cached.source(), ProcessResultSource::LocalCache);
+  assert_eq!(
+    cached
+      .execution_attempts
+      .iter()
+      .map(|attempt| attempt.was_retried())
+      .collect::<Vec<_>>(),
+    vec![true]
+  );
+}
+
+#[test]
+fn garbage_collect_removes_only_expired_entries() {
+  let runtime = task_executor::Executor::new();
+  let cache_dir = TempDir::new().unwrap();
+  let process_execution_store =
+    crate::cache::open_store(cache_dir.path(), runtime.clone()).unwrap();
+
+  let leased = TestData::roland();
+  let unleased = TestData::catnip();
+  runtime
+    .block_on(process_execution_store.store_bytes(leased.fingerprint(), leased.bytes(), true))
+    .unwrap();
+  runtime
+    .block_on(process_execution_store.store_bytes(unleased.fingerprint(), unleased.bytes(), false))
+    .unwrap();
+
+  crate::cache::garbage_collect(&process_execution_store, 0, ShrinkBehavior::Fast)
+    .expect_err("Should not have been able to collect a leased entry");
+
+  let load = |fingerprint| {
+    runtime
+      .block_on(process_execution_store.load_bytes_with(fingerprint, Ok))
+      .unwrap()
+  };
+  assert_eq!(load(leased.fingerprint()), Some(leased.bytes()));
+  assert_eq!(load(unleased.fingerprint()), None);
+}
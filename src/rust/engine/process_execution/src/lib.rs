@@ -61,6 +61,14 @@ mod speculate_tests;
 
 pub mod nailgun;
 
+pub mod retry;
+#[cfg(test)]
+mod retry_tests;
+
+pub mod routing;
+#[cfg(test)]
+mod routing_tests;
+
 extern crate uname;
 
 #[derive(PartialOrd, Ord, Clone, Copy, Debug, Eq, PartialEq, Hash)]
@@ -211,6 +219,78 @@ pub struct ExecuteProcessRequest {
   pub target_platform: Platform,
 
   pub is_nailgunnable: bool,
+
+  ///
+  /// If true, a result with a non-zero exit code may be cached by the local process cache, for a
+  /// limited time. By default, only successful results are cached.
+  ///
+  pub cache_failures: bool,
+
+  ///
+  /// If present, a failed attempt to run the process whose exit code is considered retryable by
+  /// the policy will be retried (see `retry::CommandRunner`).
+  ///
+  pub retry_policy: Option<RetryPolicy>,
+
+  ///
+  /// The priority with which a remote execution server should schedule the process, relative to
+  /// other processes (see `ExecutionPolicy.priority` in the remote execution API). If None, the
+  /// server's default priority is used.
+  ///
+  #[derivative(PartialEq = "ignore", Hash = "ignore")]
+  pub execution_priority: Option<i32>,
+
+  pub cache_policy: CachePolicy,
+}
+
+///
+/// Describes how the result of a process should interact with (local and remote) caches.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CachePolicy {
+  ///
+  /// If true, any cached result for the process is ignored, and the process is run again.
+  ///
+  pub skip_cache_lookup: bool,
+  ///
+  /// If true, the result of the process is not cached.
+  ///
+  pub do_not_cache: bool,
+}
+
+impl CachePolicy {
+  ///
+  /// Combines two policies, such that a cache is skipped if either policy skips it.
+  ///
+  pub fn union(self, other: CachePolicy) -> CachePolicy {
+    CachePolicy {
+      skip_cache_lookup: self.skip_cache_lookup || other.skip_cache_lookup,
+      do_not_cache: self.do_not_cache || other.do_not_cache,
+    }
+  }
+}
+
+///
+/// Describes when a local process which failed (possibly due to environmental flakiness) should be
+/// re-run.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RetryPolicy {
+  ///
+  /// The maximum number of times to run the process, including the first attempt.
+  ///
+  pub max_attempts: usize,
+  ///
+  /// The exit codes which indicate a retryable failure. A process which was killed by a signal
+  /// has the negated signal number as its exit code (e.g. -9 for SIGKILL).
+  ///
+  pub retryable_exit_codes: BTreeSet<i32>,
+}
+
+impl RetryPolicy {
+  pub fn is_retryable(&self, exit_code: i32) -> bool {
+    self.retryable_exit_codes.contains(&exit_code)
+  }
 }
 
 impl TryFrom<MultiPlatformExecuteProcessRequest> for ExecuteProcessRequest {
@@ -242,6 +322,29 @@ impl MultiPlatformExecuteProcessRequest {
       .next()
       .map(|(_platforms, epr)| format!("Executing process: {}", epr.description))
   }
+
+  ///
+  /// Applies defaults (generally configured for a Session) to each of the contained requests: the
+  /// given priority is used for requests which do not set one, and the given cache policy is
+  /// combined with the policy of each request.
+  ///
+  pub fn with_defaults(
+    self,
+    execution_priority: Option<i32>,
+    cache_policy: CachePolicy,
+  ) -> MultiPlatformExecuteProcessRequest {
+    MultiPlatformExecuteProcessRequest(
+      self
+        .0
+        .into_iter()
+        .map(|(platforms, mut req)| {
+          req.execution_priority = req.execution_priority.or(execution_priority);
+          req.cache_policy = req.cache_policy.union(cache_policy);
+          (platforms, req)
+        })
+        .collect(),
+    )
+  }
 }
 
 impl From<ExecuteProcessRequest> for MultiPlatformExecuteProcessRequest {
@@ -282,6 +385,19 @@ pub struct FallibleExecuteProcessResult {
   pub execution_attempts: Vec<ExecutionStats>,
 }
 
+impl FallibleExecuteProcessResult {
+  ///
+  /// Where this result came from, according to its final execution attempt.
+  ///
+  pub fn source(&self) -> ProcessResultSource {
+    match self.execution_attempts.last() {
+      Some(attempt) if attempt.was_local_cache_hit => ProcessResultSource::LocalCache,
+      Some(attempt) if attempt.was_cache_hit => ProcessResultSource::RemoteCache,
+      _ => ProcessResultSource::Executed,
+    }
+  }
+}
+
 #[cfg(test)]
 impl FallibleExecuteProcessResult {
   pub fn without_execution_attempts(mut self) -> Self {
@@ -290,6 +406,16 @@ impl FallibleExecuteProcessResult {
   }
 }
 
+///
+/// Where the result of a process came from.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProcessResultSource {
+  LocalCache,
+  RemoteCache,
+  Executed,
+}
+
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub struct ExecutionStats {
   uploaded_bytes: usize,
@@ -300,6 +426,35 @@ pub struct ExecutionStats {
   remote_execution: Option<Duration>,
   remote_output_store: Option<Duration>,
   was_cache_hit: bool,
+  // True if this "attempt" was a hit in the local process cache, rather than an execution.
+  was_local_cache_hit: bool,
+  // If this attempt failed with an exit code which was retried, the exit code.
+  retried_exit_code: Option<i32>,
+  // True if this attempt was a hit in the local process cache for a result which was only
+  // produced after retrying.
+  was_retried: bool,
+}
+
+impl ExecutionStats {
+  ///
+  /// The stats recorded for a result which was loaded from the local process cache, which was
+  /// originally produced after retrying if `was_retried` is true.
+  ///
+  pub fn local_cache_hit(was_retried: bool) -> ExecutionStats {
+    ExecutionStats {
+      was_local_cache_hit: true,
+      was_retried,
+      ..ExecutionStats::default()
+    }
+  }
+
+  pub fn retried_exit_code(&self) -> Option<i32> {
+    self.retried_exit_code
+  }
+
+  pub fn was_retried(&self) -> bool {
+    self.was_retried
+  }
 }
 
 impl AddAssign<UploadSummary> for ExecutionStats {
@@ -314,6 +469,9 @@ impl AddAssign<UploadSummary> for ExecutionStats {
 pub struct Context {
   pub workunit_store: WorkUnitStore,
   pub build_id: String,
+  /// The priority with which a BoundedCommandRunner should admit the process, relative to other
+  /// processes which are waiting to run: higher priorities are admitted first.
+  pub priority: u64,
 }
 
 pub trait CommandRunner: Send + Sync {
@@ -396,7 +554,7 @@ impl CommandRunner for BoundedCommandRunner {
     self
       .inner
       .1
-      .with_acquired(move || inner.0.run(req, context))
+      .with_acquired_prioritized(context.priority, move || inner.0.run(req, context))
   }
 
   fn extract_compatible_request(
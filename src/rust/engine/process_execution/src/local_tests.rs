@@ -2,7 +2,7 @@ use tempfile;
 use testutil;
 
 use crate::{
-  CommandRunner as CommandRunnerTrait, Context, ExecuteProcessRequest,
+  CachePolicy, CommandRunner as CommandRunnerTrait, Context, ExecuteProcessRequest,
   FallibleExecuteProcessResult, Platform, RelativePath,
 };
 use hashing::EMPTY_DIGEST;
@@ -33,6 +33,10 @@ fn stdout() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -63,6 +67,10 @@ fn stdout_and_stderr_and_exit_code() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -94,6 +102,10 @@ fn capture_exit_code_signal() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -128,6 +140,10 @@ fn env() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   let stdout = String::from_utf8(result.unwrap().stdout.to_vec()).unwrap();
@@ -168,6 +184,10 @@ fn env_is_deterministic() {
       jdk_home: None,
       target_platform: Platform::None,
       is_nailgunnable: false,
+      cache_failures: false,
+      retry_policy: None,
+      execution_priority: None,
+      cache_policy: CachePolicy::default(),
     }
   }
 
@@ -192,6 +212,10 @@ fn binary_not_found() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   })
   .expect_err("Want Err");
 }
@@ -211,6 +235,10 @@ fn output_files_none() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
   assert_eq!(
     result.unwrap(),
@@ -243,6 +271,10 @@ fn output_files_one() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -281,6 +313,10 @@ fn output_dirs() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -320,6 +356,10 @@ fn output_files_many() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -357,6 +397,10 @@ fn output_files_execution_failure() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -392,6 +436,10 @@ fn output_files_partial_output() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -425,6 +473,10 @@ fn output_overlapping_file_and_dir() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -458,6 +510,10 @@ fn jdk_symlink() {
     jdk_home: Some(preserved_work_tmpdir.path().to_path_buf()),
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
   assert_eq!(
     result,
@@ -494,6 +550,10 @@ fn test_directory_preservation() {
       jdk_home: None,
       target_platform: Platform::None,
       is_nailgunnable: false,
+      cache_failures: false,
+      retry_policy: None,
+      execution_priority: None,
+      cache_policy: CachePolicy::default(),
     },
     preserved_work_root.clone(),
     false,
@@ -535,6 +595,10 @@ fn test_directory_preservation_error() {
       jdk_home: None,
       target_platform: Platform::None,
       is_nailgunnable: false,
+      cache_failures: false,
+      retry_policy: None,
+      execution_priority: None,
+      cache_policy: CachePolicy::default(),
     },
     preserved_work_root.clone(),
     false,
@@ -573,6 +637,10 @@ fn all_containing_directories_for_outputs_are_created() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -606,6 +674,10 @@ fn output_empty_dir() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   });
 
   assert_eq!(
@@ -654,6 +726,10 @@ fn local_only_scratch_files_materialized() {
       jdk_home: None,
       target_platform: Platform::None,
       is_nailgunnable: false,
+      cache_failures: false,
+      retry_policy: None,
+      execution_priority: None,
+      cache_policy: CachePolicy::default(),
     },
     work_dir.path().to_owned(),
     true,
@@ -692,6 +768,10 @@ fn timeout() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   })
   .unwrap();
 
@@ -734,6 +814,10 @@ fn working_directory() {
       jdk_home: None,
       target_platform: Platform::None,
       is_nailgunnable: false,
+      cache_failures: false,
+      retry_policy: None,
+      execution_priority: None,
+      cache_policy: CachePolicy::default(),
     },
     work_dir.path().to_owned(),
     true,
@@ -14,8 +14,8 @@ use tokio::net::TcpStream;
 use crate::local::CapturedWorkdir;
 use crate::nailgun::nailgun_pool::NailgunProcessName;
 use crate::{
-  Context, ExecuteProcessRequest, ExecuteProcessRequestMetadata, FallibleExecuteProcessResult,
-  MultiPlatformExecuteProcessRequest, Platform,
+  CachePolicy, Context, ExecuteProcessRequest, ExecuteProcessRequestMetadata,
+  FallibleExecuteProcessResult, MultiPlatformExecuteProcessRequest, Platform,
 };
 
 #[cfg(test)]
@@ -65,6 +65,10 @@ fn construct_nailgun_server_request(
     jdk_home: Some(jdk),
     target_platform: platform,
     is_nailgunnable: true,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   }
 }
 
@@ -86,6 +90,10 @@ fn construct_nailgun_client_request(
     jdk_home: _jdk_home,
     target_platform,
     is_nailgunnable,
+    cache_failures,
+    retry_policy,
+    execution_priority,
+    cache_policy,
   } = original_req;
   client_args.insert(0, client_main_class);
   ExecuteProcessRequest {
@@ -101,6 +109,10 @@ fn construct_nailgun_client_request(
     jdk_home: None,
     target_platform,
     is_nailgunnable,
+    cache_failures,
+    retry_policy,
+    execution_priority,
+    cache_policy,
   }
 }
 
@@ -1,5 +1,5 @@
 use crate::nailgun::{CommandRunner, ARGS_TO_START_NAILGUN, NAILGUN_MAIN_CLASS};
-use crate::{ExecuteProcessRequest, ExecuteProcessRequestMetadata, Platform};
+use crate::{CachePolicy, ExecuteProcessRequest, ExecuteProcessRequestMetadata, Platform};
 use hashing::EMPTY_DIGEST;
 use std::fs::read_link;
 use std::os::unix::fs::symlink;
@@ -45,6 +45,10 @@ fn mock_nailgunnable_request(jdk_home: Option<PathBuf>) -> ExecuteProcessRequest
     jdk_home: jdk_home,
     target_platform: Platform::Darwin,
     is_nailgunnable: true,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   }
 }
 
@@ -924,12 +924,19 @@ pub fn make_execute_request(
   let mut action = bazel_protos::remote_execution::Action::new();
   action.set_command_digest((&digest(&command)?).into());
   action.set_input_root_digest((&req.input_files).into());
+  action.set_do_not_cache(req.cache_policy.do_not_cache);
 
   let mut execute_request = bazel_protos::remote_execution::ExecuteRequest::new();
   if let Some(instance_name) = instance_name {
     execute_request.set_instance_name(instance_name);
   }
   execute_request.set_action_digest((&digest(&action)?).into());
+  execute_request.set_skip_cache_lookup(req.cache_policy.skip_cache_lookup);
+  if let Some(priority) = req.execution_priority {
+    execute_request
+      .mut_execution_policy()
+      .set_priority(priority);
+  }
 
   Ok((action, command, execute_request))
 }
@@ -8,7 +8,7 @@ use hashing::{Digest, Fingerprint, EMPTY_DIGEST};
 use mock;
 use protobuf::{self, Message, ProtobufEnum};
 use spectral::{assert_that, string::StrAssertions};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use store::Store;
 use tempfile::TempDir;
 use testutil::data::{TestData, TestDirectory};
@@ -16,7 +16,7 @@ use testutil::{as_bytes, owned_string_vec};
 
 use crate::remote::{CommandRunner, ExecutionError, ExecutionHistory, OperationOrStatus};
 use crate::{
-  CommandRunner as CommandRunnerTrait, Context, ExecuteProcessRequest,
+  CachePolicy, CommandRunner as CommandRunnerTrait, Context, ExecuteProcessRequest,
   ExecuteProcessRequestMetadata, FallibleExecuteProcessResult, MultiPlatformExecuteProcessRequest,
   Platform,
 };
@@ -74,6 +74,10 @@ fn local_only_scratch_files_ignored() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
 
   let req2 = ExecuteProcessRequest {
@@ -99,6 +103,10 @@ fn local_only_scratch_files_ignored() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
 
   assert_eq!(
@@ -133,6 +141,10 @@ fn make_execute_request() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
 
   let mut want_command = bazel_protos::remote_execution::Command::new();
@@ -217,6 +229,10 @@ fn make_execute_request_with_instance_name() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
 
   let mut want_command = bazel_protos::remote_execution::Command::new();
@@ -309,6 +325,10 @@ fn make_execute_request_with_cache_key_gen_version() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
 
   let mut want_command = bazel_protos::remote_execution::Command::new();
@@ -380,6 +400,35 @@ fn make_execute_request_with_cache_key_gen_version() {
   );
 }
 
+#[test]
+fn make_execute_request_with_priority_and_cache_policy() {
+  let mut req = ExecuteProcessRequest::try_from(echo_foo_request()).unwrap();
+  let (default_action, _, default_execute_request) =
+    crate::remote::make_execute_request(&req, empty_request_metadata()).unwrap();
+  assert!(!default_action.get_do_not_cache());
+  assert!(!default_execute_request.get_skip_cache_lookup());
+  assert!(!default_execute_request.has_execution_policy());
+  assert!(!default_execute_request.has_results_cache_policy());
+
+  req.execution_priority = Some(-5);
+  req.cache_policy = CachePolicy {
+    skip_cache_lookup: true,
+    do_not_cache: true,
+  };
+  let (action, _, execute_request) =
+    crate::remote::make_execute_request(&req, empty_request_metadata()).unwrap();
+  assert!(action.get_do_not_cache());
+  assert!(execute_request.get_skip_cache_lookup());
+  assert_eq!(execute_request.get_execution_policy().get_priority(), -5);
+  // The execution priority does not affect how long the result is retained in the cache.
+  assert!(!execute_request.has_results_cache_policy());
+  // Not caching the result is part of the Action, and so changes its cache key.
+  assert_ne!(
+    execute_request.get_action_digest(),
+    default_execute_request.get_action_digest()
+  );
+}
+
 #[test]
 fn make_execute_request_with_jdk() {
   let input_directory = TestDirectory::containing_roland();
@@ -397,6 +446,10 @@ fn make_execute_request_with_jdk() {
     jdk_home: Some(PathBuf::from("/tmp")),
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
 
   let mut want_command = bazel_protos::remote_execution::Command::new();
@@ -463,6 +516,10 @@ fn make_execute_request_with_jdk_and_extra_platform_properties() {
     jdk_home: Some(PathBuf::from("/tmp")),
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
 
   let mut want_command = bazel_protos::remote_execution::Command::new();
@@ -571,6 +628,10 @@ fn server_rejecting_execute_request_gives_error() {
             jdk_home: None,
             target_platform: Platform::None,
             is_nailgunnable: false,
+            cache_failures: false,
+            retry_policy: None,
+            execution_priority: None,
+            cache_policy: CachePolicy::default(),
           },
           empty_request_metadata(),
         )
@@ -785,6 +846,7 @@ pub fn sends_headers() {
   let context = Context {
     workunit_store: WorkUnitStore::default(),
     build_id: String::from("marmosets"),
+    priority: 0,
   };
   tokio::runtime::Runtime::new()
     .unwrap()
@@ -1056,6 +1118,10 @@ fn timeout_after_sufficiently_delayed_getoperations() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
 
   let op_name = "gimme-foo".to_string();
@@ -1109,6 +1175,10 @@ fn dropped_request_cancels() {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
 
   let op_name = "gimme-foo".to_string();
@@ -2290,6 +2360,10 @@ pub fn echo_foo_request() -> MultiPlatformExecuteProcessRequest {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
   req.into()
 }
@@ -2604,6 +2678,10 @@ fn cat_roland_request() -> MultiPlatformExecuteProcessRequest {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
   req.into()
 }
@@ -2623,6 +2701,10 @@ fn echo_roland_request() -> MultiPlatformExecuteProcessRequest {
     jdk_home: None,
     target_platform: Platform::None,
     is_nailgunnable: false,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
   req.into()
 }
@@ -0,0 +1,89 @@
+use crate::{
+  Context, ExecuteProcessRequest, ExecutionStats, FallibleExecuteProcessResult,
+  MultiPlatformExecuteProcessRequest,
+};
+use boxfuture::{BoxFuture, Boxable};
+use futures::future::{self, Future, Loop};
+use log::warn;
+use std::sync::Arc;
+
+///
+/// A CommandRunner wrapper which re-runs requests that fail with an exit code that their
+/// RetryPolicy considers retryable, up to the policy's maximum number of attempts.
+///
+/// Every attempt is recorded in the `execution_attempts` of the final result: failed attempts
+/// which were retried are marked with their exit code (see `ExecutionStats::retried_exit_code`).
+///
+#[derive(Clone)]
+pub struct CommandRunner {
+  underlying: Arc<dyn crate::CommandRunner>,
+}
+
+impl CommandRunner {
+  pub fn new(underlying: Box<dyn crate::CommandRunner>) -> CommandRunner {
+    CommandRunner {
+      underlying: underlying.into(),
+    }
+  }
+}
+
+impl crate::CommandRunner for CommandRunner {
+  fn num_waiters(&self) -> usize {
+    self.underlying.num_waiters()
+  }
+
+  fn run(
+    &self,
+    req: MultiPlatformExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    let (retry_policy, description) = match self.extract_compatible_request(&req) {
+      Some(ExecuteProcessRequest {
+        retry_policy: Some(retry_policy),
+        description,
+        ..
+      }) => (retry_policy, description),
+      _ => return self.underlying.run(req, context),
+    };
+
+    let underlying = self.underlying.clone();
+    future::loop_fn(
+      (Vec::new(), 1),
+      move |(mut execution_attempts, attempt): (Vec<ExecutionStats>, usize)| {
+        let retry_policy = retry_policy.clone();
+        let description = description.clone();
+        underlying
+          .run(req.clone(), context.clone())
+          .map(move |mut result| {
+            if attempt < retry_policy.max_attempts && retry_policy.is_retryable(result.exit_code) {
+              warn!(
+                "Process {} failed with exit code {} (attempt {} of {}): retrying.",
+                description, result.exit_code, attempt, retry_policy.max_attempts
+              );
+              let mut failed_attempts = result.execution_attempts;
+              if failed_attempts.is_empty() {
+                failed_attempts.push(ExecutionStats::default());
+              }
+              for failed_attempt in &mut failed_attempts {
+                failed_attempt.retried_exit_code = Some(result.exit_code);
+              }
+              execution_attempts.extend(failed_attempts);
+              Loop::Continue((execution_attempts, attempt + 1))
+            } else {
+              execution_attempts.append(&mut result.execution_attempts);
+              result.execution_attempts = execution_attempts;
+              Loop::Break(result)
+            }
+          })
+      },
+    )
+    .to_boxed()
+  }
+
+  fn extract_compatible_request(
+    &self,
+    req: &MultiPlatformExecuteProcessRequest,
+  ) -> Option<ExecuteProcessRequest> {
+    self.underlying.extract_compatible_request(req)
+  }
+}
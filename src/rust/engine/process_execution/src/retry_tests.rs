@@ -0,0 +1,118 @@
+use crate::remote_tests::echo_foo_request;
+use crate::retry;
+use crate::{
+  CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult,
+  MultiPlatformExecuteProcessRequest, Platform, RetryPolicy,
+};
+use boxfuture::{BoxFuture, Boxable};
+use futures::future;
+use hashing::EMPTY_DIGEST;
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+#[test]
+fn retries_until_success() {
+  let (result, calls) = run_retry_test(Some(policy(3, &[1])), vec![1, 1, 0]);
+  assert_eq!(calls, 3);
+  let result = result.unwrap();
+  assert_eq!(result.exit_code, 0);
+  assert_eq!(
+    result
+      .execution_attempts
+      .iter()
+      .map(|attempt| attempt.retried_exit_code())
+      .collect::<Vec<_>>(),
+    vec![Some(1), Some(1)]
+  );
+}
+
+#[test]
+fn gives_up_after_max_attempts() {
+  let (result, calls) = run_retry_test(Some(policy(2, &[-9])), vec![-9, -9, 0]);
+  assert_eq!(calls, 2);
+  assert_eq!(result.unwrap().exit_code, -9);
+}
+
+#[test]
+fn non_retryable_exit_code_is_not_retried() {
+  let (result, calls) = run_retry_test(Some(policy(3, &[1])), vec![2, 0]);
+  assert_eq!(calls, 1);
+  let result = result.unwrap();
+  assert_eq!(result.exit_code, 2);
+  assert!(result.execution_attempts.is_empty());
+}
+
+#[test]
+fn no_retry_policy() {
+  let (result, calls) = run_retry_test(None, vec![1, 0]);
+  assert_eq!(calls, 1);
+  assert_eq!(result.unwrap().exit_code, 1);
+}
+
+fn policy(max_attempts: usize, retryable_exit_codes: &[i32]) -> RetryPolicy {
+  RetryPolicy {
+    max_attempts,
+    retryable_exit_codes: retryable_exit_codes.iter().cloned().collect(),
+  }
+}
+
+fn run_retry_test(
+  retry_policy: Option<RetryPolicy>,
+  exit_codes: Vec<i32>,
+) -> (Result<FallibleExecuteProcessResult, String>, usize) {
+  let mut req = ExecuteProcessRequest::try_from(echo_foo_request()).unwrap();
+  req.retry_policy = retry_policy;
+
+  let underlying = SequenceCommandRunner {
+    exit_codes: Arc::new(Mutex::new(exit_codes.into_iter().collect())),
+    calls: Arc::new(Mutex::new(0)),
+  };
+  let calls = underlying.calls.clone();
+  let runner = retry::CommandRunner::new(Box::new(underlying));
+
+  let mut runtime = tokio::runtime::Runtime::new().unwrap();
+  let result = runtime.block_on(runner.run(req.into(), Context::default()));
+  let calls = *calls.lock();
+  (result, calls)
+}
+
+///
+/// A CommandRunner which returns the given exit codes in order, one per call.
+///
+#[derive(Clone)]
+struct SequenceCommandRunner {
+  exit_codes: Arc<Mutex<VecDeque<i32>>>,
+  calls: Arc<Mutex<usize>>,
+}
+
+impl CommandRunner for SequenceCommandRunner {
+  fn run(
+    &self,
+    _req: MultiPlatformExecuteProcessRequest,
+    _context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    *self.calls.lock() += 1;
+    let exit_code = self
+      .exit_codes
+      .lock()
+      .pop_front()
+      .expect("Ran more times than expected.");
+    future::ok(FallibleExecuteProcessResult {
+      stdout: "".into(),
+      stderr: "".into(),
+      exit_code,
+      output_directory: EMPTY_DIGEST,
+      execution_attempts: vec![],
+    })
+    .to_boxed()
+  }
+
+  fn extract_compatible_request(
+    &self,
+    req: &MultiPlatformExecuteProcessRequest,
+  ) -> Option<ExecuteProcessRequest> {
+    req.0.get(&(Platform::None, Platform::None)).cloned()
+  }
+}
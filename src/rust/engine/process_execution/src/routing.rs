@@ -0,0 +1,65 @@
+use crate::{
+  CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult,
+  MultiPlatformExecuteProcessRequest,
+};
+use boxfuture::{BoxFuture, Boxable};
+use futures::future;
+use std::sync::Arc;
+
+///
+/// A CommandRunner which runs each request using the first of several CommandRunners which is
+/// compatible with it (as determined by `extract_compatible_request`).
+///
+/// This allows requests which target different platforms to be routed to different backends
+/// (e.g. Linux and macOS remote execution clusters).
+///
+#[derive(Clone)]
+pub struct RoutingCommandRunner {
+  runners: Arc<Vec<Box<dyn CommandRunner>>>,
+}
+
+impl RoutingCommandRunner {
+  pub fn new(runners: Vec<Box<dyn CommandRunner>>) -> RoutingCommandRunner {
+    RoutingCommandRunner {
+      runners: Arc::new(runners),
+    }
+  }
+
+  fn route(&self, req: &MultiPlatformExecuteProcessRequest) -> Option<&dyn CommandRunner> {
+    self
+      .runners
+      .iter()
+      .find(|runner| runner.extract_compatible_request(req).is_some())
+      .map(|runner| runner.as_ref())
+  }
+}
+
+impl CommandRunner for RoutingCommandRunner {
+  fn num_waiters(&self) -> usize {
+    self.runners.iter().map(|runner| runner.num_waiters()).sum()
+  }
+
+  fn run(
+    &self,
+    req: MultiPlatformExecuteProcessRequest,
+    context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    match self.route(&req) {
+      Some(runner) => runner.run(req, context),
+      None => future::err(format!(
+        "No configured backend is compatible with any of the platform constraints {:?}",
+        req.0.keys().collect::<Vec<_>>()
+      ))
+      .to_boxed(),
+    }
+  }
+
+  fn extract_compatible_request(
+    &self,
+    req: &MultiPlatformExecuteProcessRequest,
+  ) -> Option<ExecuteProcessRequest> {
+    self
+      .route(req)
+      .and_then(|runner| runner.extract_compatible_request(req))
+  }
+}
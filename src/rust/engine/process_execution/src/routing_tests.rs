@@ -0,0 +1,91 @@
+use crate::remote_tests::echo_foo_request;
+use crate::routing::RoutingCommandRunner;
+use crate::{
+  CommandRunner, Context, ExecuteProcessRequest, FallibleExecuteProcessResult,
+  MultiPlatformExecuteProcessRequest, Platform,
+};
+use boxfuture::{BoxFuture, Boxable};
+use bytes::Bytes;
+use futures::future;
+use hashing::EMPTY_DIGEST;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+#[test]
+fn routes_to_first_compatible_runner() {
+  let runner = RoutingCommandRunner::new(vec![
+    Box::new(PlatformCommandRunner(Platform::Linux)),
+    Box::new(PlatformCommandRunner(Platform::Darwin)),
+  ]);
+
+  assert_eq!(
+    run(&runner, &[Platform::Darwin]).map(|result| result.stdout),
+    Ok(Bytes::from("osx"))
+  );
+  assert_eq!(
+    run(&runner, &[Platform::Linux]).map(|result| result.stdout),
+    Ok(Bytes::from("linux"))
+  );
+  assert_eq!(
+    run(&runner, &[Platform::Darwin, Platform::Linux]).map(|result| result.stdout),
+    Ok(Bytes::from("linux"))
+  );
+}
+
+#[test]
+fn fails_when_no_runner_is_compatible() {
+  let runner = RoutingCommandRunner::new(vec![Box::new(PlatformCommandRunner(Platform::Linux))]);
+
+  assert_eq!(
+    runner.extract_compatible_request(&multi_platform_request(&[Platform::Darwin])),
+    None
+  );
+  assert!(run(&runner, &[Platform::Darwin]).is_err());
+}
+
+fn run(
+  runner: &RoutingCommandRunner,
+  platforms: &[Platform],
+) -> Result<FallibleExecuteProcessResult, String> {
+  let mut runtime = tokio::runtime::Runtime::new().unwrap();
+  runtime.block_on(runner.run(multi_platform_request(platforms), Context::default()))
+}
+
+fn multi_platform_request(platforms: &[Platform]) -> MultiPlatformExecuteProcessRequest {
+  let req = ExecuteProcessRequest::try_from(echo_foo_request()).unwrap();
+  let mut requests = BTreeMap::new();
+  for platform in platforms {
+    requests.insert((*platform, Platform::None), req.clone());
+  }
+  MultiPlatformExecuteProcessRequest(requests)
+}
+
+///
+/// A CommandRunner which is compatible only with requests for the given platform, and which
+/// returns the name of that platform as stdout.
+///
+struct PlatformCommandRunner(Platform);
+
+impl CommandRunner for PlatformCommandRunner {
+  fn run(
+    &self,
+    _req: MultiPlatformExecuteProcessRequest,
+    _context: Context,
+  ) -> BoxFuture<FallibleExecuteProcessResult, String> {
+    future::ok(FallibleExecuteProcessResult {
+      stdout: Bytes::from(String::from(self.0)),
+      stderr: "".into(),
+      exit_code: 0,
+      output_directory: EMPTY_DIGEST,
+      execution_attempts: vec![],
+    })
+    .to_boxed()
+  }
+
+  fn extract_compatible_request(
+    &self,
+    req: &MultiPlatformExecuteProcessRequest,
+  ) -> Option<ExecuteProcessRequest> {
+    req.0.get(&(self.0, Platform::None)).cloned()
+  }
+}
@@ -1,4 +1,4 @@
-use crate::{ExecuteProcessRequest, Platform, RelativePath};
+use crate::{CachePolicy, ExecuteProcessRequest, Platform, RelativePath};
 use hashing::{Digest, Fingerprint};
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{BTreeMap, BTreeSet};
@@ -21,6 +21,10 @@ fn execute_process_request_equality() {
             jdk_home: None,
             target_platform: Platform::None,
             is_nailgunnable: false,
+            cache_failures: false,
+            retry_policy: None,
+            execution_priority: None,
+            cache_policy: CachePolicy::default(),
         };
 
   fn hash<Hashable: Hash>(hashable: &Hashable) -> u64 {
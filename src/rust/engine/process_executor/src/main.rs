@@ -33,7 +33,9 @@ use process_execution;
 
 use clap::{value_t, App, AppSettings, Arg};
 use hashing::{Digest, Fingerprint};
-use process_execution::{Context, ExecuteProcessRequestMetadata, Platform, RelativePath};
+use process_execution::{
+  CachePolicy, Context, ExecuteProcessRequestMetadata, Platform, RelativePath,
+};
 use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryFrom;
 use std::iter::{FromIterator, Iterator};
@@ -341,6 +343,10 @@ fn main() {
     target_platform: Platform::try_from(&args.value_of("target-platform").unwrap().to_string())
       .expect("invalid value for `target-platform"),
     is_nailgunnable,
+    cache_failures: false,
+    retry_policy: None,
+    execution_priority: None,
+    cache_policy: CachePolicy::default(),
   };
 
   let runner: Box<dyn process_execution::CommandRunner> = match server_arg {
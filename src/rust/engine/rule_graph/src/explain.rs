@@ -0,0 +1,444 @@
+// Copyright 2020 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+
+use crate::{
+  entry_rule, entry_str, params_str, union_dependency_keys, Decisions, DependencyKey, Diagnostic,
+  DiagnosticKind, Entry, EntryWithDeps, GraphMaker, ParamTypes, RootEntry, Rule,
+  RuleDependencyEdges, SimplifiedEntries, UnfulfillableRuleMap,
+};
+
+///
+/// Whether a candidate for a dependency was used, and if not, why it was pruned.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ExplainOutcome {
+  // The candidate was used to compute the dependency.
+  Chosen,
+  // A dependency of the candidate could not be computed with the available Params.
+  MissingParam(Vec<String>),
+  // The candidate was satisfiable, but its dependent was not, so no candidate was chosen.
+  DependentUnsatisfiable,
+  // The candidate was ambiguous with another candidate, or had an ambiguous dependency.
+  Ambiguous(Vec<String>),
+  // The candidate was satisfiable, but was eliminated while simplifying the graph.
+  Simplified(String),
+}
+
+impl ExplainOutcome {
+  pub fn is_chosen(&self) -> bool {
+    match self {
+      ExplainOutcome::Chosen => true,
+      _ => false,
+    }
+  }
+
+  fn label(&self) -> &'static str {
+    match self {
+      ExplainOutcome::Chosen => "chosen",
+      ExplainOutcome::MissingParam(_) => "missing param",
+      ExplainOutcome::DependentUnsatisfiable => "dependent unsatisfiable",
+      ExplainOutcome::Ambiguous(_) => "ambiguous",
+      ExplainOutcome::Simplified(_) => "simplified",
+    }
+  }
+
+  fn reasons(&self) -> Vec<&str> {
+    match self {
+      ExplainOutcome::Chosen => vec![],
+      ExplainOutcome::MissingParam(ref reasons) | ExplainOutcome::Ambiguous(ref reasons) => {
+        reasons.iter().map(String::as_str).collect()
+      }
+      ExplainOutcome::DependentUnsatisfiable => {
+        vec!["No candidate was chosen, because the dependent was not satisfiable."]
+      }
+      ExplainOutcome::Simplified(ref reason) => vec![reason.as_str()],
+    }
+  }
+}
+
+///
+/// A Param or rule that could provide a dependency, along with the sets of Params that it would
+/// consume, and explanations for each of its own dependencies.
+///
+/// Each rule is only expanded once per Explanation: `expanded` is false (and `dependencies` is
+/// empty) for any later occurrences of it.
+///
+#[derive(Clone, Debug)]
+pub struct ExplainCandidate<R: Rule> {
+  pub entry: Entry<R>,
+  pub used_params: Vec<ParamTypes<R::TypeId>>,
+  pub outcome: ExplainOutcome,
+  pub expanded: bool,
+  pub dependencies: Vec<Explanation<R>>,
+}
+
+///
+/// Explains how a DependencyKey would be computed for a set of Params: renderable as text via
+/// Display, or as DOT via `visualize`.
+///
+#[derive(Clone, Debug)]
+pub struct Explanation<R: Rule> {
+  pub dependency_key: R::DependencyKey,
+  pub params: ParamTypes<R::TypeId>,
+  pub candidates: Vec<ExplainCandidate<R>>,
+}
+
+impl<R: Rule> Explanation<R> {
+  ///
+  /// Renders this Explanation as a DOT graph, with chosen candidates in green and pruned
+  /// candidates in red.
+  ///
+  pub fn visualize(&self, f: &mut dyn io::Write) -> io::Result<()> {
+    writeln!(f, "digraph {{")?;
+    self.visualize_helper(f, &mut 0)?;
+    writeln!(f, "}}")
+  }
+
+  fn visualize_helper(&self, f: &mut dyn io::Write, next_id: &mut usize) -> io::Result<usize> {
+    let id = *next_id;
+    *next_id += 1;
+    writeln!(
+      f,
+      "  \"{}\" [label=\"{}\" shape=box]",
+      id,
+      dot_escape(&self.title()),
+    )?;
+    for candidate in &self.candidates {
+      let candidate_id = *next_id;
+      *next_id += 1;
+      let mut label_lines = vec![entry_str(&candidate.entry)];
+      if !candidate.used_params.is_empty() {
+        label_lines.push(format!("uses {}", used_params_str(&candidate.used_params)));
+      }
+      label_lines.extend(candidate.outcome.reasons().into_iter().map(str::to_owned));
+      writeln!(
+        f,
+        "  \"{}\" [label=\"{}\" color={}]",
+        candidate_id,
+        dot_escape(&label_lines.join("\n")),
+        if candidate.outcome.is_chosen() {
+          "green"
+        } else {
+          "red"
+        },
+      )?;
+      writeln!(
+        f,
+        "  \"{}\" -> \"{}\" [label=\"{}\"]",
+        id,
+        candidate_id,
+        candidate.outcome.label(),
+      )?;
+      for dependency in &candidate.dependencies {
+        let dependency_id = dependency.visualize_helper(f, next_id)?;
+        writeln!(f, "  \"{}\" -> \"{}\"", candidate_id, dependency_id)?;
+      }
+    }
+    Ok(id)
+  }
+
+  fn title(&self) -> String {
+    format!(
+      "{} for Params({})",
+      self.dependency_key,
+      params_str(&self.params)
+    )
+  }
+
+  fn render(&self, f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+    let pad = " ".repeat(indent);
+    writeln!(f, "{}{}:", pad, self.title())?;
+    if self.candidates.is_empty() {
+      writeln!(
+        f,
+        "{}  No installed rules return the type {}.",
+        pad,
+        self.dependency_key.product(),
+      )?;
+    }
+    for candidate in &self.candidates {
+      write!(
+        f,
+        "{}  [{}] {}",
+        pad,
+        candidate.outcome.label(),
+        entry_str(&candidate.entry)
+      )?;
+      if !candidate.used_params.is_empty() {
+        write!(f, " (uses {})", used_params_str(&candidate.used_params))?;
+      }
+      if !candidate.expanded {
+        write!(f, " (expanded above)")?;
+      }
+      writeln!(f)?;
+      for reason in candidate.outcome.reasons() {
+        for line in reason.lines() {
+          writeln!(f, "{}      {}", pad, line.trim_start())?;
+        }
+      }
+      for dependency in &candidate.dependencies {
+        dependency.render(f, indent + 4)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl<R: Rule> fmt::Display for Explanation<R> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    self.render(f, 0)
+  }
+}
+
+fn used_params_str<T: crate::TypeId>(used_params: &[ParamTypes<T>]) -> String {
+  used_params
+    .iter()
+    .map(|params| format!("Params({})", params_str(params)))
+    .collect::<Vec<_>>()
+    .join(" or ")
+}
+
+fn dot_escape(s: &str) -> String {
+  s.replace('\\', "\\\\")
+    .replace('"', "\\\"")
+    .replace('\n', "\\n")
+}
+
+impl<'t, R: Rule> GraphMaker<'t, R> {
+  ///
+  /// Explains how the given product would be computed for the given Params: which rules (or
+  /// Params) could provide it, the Params that each of them would consume, and why any of them
+  /// were pruned. Recurses into the dependencies of each candidate rule.
+  ///
+  /// The explanation reports the choices that graph construction actually made: see `Decisions`.
+  ///
+  pub fn explain(&self, param_types: Vec<R::TypeId>, product: R::TypeId) -> Explanation<R> {
+    let dependency_key = R::DependencyKey::new_root(product);
+    let root = EntryWithDeps::Root(RootEntry {
+      params: param_types.into_iter().collect(),
+      dependency_key,
+    });
+
+    let mut explainer = Explainer {
+      graph_maker: self,
+      rule_dependency_edges: RuleDependencyEdges::default(),
+      simplified_entries: SimplifiedEntries::default(),
+      unfulfillable_rules: UnfulfillableRuleMap::default(),
+      decisions: Decisions::default(),
+      expanded: HashSet::new(),
+    };
+    explainer.construct(root.clone());
+    explainer.explain_dependency(&root, dependency_key)
+  }
+}
+
+struct Explainer<'a, 't, R: Rule> {
+  graph_maker: &'a GraphMaker<'t, R>,
+  rule_dependency_edges: RuleDependencyEdges<R>,
+  simplified_entries: SimplifiedEntries<R>,
+  unfulfillable_rules: UnfulfillableRuleMap<R>,
+  decisions: Decisions<R>,
+  expanded: HashSet<EntryWithDeps<R>>,
+}
+
+impl<'a, 't, R: Rule> Explainer<'a, 't, R> {
+  ///
+  /// Constructs the given entry (if it has not already been), so that it is recorded as either
+  /// satisfiable or unfulfillable. An entry whose simplified versions were shared with an
+  /// equivalent entry will not have had its own candidates constructed: they are constructed lazily
+  /// here, via the same path that construction uses.
+  ///
+  fn construct(&mut self, entry: EntryWithDeps<R>) {
+    self.graph_maker.construct_graph_helper(
+      &mut self.rule_dependency_edges,
+      &mut self.simplified_entries,
+      &mut self.unfulfillable_rules,
+      &mut self.decisions,
+      entry,
+    );
+  }
+
+  fn explain_dependency(
+    &mut self,
+    dependent: &EntryWithDeps<R>,
+    dependency_key: R::DependencyKey,
+  ) -> Explanation<R> {
+    let provided_param = dependency_key.provided_param();
+    let mut params = dependent.params().clone();
+    params.extend(provided_param);
+
+    // The entries that were chosen for this dependency in any simplified version of the dependent,
+    // and the ambiguities between candidates for it.
+    let chosen = self
+      .simplified_entries
+      .get(dependent)
+      .into_iter()
+      .flatten()
+      .filter_map(|simplified| self.rule_dependency_edges.get(simplified))
+      .filter_map(|edges| edges.entry_for(&dependency_key))
+      .cloned()
+      .collect::<HashSet<_>>();
+    let ambiguities = self
+      .decisions
+      .ambiguities
+      .get(dependent)
+      .into_iter()
+      .flatten()
+      .filter(|d| d.product == Some(dependency_key.product()))
+      .cloned()
+      .collect::<Vec<_>>();
+
+    let candidates = self
+      .graph_maker
+      .rhs(&params, dependency_key.product())
+      .into_iter()
+      .map(|candidate| self.explain_candidate(candidate, provided_param, &chosen, &ambiguities))
+      .collect();
+
+    Explanation {
+      dependency_key,
+      params,
+      candidates,
+    }
+  }
+
+  fn explain_candidate(
+    &mut self,
+    candidate: Entry<R>,
+    provided_param: Option<R::TypeId>,
+    chosen: &HashSet<Entry<R>>,
+    ambiguities: &[Diagnostic<R::TypeId>],
+  ) -> ExplainCandidate<R> {
+    let entry = match candidate {
+      Entry::WithDeps(entry) => entry,
+      Entry::Param(type_id) => {
+        let param = Entry::Param(type_id);
+        let outcome = if chosen.contains(&param) {
+          ExplainOutcome::Chosen
+        } else {
+          self.pruned_outcome(std::slice::from_ref(&param), chosen, ambiguities)
+        };
+        return ExplainCandidate {
+          entry: param,
+          used_params: vec![vec![type_id].into_iter().collect()],
+          outcome,
+          expanded: true,
+          dependencies: vec![],
+        };
+      }
+    };
+
+    self.construct(entry.clone());
+    let (outcome, used_params) = if let Some(diagnostics) = self.unfulfillable_rules.get(&entry) {
+      let mut reasons = diagnostics
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<_>>();
+      reasons.sort();
+      reasons.dedup();
+      if diagnostics
+        .iter()
+        .any(|d| d.kind == DiagnosticKind::Ambiguous)
+      {
+        (ExplainOutcome::Ambiguous(reasons), vec![])
+      } else {
+        (ExplainOutcome::MissingParam(reasons), vec![])
+      }
+    } else {
+      // As during construction, only entries that actually consume a provided (Get) parameter are
+      // eligible for consideration.
+      let simplified = self
+        .simplified_entries
+        .get(&entry)
+        .expect("A constructed entry is either satisfiable or unfulfillable.")
+        .iter()
+        .filter(|e| provided_param.map_or(true, |pp| e.params().contains(&pp)))
+        .cloned()
+        .map(Entry::WithDeps)
+        .collect::<Vec<_>>();
+      let used_params = simplified
+        .iter()
+        .map(|e| e.params().into_iter().collect())
+        .collect::<Vec<_>>();
+      let outcome = match provided_param {
+        _ if simplified.iter().any(|e| chosen.contains(e)) => ExplainOutcome::Chosen,
+        Some(pp) if simplified.is_empty() => {
+          ExplainOutcome::Simplified(format!("Did not consume the provided Param {}.", pp))
+        }
+        _ => self.pruned_outcome(&simplified, chosen, ambiguities),
+      };
+      (outcome, used_params)
+    };
+
+    let expanded = self.expanded.insert(entry.clone());
+    let dependencies = if expanded {
+      union_dependency_keys(self.graph_maker.unions, &entry)
+        .into_iter()
+        .map(|(dependency_key, _)| self.explain_dependency(&entry, dependency_key))
+        .collect()
+    } else {
+      vec![]
+    };
+
+    ExplainCandidate {
+      entry: Entry::WithDeps(entry),
+      used_params,
+      outcome,
+      expanded,
+      dependencies,
+    }
+  }
+
+  ///
+  /// Explains why a satisfiable candidate (represented by its simplified versions) was not chosen
+  /// for a dependency.
+  ///
+  fn pruned_outcome(
+    &self,
+    simplified: &[Entry<R>],
+    chosen: &HashSet<Entry<R>>,
+    ambiguities: &[Diagnostic<R::TypeId>],
+  ) -> ExplainOutcome {
+    let simplified_strs = simplified.iter().map(entry_str).collect::<Vec<_>>();
+    let mut reasons = ambiguities
+      .iter()
+      .filter(|d| d.candidates.iter().any(|c| simplified_strs.contains(c)))
+      .map(|d| d.to_string())
+      .collect::<Vec<_>>();
+    if !reasons.is_empty() {
+      reasons.sort();
+      reasons.dedup();
+      return ExplainOutcome::Ambiguous(reasons);
+    }
+
+    let mut shadowed_by = simplified
+      .first()
+      .and_then(entry_rule)
+      .and_then(|rule| self.decisions.shadowing_rules.get(rule))
+      .map(|shadowing| {
+        chosen
+          .iter()
+          .filter_map(entry_rule)
+          .filter(|r| shadowing.contains(r))
+          .map(|r| r.to_string())
+          .collect::<Vec<_>>()
+      })
+      .unwrap_or_else(Vec::new);
+    if !shadowed_by.is_empty() {
+      shadowed_by.sort();
+      shadowed_by.dedup();
+      ExplainOutcome::Simplified(format!(
+        "Shadowed by higher priority rule(s): {}",
+        shadowed_by.join(", ")
+      ))
+    } else if chosen.is_empty() {
+      ExplainOutcome::DependentUnsatisfiable
+    } else {
+      ExplainOutcome::Simplified("Another candidate consumed fewer Params.".to_owned())
+    }
+  }
+}
@@ -0,0 +1,319 @@
+// Copyright 2020 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+
+use crate::{
+  entry_str, union_dependency_keys, DependencyKey, Entry, EntryWithDeps, InnerEntry, Rule,
+  RuleGraph,
+};
+
+///
+/// The kind of an entry in an exported RuleGraph.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExportedEntryKind {
+  // A request for a product with a set of root Params.
+  Root,
+  // A rule, with the Params that it consumes.
+  Inner,
+  // A Param, which is provided by a root or by a Get.
+  Param,
+}
+
+impl ExportedEntryKind {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      ExportedEntryKind::Root => "root",
+      ExportedEntryKind::Inner => "inner",
+      ExportedEntryKind::Param => "param",
+    }
+  }
+}
+
+///
+/// An entry in an exported RuleGraph. The `id` is only unique within a single GraphExport.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExportedEntry {
+  pub id: String,
+  pub kind: ExportedEntryKind,
+  pub label: String,
+  pub rule: Option<String>,
+  pub product: String,
+  pub params: Vec<String>,
+}
+
+///
+/// A dependency of one entry on another. For the dependencies of a rule on the members of a
+/// @union, `union` is the DependencyKey that the rule declared for the union.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExportedEdge {
+  pub source: String,
+  pub target: String,
+  pub dependency_key: String,
+  pub provided_param: Option<String>,
+  pub union: Option<String>,
+}
+
+///
+/// An entry that was eliminated from the graph, along with the reasons that it was eliminated.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExportedUnfulfillable {
+  pub entry: ExportedEntry,
+  pub diagnostics: Vec<String>,
+}
+
+///
+/// A structured, deterministically ordered form of a RuleGraph, for consumption by tools.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GraphExport {
+  pub root_param_types: Vec<String>,
+  pub entries: Vec<ExportedEntry>,
+  pub edges: Vec<ExportedEdge>,
+  pub unreachable_rules: Vec<String>,
+  pub unfulfillable: Vec<ExportedUnfulfillable>,
+}
+
+fn sorted_strs<I: IntoIterator<Item = T>, T: ToString>(items: I) -> Vec<String> {
+  let mut strs = items
+    .into_iter()
+    .map(|item| item.to_string())
+    .collect::<Vec<_>>();
+  strs.sort();
+  strs
+}
+
+impl<R: Rule> RuleGraph<R> {
+  ///
+  /// Exports the entries, edges and errors of this graph.
+  ///
+  pub fn export(&self) -> GraphExport {
+    // Assign ids to entries in order of their rendered form, so that exports are stable.
+    let mut entries = self
+      .rule_dependency_edges
+      .iter()
+      .flat_map(|(entry, deps)| {
+        deps
+          .all_dependencies()
+          .cloned()
+          .chain(std::iter::once(Entry::WithDeps(entry.clone())))
+      })
+      .map(|entry| (entry_str(&entry), entry))
+      .collect::<Vec<_>>();
+    entries.sort_by(|(l, _), (r, _)| l.cmp(r));
+    entries.dedup_by(|(l, _), (r, _)| l == r);
+    let ids: HashMap<&str, String> = entries
+      .iter()
+      .enumerate()
+      .map(|(i, (entry_str, _))| (entry_str.as_str(), format!("e{}", i)))
+      .collect();
+
+    let mut edges = Vec::new();
+    for (entry, deps) in &self.rule_dependency_edges {
+      let source = &ids[entry_str(&Entry::WithDeps(entry.clone())).as_str()];
+      for (dependency_key, union_key) in union_dependency_keys(&self.unions, entry) {
+        for target in deps.dependencies.get(&dependency_key).into_iter().flatten() {
+          edges.push(ExportedEdge {
+            source: source.clone(),
+            target: ids[entry_str(target).as_str()].clone(),
+            dependency_key: dependency_key.to_string(),
+            provided_param: dependency_key.provided_param().map(|p| p.to_string()),
+            union: union_key.map(|k| k.to_string()),
+          });
+        }
+      }
+    }
+    edges.sort_by(|l, r| {
+      (&l.source, &l.dependency_key, &l.target).cmp(&(&r.source, &r.dependency_key, &r.target))
+    });
+
+    let mut unfulfillable = self
+      .unfulfillable_rules
+      .iter()
+      .map(|(entry, diagnostics)| {
+        (
+          Entry::WithDeps(entry.clone()),
+          diagnostics
+            .iter()
+            .map(|d| d.to_string())
+            .collect::<BTreeSet<_>>(),
+        )
+      })
+      .collect::<Vec<_>>();
+    unfulfillable.sort_by_cached_key(|(entry, _)| entry_str(entry));
+
+    GraphExport {
+      root_param_types: sorted_strs(self.root_param_types.iter()),
+      entries: entries
+        .iter()
+        .map(|(entry_str, entry)| self.export_entry(ids[entry_str.as_str()].clone(), entry))
+        .collect(),
+      edges,
+      unreachable_rules: sorted_strs(self.unreachable_rules.iter().map(|u| &u.rule)),
+      unfulfillable: unfulfillable
+        .into_iter()
+        .enumerate()
+        .map(|(i, (entry, diagnostics))| ExportedUnfulfillable {
+          entry: self.export_entry(format!("u{}", i), &entry),
+          diagnostics: diagnostics.into_iter().collect(),
+        })
+        .collect(),
+    }
+  }
+
+  fn export_entry(&self, id: String, entry: &Entry<R>) -> ExportedEntry {
+    let (kind, rule, product, params) = match entry {
+      Entry::WithDeps(EntryWithDeps::Root(ref root)) => (
+        ExportedEntryKind::Root,
+        None,
+        root.dependency_key.product(),
+        sorted_strs(&root.params),
+      ),
+      Entry::WithDeps(EntryWithDeps::Inner(InnerEntry {
+        ref rule,
+        ref params,
+      })) => (
+        ExportedEntryKind::Inner,
+        Some(rule.to_string()),
+        self.products[rule],
+        sorted_strs(params),
+      ),
+      Entry::Param(type_id) => (ExportedEntryKind::Param, None, *type_id, vec![]),
+    };
+    ExportedEntry {
+      id,
+      kind,
+      label: entry_str(entry),
+      rule,
+      product: product.to_string(),
+      params,
+    }
+  }
+}
+
+fn xml_escape(s: &str) -> String {
+  s.replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+    .replace('\'', "&apos;")
+}
+
+// The GraphML attributes of the graph, nodes and edges, as (id, domain, name).
+const GRAPHML_KEYS: &[(&str, &str, &str)] = &[
+  ("root_param_types", "graph", "root_param_types"),
+  ("kind", "node", "kind"),
+  ("label", "node", "label"),
+  ("rule", "node", "rule"),
+  ("product", "node", "product"),
+  ("params", "node", "params"),
+  ("diagnostics", "node", "diagnostics"),
+  ("dependency_key", "edge", "dependency_key"),
+  ("provided_param", "edge", "provided_param"),
+  ("union", "edge", "union"),
+];
+
+fn write_graphml_node(
+  f: &mut dyn io::Write,
+  id: &str,
+  kind: &str,
+  data: &[(&str, Option<String>)],
+) -> io::Result<()> {
+  writeln!(f, "    <node id=\"{}\">", xml_escape(id))?;
+  writeln!(f, "      <data key=\"kind\">{}</data>", kind)?;
+  for (key, value) in data {
+    if let Some(value) = value {
+      writeln!(
+        f,
+        "      <data key=\"{}\">{}</data>",
+        key,
+        xml_escape(value)
+      )?;
+    }
+  }
+  writeln!(f, "    </node>")
+}
+
+fn entry_data(entry: &ExportedEntry) -> Vec<(&'static str, Option<String>)> {
+  vec![
+    ("label", Some(entry.label.clone())),
+    ("rule", entry.rule.clone()),
+    ("product", Some(entry.product.clone())),
+    ("params", Some(entry.params.join(","))),
+  ]
+}
+
+impl GraphExport {
+  ///
+  /// Renders this export as GraphML. Unfulfillable entries and unreachable rules are rendered as
+  /// nodes without edges, with kinds of "unfulfillable" and "unreachable" respectively.
+  ///
+  pub fn graphml(&self, f: &mut dyn io::Write) -> io::Result<()> {
+    writeln!(f, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+      f,
+      "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+    )?;
+    for (id, domain, name) in GRAPHML_KEYS {
+      writeln!(
+        f,
+        "  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"string\"/>",
+        id, domain, name
+      )?;
+    }
+    writeln!(f, "  <graph id=\"rule_graph\" edgedefault=\"directed\">")?;
+    writeln!(
+      f,
+      "    <data key=\"root_param_types\">{}</data>",
+      xml_escape(&self.root_param_types.join(","))
+    )?;
+    for entry in &self.entries {
+      write_graphml_node(f, &entry.id, entry.kind.as_str(), &entry_data(entry))?;
+    }
+    for unfulfillable in &self.unfulfillable {
+      let mut data = entry_data(&unfulfillable.entry);
+      data.push(("diagnostics", Some(unfulfillable.diagnostics.join("\n"))));
+      write_graphml_node(f, &unfulfillable.entry.id, "unfulfillable", &data)?;
+    }
+    for (i, rule) in self.unreachable_rules.iter().enumerate() {
+      write_graphml_node(
+        f,
+        &format!("r{}", i),
+        "unreachable",
+        &[("label", Some(rule.clone())), ("rule", Some(rule.clone()))],
+      )?;
+    }
+    for (i, edge) in self.edges.iter().enumerate() {
+      writeln!(
+        f,
+        "    <edge id=\"d{}\" source=\"{}\" target=\"{}\">",
+        i,
+        xml_escape(&edge.source),
+        xml_escape(&edge.target)
+      )?;
+      let data = [
+        ("dependency_key", Some(&edge.dependency_key)),
+        ("provided_param", edge.provided_param.as_ref()),
+        ("union", edge.union.as_ref()),
+      ];
+      for (key, value) in &data {
+        if let Some(value) = value {
+          writeln!(
+            f,
+            "      <data key=\"{}\">{}</data>",
+            key,
+            xml_escape(value)
+          )?;
+        }
+      }
+      writeln!(f, "    </edge>")?;
+    }
+    writeln!(f, "  </graph>")?;
+    writeln!(f, "</graphml>")
+  }
+}
@@ -26,11 +26,18 @@
 // Arc<Mutex> can be more clear than needing to grok Orderings:
 #![allow(clippy::mutex_atomic)]
 
+mod explain;
+mod export;
 mod rules;
 
 use std::collections::{hash_map, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::io;
 
+pub use crate::explain::{ExplainCandidate, ExplainOutcome, Explanation};
+pub use crate::export::{
+  ExportedEdge, ExportedEntry, ExportedEntryKind, ExportedUnfulfillable, GraphExport,
+};
 pub use crate::rules::{DependencyKey, Rule, TypeId};
 
 // TODO: Consider switching to HashSet and dropping the Ord bound from TypeId.
@@ -47,14 +54,64 @@ impl<R: Rule> UnreachableError<R> {
     UnreachableError {
       rule,
       diagnostic: Diagnostic {
+        kind: DiagnosticKind::Unreachable,
         params: ParamTypes::default(),
+        product: None,
         reason: "Was not usable by any other @rule.".to_string(),
         details: vec![],
+        candidates: vec![],
       },
     }
   }
 }
 
+///
+/// A rule that was never used, because a higher priority rule that provides the same product was
+/// always chosen instead.
+///
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ShadowedRule<R: Rule> {
+  pub rule: R,
+  pub product: R::TypeId,
+  pub shadowed_by: Vec<R>,
+}
+
+impl<R: Rule> ShadowedRule<R> {
+  ///
+  /// A Diagnostic describing why the rule was not used. Unlike the Diagnostics of a RuleError, this
+  /// is informational: a shadowed rule was intentionally overridden.
+  ///
+  pub fn diagnostic(&self) -> Diagnostic<R::TypeId> {
+    let mut candidates: Vec<_> = self.shadowed_by.iter().map(|r| r.to_string()).collect();
+    candidates.sort();
+    Diagnostic {
+      kind: DiagnosticKind::Shadowed,
+      params: ParamTypes::new(),
+      product: Some(self.product),
+      reason: self.to_string(),
+      details: vec![],
+      candidates,
+    }
+  }
+}
+
+impl<R: Rule> fmt::Display for ShadowedRule<R> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+      f,
+      "{} (priority {}) was shadowed by: {}",
+      self.rule,
+      self.rule.priority(),
+      self
+        .shadowed_by
+        .iter()
+        .map(|r| format!("{} (priority {})", r, r.priority()))
+        .collect::<Vec<_>>()
+        .join(", "),
+    )
+  }
+}
+
 #[derive(Eq, Hash, PartialEq, Clone, Debug)]
 pub enum EntryWithDeps<R: Rule> {
   Root(RootEntry<R>),
@@ -143,13 +200,141 @@ impl<R: Rule> InnerEntry<R> {
 
 type RuleDependencyEdges<R> = HashMap<EntryWithDeps<R>, RuleEdges<R>>;
 type UnfulfillableRuleMap<R> = HashMap<EntryWithDeps<R>, Vec<Diagnostic<<R as Rule>::TypeId>>>;
+type Unions<T> = HashMap<T, Vec<T>>;
+type SimplifiedEntries<R> = HashMap<EntryWithDeps<R>, Vec<EntryWithDeps<R>>>;
 type ChosenDependency<'a, R> = (&'a <R as Rule>::DependencyKey, &'a Entry<R>);
 
+///
+/// The choices made while constructing a graph which are not visible in its edges: used to report
+/// why candidates for dependencies were not chosen (see `RuleGraph::shadowed_rules` and
+/// `GraphMaker::explain`).
+///
+struct Decisions<R: Rule> {
+  // For each rule that was satisfiable but dropped in favor of higher priority rules while choosing
+  // a dependency, the rules that were chosen instead.
+  shadowing_rules: HashMap<R, HashSet<R>>,
+  // For each entry, the ambiguities which prevented it from being satisfied with some sets of
+  // Params, whether or not it was satisfiable with others.
+  ambiguities: UnfulfillableRuleMap<R>,
+}
+
+// TODO: We can't derive this due to https://github.com/rust-lang/rust/issues/26925, which
+// unnecessarily requires `Rule: Default`.
+impl<R: Rule> Default for Decisions<R> {
+  fn default() -> Self {
+    Decisions {
+      shadowing_rules: HashMap::default(),
+      ambiguities: HashMap::default(),
+    }
+  }
+}
+
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub enum DiagnosticKind {
+  // A dependency of the rule could not be computed by any installed rule or Param.
+  Unfulfillable,
+  // The rule was not used by any other rule, or by any root.
+  Unreachable,
+  // More than one rule could compute a dependency of the rule with the same Params.
+  Ambiguous,
+  // A member of a union could not compute the product that the rule requested for the union.
+  InvalidUnionMember,
+  // The rule was never used because higher priority rules for its product were chosen instead.
+  // This is not an error.
+  Shadowed,
+}
+
+impl DiagnosticKind {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      DiagnosticKind::Unfulfillable => "unfulfillable",
+      DiagnosticKind::Unreachable => "unreachable",
+      DiagnosticKind::Ambiguous => "ambiguous",
+      DiagnosticKind::InvalidUnionMember => "invalid_union_member",
+      DiagnosticKind::Shadowed => "shadowed",
+    }
+  }
+}
+
+///
+/// Describes one reason that a rule was eliminated from the graph.
+///
+/// `params` and `product` are the (params, product) pair that could not be (unambiguously)
+/// computed, and `candidates` are the rules that might have computed it: for an unfulfillable
+/// dependency, those are all rules that produce the product, and for an ambiguous dependency
+/// those are the rules that it was ambiguous between.
+///
 #[derive(Eq, Hash, PartialEq, Clone, Debug)]
 pub struct Diagnostic<T: TypeId> {
-  params: ParamTypes<T>,
-  reason: String,
-  details: Vec<String>,
+  pub kind: DiagnosticKind,
+  pub params: ParamTypes<T>,
+  pub product: Option<T>,
+  pub reason: String,
+  pub details: Vec<String>,
+  pub candidates: Vec<String>,
+}
+
+impl<T: TypeId> fmt::Display for Diagnostic<T> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.details.is_empty() {
+      write!(f, "{}", self.reason)
+    } else {
+      write!(
+        f,
+        "{}:\n      {}",
+        self.reason,
+        self.details.join("\n      ")
+      )
+    }
+  }
+}
+
+///
+/// A rule that was eliminated from the graph, along with the Diagnostics explaining why.
+///
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct RuleError<R: Rule> {
+  pub rule: R,
+  pub diagnostics: Vec<Diagnostic<R::TypeId>>,
+}
+
+impl<R: Rule> fmt::Display for RuleError<R> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let errors = self
+      .diagnostics
+      .iter()
+      .map(|d| d.to_string())
+      .collect::<Vec<_>>()
+      .join("\n    ");
+    write!(f, "{}:\n    {}", self.rule, errors)
+  }
+}
+
+///
+/// The error returned by `RuleGraph::validate`: one RuleError per invalid rule, ordered by their
+/// rendered form. The rules which were shadowed by higher priority rules are included for context,
+/// but are not themselves errors.
+///
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub struct ValidationError<R: Rule> {
+  pub rule_errors: Vec<RuleError<R>>,
+  pub shadowed_rules: Vec<ShadowedRule<R>>,
+}
+
+impl<R: Rule> fmt::Display for ValidationError<R> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let msgs = self
+      .rule_errors
+      .iter()
+      .map(|e| e.to_string())
+      .collect::<Vec<_>>();
+    write!(
+      f,
+      "Rules with errors: {}\n  {}",
+      msgs.len(),
+      msgs.join("\n  ")
+    )
+  }
 }
 
 enum ConstructGraphResult<R: Rule> {
@@ -173,26 +358,143 @@ enum ConstructGraphResult<R: Rule> {
 
 // Given the task index and the root subjects, it produces a rule graph that allows dependency nodes
 // to be found statically rather than dynamically.
+//
+// `unions` maps from union base types to their member types: a dependency that provides a union
+// base as a Param is expanded to one dependency per member of the union.
+//
+// `consumable_params` holds the Param types that each rule could possibly consume, which allows
+// satisfiability to be shared between entries for the same rule that differ only in Params that
+// the rule could never use.
 pub struct GraphMaker<'t, R: Rule> {
   tasks: &'t HashMap<R::TypeId, Vec<R>>,
+  unions: &'t Unions<R::TypeId>,
   root_param_types: ParamTypes<R::TypeId>,
+  consumable_params: HashMap<R, ParamTypes<R::TypeId>>,
 }
 
 impl<'t, R: Rule> GraphMaker<'t, R> {
   pub fn new(
     tasks: &'t HashMap<R::TypeId, Vec<R>>,
+    unions: &'t HashMap<R::TypeId, Vec<R::TypeId>>,
     root_param_types: Vec<R::TypeId>,
   ) -> GraphMaker<'t, R> {
     let root_param_types = root_param_types.into_iter().collect();
+    let consumable_params = Self::consumable_params(tasks, unions);
     GraphMaker {
       tasks,
+      unions,
       root_param_types,
+      consumable_params,
     }
   }
 
-  pub fn sub_graph(&self, param_type: R::TypeId, product_type: R::TypeId) -> RuleGraph<R> {
-    // TODO: Update to support rendering a subgraph given a set of ParamTypes.
-    let param_types = vec![param_type].into_iter().collect();
+  ///
+  /// Computes the Param types that each rule could possibly consume, either directly or via its
+  /// transitive dependencies. A Param type that a dependency provides (via a Get) is not consumed
+  /// from the dependents of the rule that declares the Get.
+  ///
+  /// This is a fixpoint computation over products, because rules may be (mutually) recursive.
+  ///
+  fn consumable_params(
+    tasks: &HashMap<R::TypeId, Vec<R>>,
+    unions: &Unions<R::TypeId>,
+  ) -> HashMap<R, ParamTypes<R::TypeId>> {
+    let rules: Vec<(R::TypeId, &R, Vec<R::DependencyKey>)> = tasks
+      .iter()
+      .flat_map(|(product, rules)| {
+        rules.iter().map(move |rule| {
+          let dependency_keys = expand_union_keys(unions, rule.dependency_keys())
+            .into_iter()
+            .map(|(dependency_key, _)| dependency_key)
+            .collect();
+          (*product, rule, dependency_keys)
+        })
+      })
+      .collect();
+
+    // The Param types that could be consumed while computing each product: a product can always
+    // be consumed as a Param itself.
+    let mut consumable_by_product: HashMap<R::TypeId, ParamTypes<R::TypeId>> = HashMap::new();
+    let consumable_by_rule = |consumable_by_product: &HashMap<R::TypeId, ParamTypes<R::TypeId>>,
+                              dependency_keys: &[R::DependencyKey]| {
+      let mut consumable = ParamTypes::new();
+      for dependency_key in dependency_keys {
+        let product = dependency_key.product();
+        let provided_param = dependency_key.provided_param();
+        consumable.extend(
+          consumable_by_product
+            .get(&product)
+            .into_iter()
+            .flatten()
+            .chain(std::iter::once(&product))
+            .filter(|p| Some(**p) != provided_param),
+        );
+      }
+      consumable
+    };
+
+    loop {
+      let mut changed = false;
+      for (product, _, dependency_keys) in &rules {
+        let consumable = consumable_by_rule(&consumable_by_product, dependency_keys);
+        let product_consumable = consumable_by_product
+          .entry(*product)
+          .or_insert_with(ParamTypes::new);
+        if !consumable.is_subset(product_consumable) {
+          product_consumable.extend(consumable);
+          changed = true;
+        }
+      }
+      if !changed {
+        break;
+      }
+    }
+
+    rules
+      .iter()
+      .map(|(_, rule, dependency_keys)| {
+        (
+          (*rule).clone(),
+          consumable_by_rule(&consumable_by_product, dependency_keys),
+        )
+      })
+      .collect()
+  }
+
+  ///
+  /// For an Inner entry which has Params that its rule could never consume, returns an equivalent
+  /// entry without those Params. Because the two entries are satisfiable in exactly the same way,
+  /// they may share their simplified versions.
+  ///
+  fn canonical_entry(&self, entry: &EntryWithDeps<R>) -> Option<EntryWithDeps<R>> {
+    if let EntryWithDeps::Inner(InnerEntry {
+      ref rule,
+      ref params,
+    }) = entry
+    {
+      let consumable_params = self.consumable_params.get(rule)?;
+      if params.is_subset(consumable_params) {
+        return None;
+      }
+      Some(EntryWithDeps::Inner(InnerEntry {
+        params: params.intersection(consumable_params).cloned().collect(),
+        rule: rule.clone(),
+      }))
+    } else {
+      None
+    }
+  }
+
+  ///
+  /// Constructs the graph below a single root, which computes the given product for the given
+  /// Param types.
+  ///
+  pub fn sub_graph<I: IntoIterator<Item = R::TypeId>>(
+    &self,
+    param_types: I,
+    product_type: R::TypeId,
+  ) -> RuleGraph<R> {
+    let param_types = param_types.into_iter().collect();
 
     if let Some(beginning_root) = self.gen_root_entry(&param_types, product_type) {
       self.construct_graph(vec![beginning_root])
@@ -209,33 +511,48 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
     let mut dependency_edges: RuleDependencyEdges<_> = HashMap::new();
     let mut simplified_entries = HashMap::new();
     let mut unfulfillable_rules: UnfulfillableRuleMap<_> = HashMap::new();
+    let mut decisions = Decisions::default();
 
     for beginning_root in roots {
       self.construct_graph_helper(
         &mut dependency_edges,
         &mut simplified_entries,
         &mut unfulfillable_rules,
+        &mut decisions,
         EntryWithDeps::Root(beginning_root),
       );
     }
 
-    let unreachable_rules = self.unreachable_rules(&dependency_edges);
+    let (unreachable_rules, shadowed_rules) =
+      self.unreachable_rules(&dependency_edges, &decisions.shadowing_rules);
+    let invalid_union_members = self.invalid_union_members(&dependency_edges);
 
     RuleGraph {
       root_param_types: self.root_param_types.clone(),
+      unions: self.unions.clone(),
+      products: self
+        .tasks
+        .iter()
+        .flat_map(|(product, rules)| rules.iter().map(move |rule| (rule.clone(), *product)))
+        .collect(),
       rule_dependency_edges: dependency_edges,
       unfulfillable_rules,
       unreachable_rules,
+      shadowed_rules,
+      invalid_union_members,
     }
   }
 
   ///
-  /// Compute input TaskRules that are unreachable from root entries.
+  /// Compute input TaskRules that are unreachable from root entries, separating out those that
+  /// were unreachable because a higher priority rule for the same product was chosen instead (and
+  /// is itself reachable).
   ///
   fn unreachable_rules(
     &self,
     full_dependency_edges: &RuleDependencyEdges<R>,
-  ) -> Vec<UnreachableError<R>> {
+    shadowing_rules: &HashMap<R, HashSet<R>>,
+  ) -> (Vec<UnreachableError<R>>, Vec<ShadowedRule<R>>) {
     // Walk the graph, starting from root entries.
     let mut entry_stack: Vec<_> = full_dependency_edges
       .keys()
@@ -262,20 +579,90 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
     let reachable_rules: HashSet<_> = visited
       .into_iter()
       .filter_map(|entry| match entry {
-        EntryWithDeps::Inner(InnerEntry { ref rule, .. }) if rule.require_reachable() => {
-          Some(rule.clone())
-        }
+        EntryWithDeps::Inner(InnerEntry { ref rule, .. }) => Some(rule.clone()),
         _ => None,
       })
       .collect();
 
-    self
-      .tasks
-      .values()
-      .flat_map(|r| r.iter())
-      .filter(|r| r.require_reachable() && !reachable_rules.contains(r))
-      .map(|r| UnreachableError::new(r.clone()))
-      .collect()
+    let mut unreachable_rules = Vec::new();
+    let mut shadowed_rules = Vec::new();
+    for (product, rules) in self.tasks {
+      for rule in rules
+        .iter()
+        .filter(|r| r.require_reachable() && !reachable_rules.contains(r))
+      {
+        let shadowed_by: Vec<_> = rules
+          .iter()
+          .filter(|r| {
+            reachable_rules.contains(r)
+              && shadowing_rules
+                .get(rule)
+                .map_or(false, |chosen| chosen.contains(r))
+          })
+          .cloned()
+          .collect();
+        if shadowed_by.is_empty() {
+          unreachable_rules.push(UnreachableError::new(rule.clone()));
+        } else {
+          shadowed_rules.push(ShadowedRule {
+            rule: rule.clone(),
+            product: *product,
+            shadowed_by,
+          });
+        }
+      }
+    }
+    (unreachable_rules, shadowed_rules)
+  }
+
+  ///
+  /// Compute Diagnostics for the members of unions which could not compute the product that a
+  /// rule requested for their union.
+  ///
+  fn invalid_union_members(
+    &self,
+    full_dependency_edges: &RuleDependencyEdges<R>,
+  ) -> HashMap<R, Vec<Diagnostic<R::TypeId>>> {
+    let mut invalid_union_members = HashMap::new();
+    for (entry, edges) in full_dependency_edges {
+      let rule = if let EntryWithDeps::Inner(InnerEntry { ref rule, .. }) = entry {
+        rule
+      } else {
+        continue;
+      };
+      for (dependency_key, union_key) in union_dependency_keys(self.unions, entry) {
+        let (union_key, member) = match (union_key, dependency_key.provided_param()) {
+          (Some(union_key), Some(member)) if edges.entry_for(&dependency_key).is_none() => {
+            (union_key, member)
+          }
+          _ => continue,
+        };
+        let product = dependency_key.product();
+        let mut params = entry.params().clone();
+        params.extend(dependency_key.provided_param());
+        let mut candidates = self
+          .tasks
+          .get(&product)
+          .map(|rules| rules.iter().map(|r| r.to_string()).collect::<Vec<_>>())
+          .unwrap_or_else(Vec::new);
+        candidates.sort();
+        invalid_union_members
+          .entry(rule.clone())
+          .or_insert_with(Vec::new)
+          .push(Diagnostic {
+            kind: DiagnosticKind::InvalidUnionMember,
+            reason: format!(
+              "No rule was available to compute {} for {}, which is a member of the @union in {}",
+              product, member, union_key,
+            ),
+            params,
+            product: Some(product),
+            details: vec![],
+            candidates,
+          });
+      }
+    }
+    invalid_union_members
   }
 
   ///
@@ -289,13 +676,24 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
   fn construct_graph_helper(
     &self,
     rule_dependency_edges: &mut RuleDependencyEdges<R>,
-    all_simplified_entries: &mut HashMap<EntryWithDeps<R>, Vec<EntryWithDeps<R>>>,
+    all_simplified_entries: &mut SimplifiedEntries<R>,
     unfulfillable_rules: &mut UnfulfillableRuleMap<R>,
+    decisions: &mut Decisions<R>,
     entry: EntryWithDeps<R>,
   ) -> ConstructGraphResult<R> {
+    let canonical_entry = self.canonical_entry(&entry);
     if let Some(simplified) = all_simplified_entries.get(&entry) {
       // A simplified equivalent entry has already been computed, return it.
       return ConstructGraphResult::Fulfilled(simplified.clone());
+    } else if let Some(simplified) = canonical_entry
+      .as_ref()
+      .and_then(|ce| all_simplified_entries.get(ce))
+      .cloned()
+    {
+      // The simplified versions of an equivalent entry (with only the Params that the rule could
+      // consume) have already been computed: they are valid for this entry as well.
+      all_simplified_entries.insert(entry, simplified.clone());
+      return ConstructGraphResult::Fulfilled(simplified);
     } else if unfulfillable_rules.get(&entry).is_some() {
       // The rule is unfulfillable.
       return ConstructGraphResult::Unfulfillable;
@@ -332,16 +730,27 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
     //
     // This is a `loop` because if we discover that this entry needs to complete in order to break
     // a cycle on itself, it will re-compute dependencies after having partially-completed.
-    loop {
+    let result = loop {
       if let Ok(res) = self.construct_dependencies(
         rule_dependency_edges,
         all_simplified_entries,
         unfulfillable_rules,
+        decisions,
         entry.clone(),
       ) {
         break res;
       }
+    };
+
+    // Once an entry has been completely fulfilled, record its simplified versions for its canonical
+    // entry as well. Unfulfillable entries are not shared, because their Diagnostics describe the
+    // Params that were actually available.
+    if let (Some(canonical_entry), ConstructGraphResult::Fulfilled(ref simplified)) =
+      (canonical_entry, &result)
+    {
+      all_simplified_entries.insert(canonical_entry, simplified.clone());
     }
+    result
   }
 
   ///
@@ -355,17 +764,25 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
   fn construct_dependencies(
     &self,
     rule_dependency_edges: &mut RuleDependencyEdges<R>,
-    all_simplified_entries: &mut HashMap<EntryWithDeps<R>, Vec<EntryWithDeps<R>>>,
+    all_simplified_entries: &mut SimplifiedEntries<R>,
     unfulfillable_rules: &mut UnfulfillableRuleMap<R>,
+    decisions: &mut Decisions<R>,
     entry: EntryWithDeps<R>,
   ) -> Result<ConstructGraphResult<R>, ()> {
     let mut fulfillable_candidates_by_key = HashMap::new();
     let mut cycled_on = HashSet::new();
     let mut unfulfillable_diagnostics = Vec::new();
 
-    let dependency_keys = entry.dependency_keys();
+    let dependency_keys = union_dependency_keys(self.unions, &entry);
+    // Dependencies on the members of unions are optional: a member which cannot compute the
+    // product is reported separately, rather than making the entire rule unfulfillable.
+    let union_member_keys: HashSet<_> = dependency_keys
+      .iter()
+      .filter(|(_, union_key)| union_key.is_some())
+      .map(|(dependency_key, _)| *dependency_key)
+      .collect();
 
-    for dependency_key in dependency_keys {
+    for (dependency_key, _) in dependency_keys {
       let product = dependency_key.product();
       let provided_param = dependency_key.provided_param();
       let params = if let Some(provided_param) = provided_param {
@@ -389,6 +806,7 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
             rule_dependency_edges,
             all_simplified_entries,
             unfulfillable_rules,
+            decisions,
             c,
           ) {
             ConstructGraphResult::Unfulfillable => {}
@@ -435,10 +853,20 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
         continue;
       }
 
-      if fulfillable_candidates.is_empty() {
+      if fulfillable_candidates.is_empty() && union_member_keys.contains(&dependency_key) {
+        fulfillable_candidates_by_key.remove(&dependency_key);
+      } else if fulfillable_candidates.is_empty() {
         // If no candidates were fulfillable, this rule is not fulfillable.
+        let mut candidates = self
+          .tasks
+          .get(&product)
+          .map(|rules| rules.iter().map(|r| r.to_string()).collect::<Vec<_>>())
+          .unwrap_or_else(Vec::new);
+        candidates.sort();
         unfulfillable_diagnostics.push(Diagnostic {
+          kind: DiagnosticKind::Unfulfillable,
           params: params.clone(),
+          product: Some(product),
           reason: if params.is_empty() {
             format!(
               "No rule was available to compute {}. Maybe declare it as a RootRule({})?",
@@ -453,6 +881,7 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
             )
           },
           details: vec![],
+          candidates,
         });
       }
     }
@@ -473,12 +902,13 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
     // No dependencies were completely unfulfillable (although some may have been cyclic).
     let flattened_fulfillable_candidates_by_key = fulfillable_candidates_by_key
       .into_iter()
-      .map(|(k, candidate_group)| (k, candidate_group.into_iter().flatten().collect()))
+      .map(|(k, candidate_group)| (k, candidate_group.into_iter().flatten().collect::<Vec<_>>()))
+      .filter(|(k, candidates)| !candidates.is_empty() || !union_member_keys.contains(k))
       .collect::<Vec<_>>();
 
     // Generate one Entry per legal combination of parameters.
     let simplified_entries =
-      match Self::monomorphize(&entry, &flattened_fulfillable_candidates_by_key) {
+      match Self::monomorphize(&entry, &flattened_fulfillable_candidates_by_key, decisions) {
         Ok(se) => se,
         Err(ambiguous_diagnostics) => {
           // At least one combination of the dependencies was ambiguous.
@@ -530,41 +960,43 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
   /// Given an Entry and a mapping of all legal sources of each of its dependencies, generates a
   /// simplified Entry for each legal combination of parameters.
   ///
-  /// Computes the union of all parameters used by the dependencies, and then uses the powerset of
-  /// used parameters to filter the possible combinations of dependencies. If multiple choices of
+  /// Computes the distinct unions of the sets of parameters used by the dependencies, and then
+  /// uses those to filter the possible combinations of dependencies. If multiple choices of
   /// dependencies are possible for any set of parameters, then the graph is ambiguous.
   ///
   fn monomorphize(
     entry: &EntryWithDeps<R>,
     deps: &[(R::DependencyKey, Vec<Entry<R>>)],
+    decisions: &mut Decisions<R>,
   ) -> Result<RuleDependencyEdges<R>, Vec<Diagnostic<R::TypeId>>> {
-    // Collect the powerset of the union of used parameters, ordered by set size.
-    let params_powerset: Vec<Vec<R::TypeId>> = {
-      let mut all_used_params = BTreeSet::new();
-      for (key, inputs) in deps {
-        let provided_param = key.provided_param();
-        for input in inputs {
-          all_used_params.extend(
-            input
-              .params()
-              .into_iter()
-              .filter(|p| Some(*p) != provided_param),
-          );
-        }
-      }
-      // Compute the powerset ordered by ascending set size.
-      let all_used_params = all_used_params.into_iter().collect::<Vec<_>>();
-      let mut param_sets = Self::powerset(&all_used_params).collect::<Vec<_>>();
-      param_sets.sort_by(|l, r| l.len().cmp(&r.len()));
-      param_sets
-    };
+    // Every candidate is satisfiable with the Params of the entry itself, so prefer the highest
+    // priority candidates for each dependency before minimizing the Params that are used: otherwise
+    // a rule would be chosen over a higher priority rule just because it consumes fewer Params.
+    let deps = deps
+      .iter()
+      .map(|(key, inputs)| {
+        let preferred = Self::prefer_highest_priority(inputs.iter().collect(), decisions);
+        (*key, preferred.into_iter().cloned().collect())
+      })
+      .collect::<Vec<(R::DependencyKey, Vec<Entry<R>>)>>();
 
-    // Then, for the powerset of used parameters, determine which dependency combinations are
+    // Collect the unions of the sets of used parameters, ordered by set size.
+    let param_set_unions = Self::param_set_unions(deps.iter().flat_map(|(key, inputs)| {
+      let provided_param = key.provided_param();
+      inputs.iter().map(move |input| {
+        input
+          .params()
+          .into_iter()
+          .filter(|p| Some(*p) != provided_param)
+          .collect()
+      })
+    }));
+
+    // Then, for each union of used parameters, determine which dependency combinations are
     // satisfiable.
     let mut combinations: HashMap<EntryWithDeps<_>, _> = HashMap::new();
     let mut diagnostics = Vec::new();
-    for available_params in params_powerset {
-      let available_params = available_params.into_iter().collect();
+    for available_params in param_set_unions {
       // If a subset of these parameters is already satisfied, skip. This has the effect of
       // selecting the smallest sets of parameters that will satisfy a rule.
       // NB: This scan over satisfied sets is linear, but should have a small N.
@@ -575,7 +1007,7 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
         continue;
       }
 
-      match Self::choose_dependencies(&available_params, deps) {
+      match Self::choose_dependencies(&available_params, &deps, decisions) {
         Ok(Some(inputs)) => {
           let mut rule_edges = RuleEdges::default();
           for (key, input) in inputs {
@@ -588,6 +1020,17 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
       }
     }
 
+    // Record any ambiguities, which explain why candidates were not chosen even if other
+    // combinations were satisfiable. This entry may be recomputed to break a cycle, in which case
+    // only the final computation counts.
+    if diagnostics.is_empty() {
+      decisions.ambiguities.remove(entry);
+    } else {
+      decisions
+        .ambiguities
+        .insert(entry.clone(), diagnostics.clone());
+    }
+
     // If none of the combinations was satisfiable, return the generated diagnostics.
     if combinations.is_empty() {
       Err(diagnostics)
@@ -598,7 +1041,7 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
 
   ///
   /// Given a set of available Params, choose one combination of satisfiable Entry dependencies if
-  /// it exists (it may not, because we're searching for sets of legal parameters in the powerset
+  /// it exists (it may not, because we're searching for sets of legal parameters among the unions
   /// of all used params).
   ///
   /// If an ambiguity is detected in rule dependencies (ie, if multiple rules are satisfiable for
@@ -607,6 +1050,7 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
   fn choose_dependencies<'a>(
     available_params: &ParamTypes<R::TypeId>,
     deps: &'a [(R::DependencyKey, Vec<Entry<R>>)],
+    decisions: &mut Decisions<R>,
   ) -> Result<Option<Vec<ChosenDependency<'a, R>>>, Diagnostic<R::TypeId>> {
     let mut combination = Vec::new();
     for (key, input_entries) in deps {
@@ -621,7 +1065,7 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
         })
         .collect::<Vec<_>>();
 
-      let chosen_entries = Self::choose_dependency(satisfiable_entries);
+      let chosen_entries = Self::choose_dependency(satisfiable_entries, decisions);
       match chosen_entries.len() {
         0 => {
           return Ok(None);
@@ -636,15 +1080,20 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
             _ => " with parameter types ",
           };
 
+          let mut candidates: Vec<_> = chosen_entries.into_iter().map(entry_str).collect();
+          candidates.sort();
           return Err(Diagnostic {
+            kind: DiagnosticKind::Ambiguous,
             params: available_params.clone(),
+            product: Some(key.product()),
             reason: format!(
               "Ambiguous rules to compute {}{}{}",
               key,
               params_clause,
               params_str(&available_params),
             ),
-            details: chosen_entries.into_iter().map(entry_str).collect(),
+            details: candidates.clone(),
+            candidates,
           });
         }
       }
@@ -653,7 +1102,14 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
     Ok(Some(combination))
   }
 
-  fn choose_dependency<'a>(satisfiable_entries: Vec<&'a Entry<R>>) -> Vec<&'a Entry<R>> {
+  ///
+  /// Chooses among the satisfiable sources of a dependency, recording any rules that were dropped
+  /// in favor of higher priority rules in `decisions`.
+  ///
+  fn choose_dependency<'a>(
+    satisfiable_entries: Vec<&'a Entry<R>>,
+    decisions: &mut Decisions<R>,
+  ) -> Vec<&'a Entry<R>> {
     if satisfiable_entries.is_empty() {
       // No source of this dependency was satisfiable with these Params.
       return vec![];
@@ -661,9 +1117,13 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
       return satisfiable_entries;
     }
 
-    // We prefer the non-ambiguous entry with the smallest set of Params, as that minimizes Node
-    // identities in the graph and biases toward receiving values from dependencies (which do not
-    // affect our identity) rather than dependents.
+    // We prefer the rule(s) with the highest priority, which allows a rule to explicitly shadow
+    // another rule for the same product, regardless of which Params each consumes.
+    let satisfiable_entries = Self::prefer_highest_priority(satisfiable_entries, decisions);
+
+    // Then, we prefer the non-ambiguous entry with the smallest set of Params, as that minimizes
+    // Node identities in the graph and biases toward receiving values from dependencies (which do
+    // not affect our identity) rather than dependents.
     let mut minimum_param_set_size = ::std::usize::MAX;
     let mut rules = Vec::new();
     for satisfiable_entry in satisfiable_entries {
@@ -683,22 +1143,58 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
     rules
   }
 
-  fn powerset<'a, T: Clone>(slice: &'a [T]) -> impl Iterator<Item = Vec<T>> + 'a {
-    (0..(1 << slice.len())).map(move |mask| {
-      let mut ss = Vec::new();
-      let mut bitset = mask;
-      while bitset > 0 {
-        // isolate the rightmost bit to select one item
-        let rightmost: u64 = bitset & !(bitset - 1);
-        // turn the isolated bit into an array index
-        let idx = rightmost.trailing_zeros();
-        let item = &slice[idx as usize];
-        ss.push(item.clone());
-        // zero the trailing bit
-        bitset &= bitset - 1;
+  ///
+  /// Filters the given entries to the rule(s) with the highest priority, and records the rules
+  /// that they shadow. Params have no priority, so they are never shadowed.
+  ///
+  fn prefer_highest_priority<'a>(
+    entries: Vec<&'a Entry<R>>,
+    decisions: &mut Decisions<R>,
+  ) -> Vec<&'a Entry<R>> {
+    let maximum_priority = entries.iter().filter_map(|e| entry_priority(e)).max();
+    let (chosen, dropped): (Vec<_>, Vec<_>) =
+      entries.into_iter().partition(|e| match entry_priority(e) {
+        Some(priority) => Some(priority) == maximum_priority,
+        None => true,
+      });
+    for dropped_entry in dropped {
+      if let Some(dropped_rule) = entry_rule(dropped_entry) {
+        decisions
+          .shadowing_rules
+          .entry(dropped_rule.clone())
+          .or_insert_with(HashSet::new)
+          .extend(chosen.iter().filter_map(|e| entry_rule(e)).cloned());
       }
-      ss
-    })
+    }
+    chosen
+  }
+
+  ///
+  /// Computes the distinct unions of any number of the given sets of Params, ordered by ascending
+  /// size.
+  ///
+  /// When choosing dependencies, these are the only sets of Params that need to be considered: any
+  /// other set of Params makes exactly the same dependencies satisfiable as the largest of these
+  /// unions that it contains. In the common case, this is a much smaller collection than the
+  /// powerset of all used Params.
+  ///
+  fn param_set_unions<I>(param_sets: I) -> Vec<ParamTypes<R::TypeId>>
+  where
+    I: IntoIterator<Item = ParamTypes<R::TypeId>>,
+  {
+    let mut unions = HashSet::new();
+    unions.insert(ParamTypes::new());
+    for param_set in param_sets.into_iter().collect::<HashSet<_>>() {
+      let extended = unions
+        .iter()
+        .filter(|union| !param_set.is_subset(union))
+        .map(|union| union.union(&param_set).cloned().collect())
+        .collect::<Vec<ParamTypes<_>>>();
+      unions.extend(extended);
+    }
+    let mut unions = unions.into_iter().collect::<Vec<_>>();
+    unions.sort_by(|l, r| l.len().cmp(&r.len()).then_with(|| l.cmp(r)));
+    unions
   }
 
   fn gen_root_entries(&self, product_types: &HashSet<R::TypeId>) -> Vec<RootEntry<R>> {
@@ -755,6 +1251,7 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
 ///
 ///
 /// `root_param_types` the root parameter types that this graph was generated with.
+/// `products` A map from rules to the product types that they compute.
 /// `root_dependencies` A map from root rules, ie rules representing the expected selector / subject
 ///   types for requests, to the rules that can fulfill them.
 /// `rule_dependency_edges` A map from rule entries to the rule entries they depend on.
@@ -764,9 +1261,13 @@ impl<'t, R: Rule> GraphMaker<'t, R> {
 #[derive(Debug)]
 pub struct RuleGraph<R: Rule> {
   root_param_types: ParamTypes<R::TypeId>,
+  unions: Unions<R::TypeId>,
+  products: HashMap<R, R::TypeId>,
   rule_dependency_edges: RuleDependencyEdges<R>,
   unfulfillable_rules: UnfulfillableRuleMap<R>,
   unreachable_rules: Vec<UnreachableError<R>>,
+  shadowed_rules: Vec<ShadowedRule<R>>,
+  invalid_union_members: HashMap<R, Vec<Diagnostic<R::TypeId>>>,
 }
 
 // TODO: We can't derive this due to https://github.com/rust-lang/rust/issues/26925, which
@@ -775,9 +1276,13 @@ impl<R: Rule> Default for RuleGraph<R> {
   fn default() -> Self {
     RuleGraph {
       root_param_types: ParamTypes::default(),
+      unions: Unions::default(),
+      products: HashMap::default(),
       rule_dependency_edges: RuleDependencyEdges::default(),
       unfulfillable_rules: UnfulfillableRuleMap::default(),
       unreachable_rules: Vec::default(),
+      shadowed_rules: Vec::default(),
+      invalid_union_members: HashMap::default(),
     }
   }
 }
@@ -796,6 +1301,62 @@ pub fn entry_str<R: Rule>(entry: &Entry<R>) -> String {
   }
 }
 
+///
+/// Returns the DependencyKeys of the given entry, expanding each key that provides a union base as
+/// its Param into one key per member of the union. Each expanded key is paired with the key that
+/// it was expanded from.
+///
+fn union_dependency_keys<R: Rule>(
+  unions: &Unions<R::TypeId>,
+  entry: &EntryWithDeps<R>,
+) -> Vec<(R::DependencyKey, Option<R::DependencyKey>)> {
+  expand_union_keys(unions, entry.dependency_keys())
+}
+
+fn expand_union_keys<K: DependencyKey>(
+  unions: &Unions<K::TypeId>,
+  dependency_keys: Vec<K>,
+) -> Vec<(K, Option<K>)> {
+  dependency_keys
+    .into_iter()
+    .flat_map(|dependency_key| {
+      let members = dependency_key
+        .provided_param()
+        .and_then(|param| unions.get(&param));
+      if let Some(members) = members {
+        members
+          .iter()
+          .map(|member| {
+            (
+              dependency_key.for_union_member(*member),
+              Some(dependency_key),
+            )
+          })
+          .collect()
+      } else {
+        vec![(dependency_key, None)]
+      }
+    })
+    .collect()
+}
+
+///
+/// The rule for an Entry, or None if the Entry is not a rule.
+///
+fn entry_rule<R: Rule>(entry: &Entry<R>) -> Option<&R> {
+  match entry {
+    Entry::WithDeps(EntryWithDeps::Inner(InnerEntry { ref rule, .. })) => Some(rule),
+    _ => None,
+  }
+}
+
+///
+/// The priority of the rule for an Entry, or None if the Entry is not a rule.
+///
+fn entry_priority<R: Rule>(entry: &Entry<R>) -> Option<i32> {
+  entry_rule(entry).map(Rule::priority)
+}
+
 fn entry_with_deps_str<R: Rule>(entry: &EntryWithDeps<R>) -> String {
   match entry {
     EntryWithDeps::Inner(InnerEntry {
@@ -815,8 +1376,12 @@ fn entry_with_deps_str<R: Rule>(entry: &EntryWithDeps<R>) -> String {
 }
 
 impl<R: Rule> RuleGraph<R> {
-  pub fn new(tasks: &HashMap<R::TypeId, Vec<R>>, root_param_types: Vec<R::TypeId>) -> RuleGraph<R> {
-    GraphMaker::new(tasks, root_param_types).full_graph()
+  pub fn new(
+    tasks: &HashMap<R::TypeId, Vec<R>>,
+    unions: &HashMap<R::TypeId, Vec<R::TypeId>>,
+    root_param_types: Vec<R::TypeId>,
+  ) -> RuleGraph<R> {
+    GraphMaker::new(tasks, unions, root_param_types).full_graph()
   }
 
   pub fn find_root_edges<I: IntoIterator<Item = R::TypeId>>(
@@ -921,9 +1486,15 @@ impl<R: Rule> RuleGraph<R> {
     }
   }
 
-  pub fn validate(&self) -> Result<(), String> {
+  ///
+  /// Returns a ValidationError describing each rule that was eliminated from the graph, or which
+  /// was not reachable from any root.
+  ///
+  pub fn validate(&self) -> Result<(), ValidationError<R>> {
     let mut collated_errors: HashMap<R, Vec<Diagnostic<_>>> = HashMap::new();
 
+    // Rules which were shadowed by higher priority rules are not errors: they were intentionally
+    // overridden.
     let used_rules: HashSet<_> = self
       .rule_dependency_edges
       .keys()
@@ -931,6 +1502,7 @@ impl<R: Rule> RuleGraph<R> {
         EntryWithDeps::Inner(InnerEntry { ref rule, .. }) => Some(rule),
         _ => None,
       })
+      .chain(self.shadowed_rules.iter().map(|s| &s.rule))
       .collect();
 
     // Collect and dedupe rule diagnostics, preferring to render an unfulfillable error for a rule
@@ -950,6 +1522,12 @@ impl<R: Rule> RuleGraph<R> {
         _ => {}
       }
     }
+    for (rule, diagnostics) in &self.invalid_union_members {
+      rule_diagnostics
+        .entry(rule)
+        .or_insert_with(Vec::new)
+        .extend(diagnostics.iter().cloned());
+    }
     for (rule, diagnostics) in rule_diagnostics {
       for d in diagnostics {
         collated_errors
@@ -963,29 +1541,33 @@ impl<R: Rule> RuleGraph<R> {
       return Ok(());
     }
 
-    let mut msgs: Vec<String> = collated_errors
+    let mut rule_errors: Vec<RuleError<R>> = collated_errors
       .into_iter()
       .map(|(rule, mut diagnostics)| {
         diagnostics.sort_by(|l, r| l.reason.cmp(&r.reason));
         diagnostics.dedup_by(|l, r| l.reason == r.reason);
-        let errors = diagnostics
-          .into_iter()
-          .map(|mut d| {
-            if d.details.is_empty() {
-              d.reason.clone()
-            } else {
-              d.details.sort();
-              format!("{}:\n      {}", d.reason, d.details.join("\n      "))
-            }
-          })
-          .collect::<Vec<_>>()
-          .join("\n    ");
-        format!("{}:\n    {}", rule, errors)
+        for d in &mut diagnostics {
+          d.details.sort();
+        }
+        RuleError { rule, diagnostics }
       })
       .collect();
-    msgs.sort();
+    rule_errors.sort_by_cached_key(|e| e.to_string());
+
+    Err(ValidationError {
+      rule_errors,
+      shadowed_rules: self.shadowed_rules(),
+    })
+  }
 
-    Err(format!("Rules with errors: {}\n  {}", msgs.len(), msgs.join("\n  ")).to_string())
+  ///
+  /// Returns the rules that were never used because higher priority rules were used instead,
+  /// ordered by their rendered form.
+  ///
+  pub fn shadowed_rules(&self) -> Vec<ShadowedRule<R>> {
+    let mut shadowed_rules = self.shadowed_rules.clone();
+    shadowed_rules.sort_by_cached_key(|s| s.to_string());
+    shadowed_rules
   }
 
   pub fn visualize(&self, f: &mut dyn io::Write) -> io::Result<()> {
@@ -1026,27 +1608,61 @@ impl<R: Rule> RuleGraph<R> {
     writeln!(f, "{}", root_rule_strs.join("\n"))?;
 
     writeln!(f, "  // internal entries")?;
-    let mut internal_rule_strs = self
-      .rule_dependency_edges
-      .iter()
-      .filter_map(|(k, deps)| match k {
-        EntryWithDeps::Inner(_) => {
-          let mut deps_strs = deps
-            .all_dependencies()
-            .map(|d| format!("\"{}\"", entry_str(d)))
-            .collect::<Vec<String>>();
-          deps_strs.sort();
-          Some(format!(
-            "    \"{}\" -> {{{}}}",
-            entry_with_deps_str(k),
-            deps_strs.join(" ")
-          ))
+    let mut internal_rule_strs = Vec::new();
+    let mut union_strs = Vec::new();
+    for (k, deps) in &self.rule_dependency_edges {
+      if let EntryWithDeps::Root(_) = k {
+        continue;
+      }
+      let entry_str_k = entry_with_deps_str(k);
+
+      // Dependencies on the members of a union are rendered as fanning out from the union.
+      let mut union_members: HashMap<R::DependencyKey, Vec<String>> = HashMap::new();
+      let mut union_member_keys = HashSet::new();
+      for (dependency_key, union_key) in union_dependency_keys(&self.unions, k) {
+        if let Some(union_key) = union_key {
+          union_member_keys.insert(dependency_key);
+          let members = union_members.entry(union_key).or_insert_with(Vec::new);
+          members.extend(
+            deps
+              .entry_for(&dependency_key)
+              .map(|d| format!("\"{}\"", entry_str(d))),
+          );
         }
-        _ => None,
-      })
-      .collect::<Vec<String>>();
+      }
+
+      let mut deps_strs = deps
+        .dependencies
+        .iter()
+        .filter(|(dependency_key, _)| !union_member_keys.contains(dependency_key))
+        .flat_map(|(_, entries)| entries.iter())
+        .map(|d| format!("\"{}\"", entry_str(d)))
+        .collect::<Vec<String>>();
+      for (union_key, mut members) in union_members {
+        let union_str = format!("{} (@union) for {}", union_key, entry_str_k);
+        members.sort();
+        deps_strs.push(format!("\"{}\"", union_str));
+        union_strs.push(format!(
+          "    \"{}\" [shape=diamond]\n    \"{}\" -> {{{}}}",
+          union_str,
+          union_str,
+          members.join(" ")
+        ));
+      }
+      deps_strs.sort();
+      internal_rule_strs.push(format!(
+        "    \"{}\" -> {{{}}}",
+        entry_str_k,
+        deps_strs.join(" ")
+      ));
+    }
     internal_rule_strs.sort();
     writeln!(f, "{}", internal_rule_strs.join("\n"))?;
+    if !union_strs.is_empty() {
+      union_strs.sort();
+      writeln!(f, "  // union fan-out")?;
+      writeln!(f, "{}", union_strs.join("\n"))?;
+    }
     writeln!(f, "}}")
   }
 }
@@ -31,6 +31,12 @@ pub trait DependencyKey: Clone + Copy + Debug + Display + Hash + Eq + Sized + 's
   /// Returns the Param (input) type for this dependency, if it provides one.
   ///
   fn provided_param(&self) -> Option<Self::TypeId>;
+
+  ///
+  /// For a dependency that provides a union base as its Param, returns the equivalent dependency
+  /// for the given member of the union.
+  ///
+  fn for_union_member(&self, member: Self::TypeId) -> Self;
 }
 
 pub trait Rule: Clone + Debug + Display + Hash + Eq + Sized + 'static {
@@ -46,4 +52,10 @@ pub trait Rule: Clone + Debug + Display + Hash + Eq + Sized + 'static {
   /// True if this rule implementation should be required to be reachable in the RuleGraph.
   ///
   fn require_reachable(&self) -> bool;
+
+  ///
+  /// The priority of this Rule relative to other Rules that provide the same product: when more
+  /// than one Rule could otherwise be used with the same Params, the highest priority Rule wins.
+  ///
+  fn priority(&self) -> i32;
 }
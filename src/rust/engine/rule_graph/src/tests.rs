@@ -1,32 +1,726 @@
-use crate::RuleGraph;
+use crate::{
+  Decisions, DiagnosticKind, Entry, EntryWithDeps, ExplainOutcome, ExportedEntryKind, GraphMaker,
+  InnerEntry, RuleGraph,
+};
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
+use std::time::Instant;
 
 #[test]
 fn create_and_validate_valid() {
-  let rules = vec![("a", vec![Rule("a_from_b", vec![DependencyKey("b", None)])])]
-    .into_iter()
-    .collect();
+  let rules = vec![(
+    "a",
+    vec![Rule("a_from_b", vec![DependencyKey("b", None)], 0)],
+  )]
+  .into_iter()
+  .collect();
   let roots = vec!["b"];
-  let graph = RuleGraph::new(&rules, roots);
+  let graph = RuleGraph::new(&rules, &HashMap::new(), roots);
 
   graph.validate().unwrap();
 }
 
 #[test]
 fn create_and_validate_no_root() {
-  let rules = vec![("a", vec![Rule("a_from_b", vec![DependencyKey("b", None)])])]
-    .into_iter()
-    .collect();
+  let rules = vec![(
+    "a",
+    vec![Rule("a_from_b", vec![DependencyKey("b", None)], 0)],
+  )]
+  .into_iter()
+  .collect();
   let roots = vec![];
-  let graph = RuleGraph::new(&rules, roots);
+  let graph = RuleGraph::new(&rules, &HashMap::new(), roots);
 
   assert!(graph
     .validate()
     .err()
     .unwrap()
+    .to_string()
     .contains("No rule was available to compute DependencyKey(\"b\", None)."));
 }
 
+#[test]
+fn validate_unfulfillable_diagnostics() {
+  let rules = vec![
+    (
+      "a",
+      vec![Rule("a_from_b", vec![DependencyKey("b", None)], 0)],
+    ),
+    (
+      "b",
+      vec![Rule("b_from_c", vec![DependencyKey("c", None)], 0)],
+    ),
+  ]
+  .into_iter()
+  .collect();
+  let roots = vec!["d"];
+  let graph = RuleGraph::new(&rules, &HashMap::new(), roots);
+
+  let error = graph.validate().err().unwrap();
+  let rules_with_errors = error
+    .rule_errors
+    .iter()
+    .map(|e| e.rule.0)
+    .collect::<Vec<_>>();
+  assert_eq!(vec!["a_from_b", "b_from_c"], rules_with_errors);
+
+  let a_from_b = &error.rule_errors[0].diagnostics;
+  assert_eq!(1, a_from_b.len());
+  assert_eq!(DiagnosticKind::Unfulfillable, a_from_b[0].kind);
+  assert_eq!(
+    vec!["d"],
+    a_from_b[0].params.iter().cloned().collect::<Vec<_>>()
+  );
+  assert_eq!(Some("b"), a_from_b[0].product);
+  assert_eq!(
+    vec!["Rule(\"b_from_c\", [DependencyKey(\"c\", None)], 0)".to_owned()],
+    a_from_b[0].candidates
+  );
+
+  let b_from_c = &error.rule_errors[1].diagnostics;
+  assert_eq!(Some("c"), b_from_c[0].product);
+  assert!(b_from_c[0].candidates.is_empty());
+}
+
+#[test]
+fn validate_ambiguous_diagnostics() {
+  let rules = vec![
+    (
+      "a",
+      vec![Rule("a_from_b", vec![DependencyKey("b", None)], 0)],
+    ),
+    (
+      "b",
+      vec![
+        Rule("b_from_c", vec![DependencyKey("c", None)], 0),
+        Rule("b_from_c_too", vec![DependencyKey("c", None)], 0),
+      ],
+    ),
+  ]
+  .into_iter()
+  .collect();
+  let roots = vec!["c"];
+  let graph = RuleGraph::new(&rules, &HashMap::new(), roots);
+
+  let error = graph.validate().err().unwrap();
+  let a_from_b = error
+    .rule_errors
+    .iter()
+    .find(|e| e.rule.0 == "a_from_b")
+    .unwrap();
+  let diagnostic = &a_from_b.diagnostics[0];
+  assert_eq!(DiagnosticKind::Ambiguous, diagnostic.kind);
+  assert_eq!(Some("b"), diagnostic.product);
+  assert_eq!(2, diagnostic.candidates.len());
+  assert!(error.to_string().contains("Ambiguous rules to compute"));
+}
+
+#[test]
+fn explain_missing_param() {
+  let rules = vec![
+    (
+      "a",
+      vec![
+        Rule("a_from_b", vec![DependencyKey("b", None)], 0),
+        Rule("a_from_c", vec![DependencyKey("c", None)], 0),
+      ],
+    ),
+    (
+      "b",
+      vec![Rule("b_from_d", vec![DependencyKey("d", None)], 0)],
+    ),
+  ]
+  .into_iter()
+  .collect();
+  let unions = HashMap::new();
+  let graph_maker = GraphMaker::new(&rules, &unions, vec!["c"]);
+  let explanation = graph_maker.explain(vec!["c"], "a");
+
+  let outcomes = explanation
+    .candidates
+    .iter()
+    .map(|c| c.outcome.clone())
+    .collect::<Vec<_>>();
+  assert_eq!(2, outcomes.len());
+  match outcomes[0] {
+    ExplainOutcome::MissingParam(ref reasons) => {
+      assert!(reasons[0].contains("No rule was available to compute DependencyKey(\"b\", None)"))
+    }
+    ref o => panic!("Expected a missing param for a_from_b, got {:?}", o),
+  }
+  assert_eq!(ExplainOutcome::Chosen, outcomes[1]);
+  assert_eq!(
+    vec![vec!["c"].into_iter().collect::<BTreeSet<_>>()],
+    explanation.candidates[1].used_params
+  );
+
+  // The pruned candidate's own dependency is explained too.
+  let b = &explanation.candidates[0].dependencies[0];
+  assert_eq!(
+    ExplainOutcome::MissingParam(vec![
+      "No rule was available to compute DependencyKey(\"d\", None) with parameter type c"
+        .to_owned()
+    ]),
+    b.candidates[0].outcome
+  );
+
+  let rendered = explanation.to_string();
+  assert!(rendered.contains("[missing param] Rule(\"a_from_b\""));
+  assert!(rendered.contains("[chosen] Rule(\"a_from_c\""));
+
+  let mut dot = Vec::new();
+  explanation.visualize(&mut dot).unwrap();
+  assert!(String::from_utf8(dot).unwrap().starts_with("digraph {"));
+}
+
+#[test]
+fn explain_ambiguity_and_simplification() {
+  let rules = vec![(
+    "a",
+    vec![
+      Rule("a_from_b", vec![DependencyKey("b", None)], 0),
+      Rule("a_from_c", vec![DependencyKey("c", None)], 0),
+      Rule("a_from_b_too", vec![DependencyKey("b", None)], 0),
+      Rule(
+        "a_from_b_and_c",
+        vec![DependencyKey("b", None), DependencyKey("c", None)],
+        0,
+      ),
+    ],
+  )]
+  .into_iter()
+  .collect();
+  let unions = HashMap::new();
+  let graph_maker = GraphMaker::new(&rules, &unions, vec!["b", "c"]);
+  let explanation = graph_maker.explain(vec!["b", "c"], "a");
+
+  let outcome_labels = explanation
+    .candidates
+    .iter()
+    .map(|c| match c.outcome {
+      ExplainOutcome::Chosen => "chosen",
+      ExplainOutcome::Ambiguous(_) => "ambiguous",
+      ExplainOutcome::Simplified(_) => "simplified",
+      _ => "other",
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(
+    vec!["ambiguous", "chosen", "ambiguous", "simplified"],
+    outcome_labels
+  );
+}
+
+#[test]
+fn explain_choices_across_dependencies() {
+  // Considered alone, either candidate for "a" could be chosen. But "top" also depends on "x",
+  // which needs "b", and so construction chooses the candidate for "a" which uses "b" as well.
+  let rules = vec![
+    (
+      "top",
+      vec![Rule(
+        "top",
+        vec![DependencyKey("a", None), DependencyKey("x", None)],
+        0,
+      )],
+    ),
+    (
+      "a",
+      vec![
+        Rule("a_from_b", vec![DependencyKey("b", None)], 0),
+        Rule("a_from_c", vec![DependencyKey("c", None)], 0),
+      ],
+    ),
+    (
+      "x",
+      vec![Rule("x_from_b", vec![DependencyKey("b", None)], 0)],
+    ),
+  ]
+  .into_iter()
+  .collect();
+  let unions = HashMap::new();
+  let graph_maker = GraphMaker::new(&rules, &unions, vec!["b", "c"]);
+  let explanation = graph_maker.explain(vec!["b", "c"], "top");
+
+  assert_eq!(ExplainOutcome::Chosen, explanation.candidates[0].outcome);
+  let a = &explanation.candidates[0].dependencies[0];
+  assert_eq!(DependencyKey("a", None), a.dependency_key);
+  assert_eq!(ExplainOutcome::Chosen, a.candidates[0].outcome);
+  assert_eq!(
+    ExplainOutcome::Simplified("Another candidate consumed fewer Params.".to_owned()),
+    a.candidates[1].outcome
+  );
+}
+
+#[test]
+fn explain_unsatisfiable_dependent() {
+  let rules = vec![
+    (
+      "top",
+      vec![Rule(
+        "top",
+        vec![DependencyKey("a", None), DependencyKey("z", None)],
+        0,
+      )],
+    ),
+    (
+      "a",
+      vec![Rule("a_from_b", vec![DependencyKey("b", None)], 0)],
+    ),
+  ]
+  .into_iter()
+  .collect();
+  let unions = HashMap::new();
+  let graph_maker = GraphMaker::new(&rules, &unions, vec!["b"]);
+  let explanation = graph_maker.explain(vec!["b"], "top");
+
+  match explanation.candidates[0].outcome {
+    ExplainOutcome::MissingParam(_) => {}
+    ref o => panic!("Expected a missing param for top, got {:?}", o),
+  }
+  let a = &explanation.candidates[0].dependencies[0];
+  assert_eq!(DependencyKey("a", None), a.dependency_key);
+  assert_eq!(
+    ExplainOutcome::DependentUnsatisfiable,
+    a.candidates[0].outcome
+  );
+}
+
+#[test]
+fn priority_shadows_rule() {
+  let rules = vec![(
+    "a",
+    vec![
+      Rule("a_from_b", vec![DependencyKey("b", None)], 0),
+      Rule("a_from_b_override", vec![DependencyKey("b", None)], 1),
+    ],
+  )]
+  .into_iter()
+  .collect();
+  let roots = vec!["b"];
+  let graph = RuleGraph::new(&rules, &HashMap::new(), roots);
+
+  graph.validate().unwrap();
+  let shadowed_rules = graph.shadowed_rules();
+  assert_eq!(1, shadowed_rules.len());
+  assert_eq!("a_from_b", shadowed_rules[0].rule.0);
+  assert_eq!(
+    vec!["a_from_b_override"],
+    shadowed_rules[0]
+      .shadowed_by
+      .iter()
+      .map(|r| r.0)
+      .collect::<Vec<_>>()
+  );
+  let diagnostic = shadowed_rules[0].diagnostic();
+  assert_eq!(DiagnosticKind::Shadowed, diagnostic.kind);
+  assert_eq!(Some("a"), diagnostic.product);
+  assert_eq!(
+    vec![shadowed_rules[0].shadowed_by[0].to_string()],
+    diagnostic.candidates
+  );
+
+  let edges = graph.find_root_edges(vec!["b"], "a").unwrap();
+  let chosen = edges
+    .all_dependencies()
+    .map(|e| match e {
+      Entry::WithDeps(EntryWithDeps::Inner(ie)) => ie.rule().0,
+      e => panic!("Expected a rule entry, got {:?}", e),
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(vec!["a_from_b_override"], chosen);
+
+  let explanation = GraphMaker::new(&rules, &HashMap::new(), vec!["b"]).explain(vec!["b"], "a");
+  match explanation.candidates[0].outcome {
+    ExplainOutcome::Simplified(ref reason) => {
+      assert!(reason.starts_with("Shadowed by higher priority rule(s)"))
+    }
+    ref o => panic!("Expected a_from_b to be shadowed, got {:?}", o),
+  }
+}
+
+#[test]
+fn priority_shadows_rule_consuming_fewer_params() {
+  // The higher priority rule is chosen even though it consumes more Params than the rule that it
+  // shadows.
+  let rules = vec![(
+    "a",
+    vec![
+      Rule("a_from_b", vec![DependencyKey("b", None)], 0),
+      Rule(
+        "a_from_b_and_c",
+        vec![DependencyKey("b", None), DependencyKey("c", None)],
+        1,
+      ),
+    ],
+  )]
+  .into_iter()
+  .collect();
+  let roots = vec!["b", "c"];
+  let graph = RuleGraph::new(&rules, &HashMap::new(), roots);
+
+  graph.validate().unwrap();
+  let shadowed_rules = graph.shadowed_rules();
+  assert_eq!(1, shadowed_rules.len());
+  assert_eq!("a_from_b", shadowed_rules[0].rule.0);
+
+  let edges = graph.find_root_edges(vec!["b", "c"], "a").unwrap();
+  let chosen = edges
+    .all_dependencies()
+    .map(|e| match e {
+      Entry::WithDeps(EntryWithDeps::Inner(ie)) => ie.rule().0,
+      e => panic!("Expected a rule entry, got {:?}", e),
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(vec!["a_from_b_and_c"], chosen);
+}
+
+#[test]
+fn unfulfillable_rule_is_not_shadowed() {
+  // The lower priority rule could never have been chosen, so it is reported as unfulfillable
+  // rather than as shadowed.
+  let rules = vec![(
+    "a",
+    vec![
+      Rule("a_from_c", vec![DependencyKey("c", None)], 0),
+      Rule("a_from_b", vec![DependencyKey("b", None)], 1),
+    ],
+  )]
+  .into_iter()
+  .collect();
+  let roots = vec!["b"];
+  let graph = RuleGraph::new(&rules, &HashMap::new(), roots);
+
+  assert!(graph.shadowed_rules().is_empty());
+  let rule_errors = graph.validate().err().unwrap().rule_errors;
+  assert_eq!(1, rule_errors.len());
+  assert_eq!("a_from_c", rule_errors[0].rule.0);
+  assert_eq!(
+    vec![DiagnosticKind::Unfulfillable],
+    rule_errors[0]
+      .diagnostics
+      .iter()
+      .map(|d| d.kind)
+      .collect::<Vec<_>>()
+  );
+}
+
+#[test]
+fn union_members_are_validated() {
+  let rules = vec![
+    (
+      "a",
+      vec![Rule("a_from_u", vec![DependencyKey("b", Some("u"))], 0)],
+    ),
+    (
+      "b",
+      vec![Rule("b_from_m1", vec![DependencyKey("m1", None)], 0)],
+    ),
+  ]
+  .into_iter()
+  .collect();
+  let unions = vec![("u", vec!["m1", "m2"])].into_iter().collect();
+  let roots = vec!["c"];
+  let graph = RuleGraph::new(&rules, &unions, roots);
+
+  // The rule is usable for its valid member...
+  let edges = graph.find_root_edges(vec!["c"], "a").unwrap();
+  let a_from_u = edges.all_dependencies().next().unwrap();
+  let a_from_u_edges = graph.edges_for_inner(a_from_u).unwrap();
+  assert!(a_from_u_edges
+    .entry_for(&DependencyKey("b", Some("m1")))
+    .is_some());
+  assert!(a_from_u_edges
+    .entry_for(&DependencyKey("b", Some("m2")))
+    .is_none());
+
+  // ...but validation reports the member that could not compute the product.
+  let error = graph.validate().err().unwrap();
+  assert_eq!(1, error.rule_errors.len());
+  let diagnostic = &error.rule_errors[0].diagnostics[0];
+  assert_eq!("a_from_u", error.rule_errors[0].rule.0);
+  assert_eq!(DiagnosticKind::InvalidUnionMember, diagnostic.kind);
+  assert_eq!(Some("b"), diagnostic.product);
+  assert_eq!(
+    vec!["m2"],
+    diagnostic.params.iter().cloned().collect::<Vec<_>>()
+  );
+
+  let mut dot = Vec::new();
+  graph.visualize(&mut dot).unwrap();
+  let dot = String::from_utf8(dot).unwrap();
+  assert!(dot.contains("// union fan-out"));
+  assert!(dot.contains("DependencyKey(\"b\", Some(\"u\")) (@union)"));
+}
+
+#[test]
+fn export_sub_graph() {
+  let rules = vec![
+    (
+      "a",
+      vec![Rule(
+        "a_from_b_and_c",
+        vec![DependencyKey("b", None), DependencyKey("c", None)],
+        0,
+      )],
+    ),
+    (
+      "b",
+      vec![Rule("b_from_d", vec![DependencyKey("d", None)], 0)],
+    ),
+    (
+      "x",
+      vec![Rule(
+        "x_from_missing",
+        vec![DependencyKey("missing", None)],
+        0,
+      )],
+    ),
+  ]
+  .into_iter()
+  .collect();
+  let unions = HashMap::new();
+  let graph_maker = GraphMaker::new(&rules, &unions, vec!["c", "d"]);
+
+  // A sub graph only contains the entries below its root.
+  let export = graph_maker.sub_graph(vec!["c", "d"], "a").export();
+  assert_eq!(vec!["c", "d"], export.root_param_types);
+  let kinds_and_rules = export
+    .entries
+    .iter()
+    .map(|e| {
+      (
+        e.kind,
+        e.rule.as_ref().map(|r| r.split('"').nth(1).unwrap()),
+      )
+    })
+    .collect::<Vec<_>>();
+  assert_eq!(
+    vec![
+      (ExportedEntryKind::Param, None),
+      (ExportedEntryKind::Param, None),
+      (ExportedEntryKind::Inner, Some("a_from_b_and_c")),
+      (ExportedEntryKind::Inner, Some("b_from_d")),
+      (ExportedEntryKind::Root, None),
+    ],
+    kinds_and_rules
+  );
+  let a_from_b_and_c = &export.entries[2];
+  assert_eq!("a", a_from_b_and_c.product);
+  assert_eq!(vec!["c", "d"], a_from_b_and_c.params);
+  let a_deps = export
+    .edges
+    .iter()
+    .filter(|e| e.source == a_from_b_and_c.id)
+    .map(|e| (e.dependency_key.as_str(), e.target.as_str()))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    vec![
+      ("DependencyKey(\"b\", None)", export.entries[3].id.as_str()),
+      ("DependencyKey(\"c\", None)", export.entries[0].id.as_str()),
+    ],
+    a_deps
+  );
+  assert!(export.unfulfillable.is_empty());
+
+  // While the full graph also reports the rules that could not be used.
+  let export = graph_maker.full_graph().export();
+  let unfulfillable = export
+    .unfulfillable
+    .iter()
+    .map(|u| (u.entry.kind, u.entry.product.as_str()))
+    .collect::<Vec<_>>();
+  assert_eq!(
+    vec![
+      (ExportedEntryKind::Inner, "x"),
+      (ExportedEntryKind::Root, "x")
+    ],
+    unfulfillable
+  );
+  assert!(export.unfulfillable[0].diagnostics[0].contains("DependencyKey(\"missing\", None)"));
+
+  let mut graphml = Vec::new();
+  export.graphml(&mut graphml).unwrap();
+  let graphml = String::from_utf8(graphml).unwrap();
+  assert!(graphml.contains("<graph id=\"rule_graph\" edgedefault=\"directed\">"));
+  assert!(graphml.contains("<data key=\"kind\">unfulfillable</data>"));
+  assert!(graphml.contains("<data key=\"product\">x</data>"));
+  assert!(graphml.contains("Rule(&quot;x_from_missing&quot;"));
+}
+
+///
+/// Constructs a synthetic graph of thousands of rules which consume many Params (which was
+/// intractable when every subset of the used Params was considered), and checks that every rule
+/// was used.
+///
+#[test]
+fn construct_synthetic_graph() {
+  let (rules, roots) = synthetic_rules(12, 20, 100);
+  let graph = RuleGraph::new(&rules, &HashMap::new(), roots.clone());
+
+  graph.validate().unwrap();
+  let root_edges = graph.find_root_edges(roots, "top").unwrap();
+  let top = root_edges.all_dependencies().collect::<Vec<_>>();
+  assert_eq!(1, top.len());
+  assert_eq!(
+    100,
+    graph
+      .edges_for_inner(top[0])
+      .unwrap()
+      .all_dependencies()
+      .count()
+  );
+}
+
+///
+/// A timed comparison of choosing the dependencies of an entry for each union of the Params used by
+/// its dependencies, against choosing them for every subset of the used Params (as graph
+/// construction did previously). Run with:
+///   cargo test -p rule_graph --release -- --ignored --nocapture bench_monomorphize
+///
+#[test]
+#[ignore]
+fn bench_monomorphize_param_sets() {
+  for &param_count in &[4, 8, 12, 16, 20] {
+    let deps = nested_dependencies(param_count);
+    let param_sets = deps.iter().flat_map(|(_, inputs)| {
+      inputs
+        .iter()
+        .map(|input| input.params().into_iter().collect::<BTreeSet<_>>())
+    });
+
+    let start = Instant::now();
+    let unions = GraphMaker::<Rule>::param_set_unions(param_sets.clone());
+    let union_count = unions.len();
+    let from_unions = satisfiable_param_sets(unions, &deps);
+    let unions_elapsed = start.elapsed();
+
+    let start = Instant::now();
+    let used_params = param_sets.flatten().collect::<BTreeSet<_>>();
+    let mut powerset = vec![BTreeSet::new()];
+    for param in used_params {
+      let extended = powerset
+        .iter()
+        .map(|set: &BTreeSet<_>| {
+          let mut set = set.clone();
+          set.insert(param);
+          set
+        })
+        .collect::<Vec<_>>();
+      powerset.extend(extended);
+    }
+    powerset.sort_by_key(BTreeSet::len);
+    let powerset_count = powerset.len();
+    let from_powerset = satisfiable_param_sets(powerset, &deps);
+    let powerset_elapsed = start.elapsed();
+
+    assert_eq!(from_powerset, from_unions);
+    println!(
+      "{} params: {} unions in {:?}, {} subsets in {:?}",
+      param_count, union_count, unions_elapsed, powerset_count, powerset_elapsed
+    );
+  }
+}
+
+///
+/// Returns the sets of Params (from the given candidate sets, in order) for which the dependencies
+/// are satisfiable, skipping supersets of already satisfiable sets in the same way that
+/// `GraphMaker::monomorphize` does.
+///
+fn satisfiable_param_sets(
+  param_sets: Vec<BTreeSet<&'static str>>,
+  deps: &[(DependencyKey, Vec<Entry<Rule>>)],
+) -> Vec<BTreeSet<&'static str>> {
+  let mut decisions = Decisions::default();
+  let mut satisfiable: Vec<BTreeSet<_>> = Vec::new();
+  for available_params in param_sets {
+    if satisfiable
+      .iter()
+      .any(|params| params.is_subset(&available_params))
+    {
+      continue;
+    }
+    if let Ok(Some(_)) =
+      GraphMaker::<Rule>::choose_dependencies(&available_params, deps, &mut decisions)
+    {
+      satisfiable.push(available_params);
+    }
+  }
+  satisfiable
+}
+
+///
+/// Generates `param_count` dependencies, each of which has a single candidate that consumes one
+/// more Param than the previous one. This is the shape that transitively accumulated Params take:
+/// few distinct unions of many used Params.
+///
+fn nested_dependencies(param_count: usize) -> Vec<(DependencyKey, Vec<Entry<Rule>>)> {
+  fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+  }
+  let params: Vec<_> = (0..param_count).map(|p| leak(format!("p{}", p))).collect();
+  (0..param_count)
+    .map(|i| {
+      let product = leak(format!("t{}", i));
+      let entry = Entry::WithDeps(EntryWithDeps::Inner(InnerEntry {
+        params: params[..=i].iter().cloned().collect(),
+        rule: Rule(leak(format!("r{}", i)), vec![], 0),
+      }));
+      (DependencyKey(product, None), vec![entry])
+    })
+    .collect()
+}
+
+///
+/// Generates `layers` layers of `width` rules each, where each rule consumes one of `param_count`
+/// root Params, and depends on two rules in the previous layer: one directly, and one via a Get
+/// that provides the Param that the other rule consumes. A "top" rule depends on all rules in the
+/// last layer.
+///
+fn synthetic_rules(
+  param_count: usize,
+  layers: usize,
+  width: usize,
+) -> (HashMap<&'static str, Vec<Rule>>, Vec<&'static str>) {
+  fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+  }
+  let params: Vec<_> = (0..param_count).map(|p| leak(format!("p{}", p))).collect();
+  let products: Vec<Vec<_>> = (0..layers)
+    .map(|l| (0..width).map(|i| leak(format!("t{}_{}", l, i))).collect())
+    .collect();
+
+  let mut rules: HashMap<_, Vec<_>> = HashMap::new();
+  for l in 0..layers {
+    for i in 0..width {
+      let mut dependency_keys = vec![DependencyKey(params[(l * width + i) % param_count], None)];
+      if l > 0 {
+        dependency_keys.push(DependencyKey(products[l - 1][i], None));
+        let j = (i + 1) % width;
+        dependency_keys.push(DependencyKey(
+          products[l - 1][j],
+          Some(params[((l - 1) * width + j) % param_count]),
+        ));
+      }
+      rules
+        .entry(products[l][i])
+        .or_insert_with(Vec::new)
+        .push(Rule(leak(format!("r{}_{}", l, i)), dependency_keys, 0));
+    }
+  }
+  rules.insert(
+    "top",
+    vec![Rule(
+      "top",
+      products[layers - 1]
+        .iter()
+        .map(|p| DependencyKey(p, None))
+        .collect(),
+      0,
+    )],
+  );
+  (rules, params)
+}
+
 impl super::TypeId for &'static str {
   fn display<I>(type_ids: I) -> String
   where
@@ -36,9 +730,9 @@ impl super::TypeId for &'static str {
   }
 }
 
-// A name and vec of DependencyKeys. Abbreviated for simpler construction and matching.
+// A name, vec of DependencyKeys, and priority. Abbreviated for simpler construction and matching.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
-struct Rule(&'static str, Vec<DependencyKey>);
+struct Rule(&'static str, Vec<DependencyKey>, i32);
 
 impl super::Rule for Rule {
   type TypeId = &'static str;
@@ -51,6 +745,10 @@ impl super::Rule for Rule {
   fn require_reachable(&self) -> bool {
     true
   }
+
+  fn priority(&self) -> i32 {
+    self.2
+  }
 }
 
 impl fmt::Display for Rule {
@@ -77,6 +775,10 @@ impl super::DependencyKey for DependencyKey {
   fn provided_param(&self) -> Option<Self::TypeId> {
     self.1
   }
+
+  fn for_union_member(&self, member: Self::TypeId) -> Self {
+    DependencyKey(self.0, Some(member))
+  }
 }
 
 impl fmt::Display for DependencyKey {
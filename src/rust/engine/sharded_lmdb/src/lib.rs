@@ -30,11 +30,11 @@ use bytes::Bytes;
 use futures::Future;
 use hashing::Fingerprint;
 use lmdb::{
-  self, Database, DatabaseFlags, Environment, EnvironmentCopyFlags, EnvironmentFlags,
+  self, Cursor, Database, DatabaseFlags, Environment, EnvironmentCopyFlags, EnvironmentFlags,
   RwTransaction, Transaction, WriteFlags,
 };
 use log::trace;
-use std::collections::HashMap;
+use std::collections::{BinaryHeap, HashMap};
 use std::fmt;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -184,7 +184,7 @@ impl ShardedLmdb {
         let put_res = env.begin_rw_txn().and_then(|mut txn| {
           txn.put(db, &key, &bytes, WriteFlags::NO_OVERWRITE)?;
           if initial_lease {
-            store.lease(
+            store.put_lease(
               lease_database,
               &key,
               Self::default_lease_until_secs_since_epoch(),
@@ -202,7 +202,31 @@ impl ShardedLmdb {
       }))
   }
 
-  fn lease(
+  ///
+  /// Extends the lease on the given fingerprint to the default lease time from now.
+  ///
+  pub fn lease(&self, fingerprint: Fingerprint) -> impl Future<Item = (), Error = String> {
+    let store = self.clone();
+    self
+      .executor
+      .spawn_on_io_pool(futures::future::lazy(move || {
+        let (env, _, lease_database) = store.get(&fingerprint);
+        env
+          .begin_rw_txn()
+          .and_then(|mut txn| {
+            store.put_lease(
+              lease_database,
+              &fingerprint,
+              Self::default_lease_until_secs_since_epoch(),
+              &mut txn,
+            )?;
+            txn.commit()
+          })
+          .map_err(|err| format!("Error leasing key {:?}: {}", fingerprint.to_hex(), err))
+      }))
+  }
+
+  fn put_lease(
     &self,
     database: Database,
     fingerprint: &Fingerprint,
@@ -218,10 +242,102 @@ impl ShardedLmdb {
   }
 
   fn default_lease_until_secs_since_epoch() -> u64 {
+    Self::lease_until_secs_since_epoch(time::Duration::from_secs(2 * 60 * 60))
+  }
+
+  fn lease_until_secs_since_epoch(lease_time: time::Duration) -> u64 {
     let now_since_epoch = time::SystemTime::now()
       .duration_since(time::UNIX_EPOCH)
       .expect("Surely you're not before the unix epoch?");
-    (now_since_epoch + time::Duration::from_secs(2 * 60 * 60)).as_secs()
+    (now_since_epoch + lease_time).as_secs()
+  }
+
+  ///
+  /// Stores the given bytes under the given key with a lease which expires after lease_time,
+  /// replacing any existing value and lease.
+  ///
+  /// Unlike `store_bytes`, this is intended for values which should stop being used once their
+  /// lease has expired: see `load_leased_bytes_with`.
+  ///
+  pub fn store_bytes_with_lease(
+    &self,
+    key: Fingerprint,
+    bytes: Bytes,
+    lease_time: time::Duration,
+  ) -> impl Future<Item = (), Error = String> {
+    let store = self.clone();
+    self
+      .executor
+      .spawn_on_io_pool(futures::future::lazy(move || {
+        let (env, db, lease_database) = store.get(&key);
+        env
+          .begin_rw_txn()
+          .and_then(|mut txn| {
+            txn.put(db, &key, &bytes, WriteFlags::empty())?;
+            store.put_lease(
+              lease_database,
+              &key,
+              Self::lease_until_secs_since_epoch(lease_time),
+              &mut txn,
+            )?;
+            txn.commit()
+          })
+          .map_err(|err| format!("Error storing key {:?}: {}", key.to_hex(), err))
+      }))
+  }
+
+  ///
+  /// Like `load_bytes_with`, but treats a value whose lease has expired (or which was never
+  /// leased) as missing.
+  ///
+  pub fn load_leased_bytes_with<
+    T: Send + 'static,
+    F: Fn(Bytes) -> Result<T, String> + Send + Sync + 'static,
+  >(
+    &self,
+    fingerprint: Fingerprint,
+    f: F,
+  ) -> impl Future<Item = Option<T>, Error = String> {
+    let store = self.clone();
+    self
+      .executor
+      .spawn_on_io_pool(futures::future::lazy(move || {
+        let (env, db, lease_database) = store.get(&fingerprint);
+        let ro_txn = env
+          .begin_ro_txn()
+          .map_err(|err| format!("Failed to begin read transaction: {}", err));
+        ro_txn.and_then(|txn| {
+          let leased_until_secs_since_epoch = match txn.get(lease_database, &fingerprint) {
+            Ok(b) => {
+              let mut array = [0_u8; 8];
+              array.copy_from_slice(b);
+              u64::from_le_bytes(array)
+            }
+            Err(lmdb::Error::NotFound) => return Ok(None),
+            Err(err) => {
+              return Err(format!(
+                "Error loading lease for fingerprint {:?}: {}",
+                fingerprint.to_hex(),
+                err,
+              ))
+            }
+          };
+          if leased_until_secs_since_epoch
+            <= Self::lease_until_secs_since_epoch(time::Duration::from_secs(0))
+          {
+            return Ok(None);
+          }
+          match txn.get(db, &fingerprint) {
+            Ok(bytes) => f(Bytes::from(bytes)).map(Some),
+            Err(lmdb::Error::NotFound) => Ok(None),
+            Err(err) => Err(format!(
+              "Error loading fingerprint {:?}: {}",
+              fingerprint.to_hex(),
+              err,
+            )),
+          }
+        })
+      }))
   }
 
   pub fn load_bytes_with<
@@ -252,6 +368,104 @@ impl ShardedLmdb {
       }))
   }
 
+  ///
+  /// Returns every stored fingerprint along with its size and how long ago its lease expired.
+  ///
+  /// Note: This performs IO on the calling thread.
+  ///
+  pub fn aged_fingerprints(&self) -> Result<Vec<AgedFingerprint>, String> {
+    let now = time::SystemTime::now();
+    let mut aged_fingerprints = vec![];
+    for &(ref env, ref database, ref lease_database) in &self.all_lmdbs() {
+      let txn = env
+        .begin_ro_txn()
+        .map_err(|err| format!("Error beginning transaction to garbage collect: {}", err))?;
+      let mut cursor = txn
+        .open_ro_cursor(*database)
+        .map_err(|err| format!("Failed to open lmdb read cursor: {}", err))?;
+      for (key, bytes) in cursor.iter() {
+        // Random access into the lease_database is slower than iterating, but hopefully garbage
+        // collection is rare enough that we can get away with this, rather than do two passes
+        // here (either to populate leases into pre-populated AgedFingerprints, or to read sizes
+        // when we delete from lmdb to track how much we've freed).
+        let lease_until_unix_timestamp = txn
+          .get(*lease_database, &key)
+          .map(|b| {
+            let mut array = [0_u8; 8];
+            array.copy_from_slice(b);
+            u64::from_le_bytes(array)
+          })
+          .unwrap_or_else(|e| match e {
+            lmdb::Error::NotFound => 0,
+            e => panic!("Error reading lease, probable lmdb corruption: {:?}", e),
+          });
+
+        let leased_until = time::UNIX_EPOCH + time::Duration::from_secs(lease_until_unix_timestamp);
+
+        let expired_seconds_ago = now
+          .duration_since(leased_until)
+          .map(|t| t.as_secs())
+          // 0 indicates unleased.
+          .unwrap_or(0);
+
+        aged_fingerprints.push(AgedFingerprint {
+          expired_seconds_ago: expired_seconds_ago,
+          fingerprint: Fingerprint::from_bytes_unsafe(key),
+          size_bytes: bytes.len(),
+        });
+      }
+    }
+    Ok(aged_fingerprints)
+  }
+
+  ///
+  /// Removes the given fingerprint and its lease.
+  ///
+  /// Note: This performs IO on the calling thread.
+  ///
+  pub fn remove(&self, fingerprint: &Fingerprint) -> Result<(), String> {
+    let (env, database, lease_database) = self.get(fingerprint);
+    env
+      .begin_rw_txn()
+      .and_then(|mut txn| {
+        txn.del(database, &fingerprint.as_ref(), None)?;
+
+        txn
+          .del(lease_database, &fingerprint.as_ref(), None)
+          .or_else(|err| match err {
+            lmdb::Error::NotFound => Ok(()),
+            err => Err(err),
+          })?;
+        txn.commit()
+      })
+      .map_err(|err| format!("Error garbage collecting: {}", err))
+  }
+
+  ///
+  /// Attempts to shrink the stored values to be no bigger than target_bytes (excluding lmdb
+  /// overhead), by removing the values whose leases expired the longest time ago first.
+  ///
+  /// Returns the size it was shrunk to, which may be larger than target_bytes if too many values
+  /// are still leased.
+  ///
+  pub fn shrink(&self, target_bytes: usize) -> Result<usize, String> {
+    let aged_fingerprints = self.aged_fingerprints()?;
+    let mut used_bytes: usize = aged_fingerprints.iter().map(|a| a.size_bytes).sum();
+    let mut fingerprints_by_expired_ago: BinaryHeap<_> = aged_fingerprints.into_iter().collect();
+    while used_bytes > target_bytes {
+      let aged_fingerprint = fingerprints_by_expired_ago
+        .pop()
+        .expect("lmdb corruption detected, sum of size of blobs exceeded stored blobs");
+      if aged_fingerprint.expired_seconds_ago == 0 {
+        // Ran out of expired blobs - everything remaining is leased and cannot be collected.
+        return Ok(used_bytes);
+      }
+      self.remove(&aged_fingerprint.fingerprint)?;
+      used_bytes -= aged_fingerprint.size_bytes;
+    }
+    Ok(used_bytes)
+  }
+
   #[allow(clippy::identity_conversion)] // False positive: https://github.com/rust-lang/rust-clippy/issues/3913
   pub fn compact(&self) -> Result<(), String> {
     for (env, old_dir, _) in ShardedLmdb::envs(&self.root_path, self.max_size)? {
@@ -283,3 +497,11 @@ impl ShardedLmdb {
     Ok(())
   }
 }
+
+#[derive(Eq, PartialEq, Ord, PartialOrd)]
+pub struct AgedFingerprint {
+  // expired_seconds_ago must be the first field for the Ord implementation.
+  pub expired_seconds_ago: u64,
+  pub fingerprint: Fingerprint,
+  pub size_bytes: usize,
+}
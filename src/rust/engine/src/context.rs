@@ -2,35 +2,71 @@
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
 use std;
-use std::convert::{Into, TryInto};
+use std::collections::HashMap;
+use std::convert::{Into, TryFrom, TryInto};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
 use futures::Future;
+use log::debug;
 
 use crate::core::{Failure, TypeId};
 use crate::handles::maybe_drop_handles;
-use crate::nodes::{NodeKey, WrappedNode};
+use crate::nodes::{NodeKey, ProcessIdentity, WrappedNode};
 use crate::scheduler::Session;
 use crate::tasks::{Rule, Tasks};
 use crate::types::Types;
 use boxfuture::{BoxFuture, Boxable};
 use core::clone::Clone;
 use fs::{safe_create_dir_all_ioerror, PosixFS};
-use graph::{EntryId, Graph, NodeContext};
+use graph::{EntryId, Graph, NodeContext, NodeEvent};
+use parking_lot::Mutex;
 use process_execution::{
-  self, speculate::SpeculatingCommandRunner, BoundedCommandRunner, ExecuteProcessRequestMetadata,
-  Platform,
+  self, routing::RoutingCommandRunner, speculate::SpeculatingCommandRunner, BoundedCommandRunner,
+  ExecuteProcessRequestMetadata, Platform,
 };
 use rand::seq::SliceRandom;
 use reqwest;
 use rule_graph::RuleGraph;
 use sharded_lmdb::ShardedLmdb;
 use std::collections::BTreeMap;
-use store::Store;
+use store::{ShrinkBehavior, Store};
 
-const GIGABYTES: usize = 1024 * 1024 * 1024;
+///
+/// A remote execution backend which is used (in addition to the default remote execution server)
+/// to run requests which are compatible with its platform.
+///
+#[derive(Clone, Debug)]
+pub struct RemoteExecutionBackend {
+  pub server: String,
+  pub platform: Platform,
+  pub instance_name: Option<String>,
+  pub extra_platform_properties: Vec<(String, String)>,
+}
+
+impl RemoteExecutionBackend {
+  pub fn new(
+    server: String,
+    platform: &str,
+    instance_name: Option<String>,
+    extra_platform_properties: Vec<(String, String)>,
+  ) -> Result<RemoteExecutionBackend, String> {
+    let platform = Platform::try_from(&platform.to_owned())?;
+    if platform == Platform::None {
+      return Err(format!(
+        "Remote execution backend {} must be configured with a specific platform.",
+        server
+      ));
+    }
+    Ok(RemoteExecutionBackend {
+      server,
+      platform,
+      instance_name,
+      extra_platform_properties,
+    })
+  }
+}
 
 ///
 /// The core context shared (via Arc) between the Scheduler and the Context objects of
@@ -47,10 +83,55 @@ pub struct Core {
   pub types: Types,
   pub executor: task_executor::Executor,
   store: Store,
+  process_execution_store: Option<ShardedLmdb>,
   pub command_runner: Box<dyn process_execution::CommandRunner>,
   pub http_client: reqwest::r#async::Client,
   pub vfs: PosixFS,
   pub build_root: PathBuf,
+  // For each process identity, the duration (in milliseconds) of the longest chain of work
+  // through the process when it last ran: see `Scheduler::record_process_priorities`.
+  process_priorities: Mutex<ProcessPriorities>,
+}
+
+// The number of process identities to remember priorities for, beyond which the least recently
+// recorded half are forgotten.
+const MAX_PROCESS_PRIORITIES: usize = 100_000;
+
+///
+/// The recorded priority of each process identity, along with the batch in which it was last
+/// recorded.
+///
+#[derive(Default)]
+struct ProcessPriorities {
+  batches: u64,
+  priorities: HashMap<ProcessIdentity, (u64, u64)>,
+}
+
+impl ProcessPriorities {
+  fn get(&self, identity: ProcessIdentity) -> Option<u64> {
+    self
+      .priorities
+      .get(&identity)
+      .map(|&(priority, _)| priority)
+  }
+
+  fn record(&mut self, priorities: HashMap<ProcessIdentity, u64>) {
+    self.batches += 1;
+    let batch = self.batches;
+    self.priorities.extend(
+      priorities
+        .into_iter()
+        .map(|(identity, priority)| (identity, (priority, batch))),
+    );
+    if self.priorities.len() > MAX_PROCESS_PRIORITIES {
+      let mut batches: Vec<u64> = self.priorities.values().map(|&(_, batch)| batch).collect();
+      batches.sort();
+      let oldest_retained = batches[batches.len() - MAX_PROCESS_PRIORITIES / 2];
+      self
+        .priorities
+        .retain(|_, &mut (_, batch)| batch >= oldest_retained);
+    }
+  }
 }
 
 impl Core {
@@ -82,6 +163,8 @@ impl Core {
     process_execution_use_local_cache: bool,
     remote_execution_headers: BTreeMap<String, String>,
     process_execution_local_enable_nailgun: bool,
+    process_execution_failure_cache_ttl: Duration,
+    remote_execution_backends: Vec<RemoteExecutionBackend>,
   ) -> Result<Core, String> {
     // Randomize CAS address order to avoid thundering herds from common config.
     let mut remote_store_servers = remote_store_servers;
@@ -160,34 +243,60 @@ impl Core {
         Box::new(local_command_runner)
       };
 
-    let mut command_runner: Box<dyn process_execution::CommandRunner> =
-      Box::new(BoundedCommandRunner::new(
+    let mut command_runner: Box<dyn process_execution::CommandRunner> = Box::new(
+      process_execution::retry::CommandRunner::new(Box::new(BoundedCommandRunner::new(
         maybe_nailgunnable_local_command_runner,
         process_execution_local_parallelism,
-      ));
+      ))),
+    );
 
     if remote_execution {
-      let remote_command_runner: Box<dyn process_execution::CommandRunner> =
-        Box::new(BoundedCommandRunner::new(
-          Box::new(process_execution::remote::CommandRunner::new(
-            // No problem unwrapping here because the global options validation
-            // requires the remote_execution_server be present when remote_execution is set.
-            &remote_execution_server.unwrap(),
-            process_execution_metadata.clone(),
-            root_ca_certs.clone(),
-            oauth_bearer_token.clone(),
-            remote_execution_headers,
-            store.clone(),
-            // TODO if we ever want to configure the remote platform to be something else we
-            // need to take an option all the way down here and into the remote::CommandRunner struct.
-            Platform::Linux,
-            executor.clone(),
-            std::time::Duration::from_secs(160),
-            std::time::Duration::from_millis(500),
-            std::time::Duration::from_secs(5),
-          )?),
-          process_execution_remote_parallelism,
-        ));
+      let make_remote_command_runner =
+        |server: &str,
+         platform: Platform,
+         metadata: ExecuteProcessRequestMetadata|
+         -> Result<Box<dyn process_execution::CommandRunner>, String> {
+          Ok(Box::new(BoundedCommandRunner::new(
+            Box::new(process_execution::remote::CommandRunner::new(
+              server,
+              metadata,
+              root_ca_certs.clone(),
+              oauth_bearer_token.clone(),
+              remote_execution_headers.clone(),
+              store.clone(),
+              platform,
+              executor.clone(),
+              std::time::Duration::from_secs(160),
+              std::time::Duration::from_millis(500),
+              std::time::Duration::from_secs(5),
+            )?),
+            process_execution_remote_parallelism,
+          )))
+        };
+
+      let mut remote_command_runner = make_remote_command_runner(
+        // No problem unwrapping here because the global options validation
+        // requires the remote_execution_server be present when remote_execution is set.
+        &remote_execution_server.unwrap(),
+        // Additional platforms are supported by configuring remote_execution_backends.
+        Platform::Linux,
+        process_execution_metadata.clone(),
+      )?;
+      if !remote_execution_backends.is_empty() {
+        let mut remote_command_runners = vec![remote_command_runner];
+        for backend in remote_execution_backends {
+          remote_command_runners.push(make_remote_command_runner(
+            &backend.server,
+            backend.platform,
+            ExecuteProcessRequestMetadata {
+              instance_name: backend.instance_name,
+              cache_key_gen_version: remote_execution_process_cache_namespace.clone(),
+              platform_properties: backend.extra_platform_properties,
+            },
+          )?);
+        }
+        remote_command_runner = Box::new(RoutingCommandRunner::new(remote_command_runners));
+      }
       command_runner = match process_execution_speculation_strategy.as_ref() {
         "local_first" => Box::new(SpeculatingCommandRunner::new(
           command_runner,
@@ -204,23 +313,27 @@ impl Core {
       };
     }
 
-    if process_execution_use_local_cache {
-      let process_execution_store = ShardedLmdb::new(
-        local_store_dir2.join("processes"),
-        5 * GIGABYTES,
-        executor.clone(),
-      )
-      .map_err(|err| format!("Could not initialize store for process cache: {:?}", err))?;
+    let process_execution_store = if process_execution_use_local_cache {
+      let process_execution_store =
+        process_execution::cache::open_store(&local_store_dir2, executor.clone())
+          .map_err(|err| format!("Could not initialize store for process cache: {:?}", err))?;
       command_runner = Box::new(process_execution::cache::CommandRunner {
         underlying: command_runner.into(),
-        process_execution_store,
+        process_execution_store: process_execution_store.clone(),
         file_store: store.clone(),
         metadata: process_execution_metadata,
-      })
-    }
+        failure_ttl: process_execution_failure_cache_ttl,
+      });
+      Some(process_execution_store)
+    } else {
+      None
+    };
 
     let http_client = reqwest::r#async::Client::new();
-    let rule_graph = RuleGraph::new(tasks.as_map(), root_subject_types);
+    let rule_graph = RuleGraph::new(tasks.as_map(), tasks.unions(), root_subject_types);
+    for shadowed_rule in rule_graph.shadowed_rules() {
+      debug!("{}", shadowed_rule);
+    }
 
     Ok(Core {
       graph: Graph::new(),
@@ -229,6 +342,7 @@ impl Core {
       types: types,
       executor: executor.clone(),
       store,
+      process_execution_store,
       command_runner,
       http_client,
       // TODO: Errors in initialization should definitely be exposed as python
@@ -236,12 +350,43 @@ impl Core {
       vfs: PosixFS::new(&build_root, &ignore_patterns, executor)
         .map_err(|e| format!("Could not initialize VFS: {:?}", e))?,
       build_root: build_root,
+      process_priorities: Mutex::new(ProcessPriorities::default()),
     })
   }
 
   pub fn store(&self) -> Store {
     self.store.clone()
   }
+
+  ///
+  /// The priority with which to run a process with the given identity, if it has run before.
+  ///
+  pub fn process_priority(&self, identity: ProcessIdentity) -> Option<u64> {
+    self.process_priorities.lock().get(identity)
+  }
+
+  pub fn record_process_priorities(&self, priorities: HashMap<ProcessIdentity, u64>) {
+    self.process_priorities.lock().record(priorities);
+  }
+
+  ///
+  /// Garbage collects the local process execution cache (if it is enabled) down to its default
+  /// target size.
+  ///
+  pub fn garbage_collect_process_cache(
+    &self,
+    shrink_behavior: ShrinkBehavior,
+  ) -> Result<(), String> {
+    if let Some(ref process_execution_store) = self.process_execution_store {
+      process_execution::cache::garbage_collect(
+        process_execution_store,
+        process_execution::cache::DEFAULT_GC_TARGET_BYTES,
+        shrink_behavior,
+      )
+    } else {
+      Ok(())
+    }
+  }
 }
 
 #[derive(Clone)]
@@ -304,4 +449,8 @@ impl NodeContext for Context {
   {
     self.core.executor.spawn_and_ignore(future);
   }
+
+  fn record_event(&self, node: &NodeKey, event: NodeEvent) {
+    self.session.record_node_event(node, event);
+  }
 }
@@ -83,6 +83,10 @@ impl Params {
       .binary_search_by(|probe| probe.type_id().cmp(&type_id))
   }
 
+  pub fn keys(&self) -> impl Iterator<Item = &Key> {
+    self.0.iter()
+  }
+
   pub fn type_ids<'a>(&'a self) -> impl Iterator<Item = TypeId> + 'a {
     self.0.iter().map(|k| *k.type_id())
   }
@@ -278,20 +282,63 @@ impl From<Handle> for Value {
   }
 }
 
+///
+/// A rule which failed, either because it raised an exception or because one of its dependencies
+/// failed: the product that it was computing, and the Params that it was computing it for.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RuleFrame {
+  pub rule: String,
+  pub product: String,
+  pub params: String,
+}
+
+impl fmt::Display for RuleFrame {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} for {} in {}", self.product, self.params, self.rule)
+  }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Failure {
   /// A Node failed because a filesystem change invalidated it or its inputs.
   /// A root requestor should usually immediately retry their request.
   Invalidated,
-  /// A rule raised an exception.
-  Throw(Value, String),
+  /// A Node was cancelled, generally because the Session that requested it was cancelled.
+  Cancelled,
+  /// A rule raised an exception. The RuleFrames record the chain of rules that the exception
+  /// propagated through, beginning with the rule that raised it.
+  Throw(Value, String, Vec<RuleFrame>),
+}
+
+impl Failure {
+  ///
+  /// Records that the rule described by the given RuleFrame failed because of this Failure.
+  ///
+  pub fn with_rule_frame<F: FnOnce() -> RuleFrame>(self, frame: F) -> Failure {
+    match self {
+      Failure::Throw(exc, traceback, mut frames) => {
+        frames.push(frame());
+        Failure::Throw(exc, traceback, frames)
+      }
+      f => f,
+    }
+  }
+
+  pub fn rule_trace(&self) -> &[RuleFrame] {
+    match self {
+      Failure::Throw(_, _, frames) => frames,
+      Failure::Invalidated | Failure::Cancelled => &[],
+    }
+  }
 }
 
 impl fmt::Display for Failure {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
     match self {
       Failure::Invalidated => write!(f, "Exhausted retries due to changed files."),
-      Failure::Throw(exc, _) => write!(f, "{}", externs::val_to_str(exc)),
+      Failure::Cancelled => write!(f, "The request was cancelled."),
+      Failure::Throw(exc, _, _) => write!(f, "{}", externs::val_to_str(exc)),
     }
   }
 }
@@ -303,5 +350,6 @@ pub fn throw(msg: &str) -> Failure {
       "Traceback (no traceback):\n  <pants native internals>\nException: {}",
       msg
     ),
+    vec![],
   )
 }
@@ -9,13 +9,13 @@ use std::os::raw;
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::string::FromUtf8Error;
 
-use crate::core::{Failure, Function, Key, TypeId, Value};
+use crate::core::{Failure, Function, Key, TypeId, Value, FNV};
 use crate::handles::{DroppingHandle, Handle};
 use crate::interning::Interns;
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use parking_lot::RwLock;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
 
 /// Return the Python value None.
 pub fn none() -> Handle {
@@ -50,6 +50,46 @@ pub fn key_for(val: Value) -> Key {
   interns.insert(val)
 }
 
+///
+/// Interns the given Value as a Key which is never collected: see `Interns::insert_pinned`.
+///
+pub fn pinned_key_for(val: Value) -> Key {
+  let mut interns = INTERNS.write();
+  interns.insert_pinned(val)
+}
+
+///
+/// Interns the given Value as a Key which is not collected until it is released with
+/// `release_keys`: see `Interns::insert_held`.
+///
+pub fn held_key_for(val: Value) -> Key {
+  let mut interns = INTERNS.write();
+  interns.insert_held(val)
+}
+
+pub fn release_keys(keys: &[Key]) {
+  let mut interns = INTERNS.write();
+  for key in keys {
+    interns.release(*key);
+  }
+}
+
+pub fn interned_key_count() -> usize {
+  INTERNS.read().len()
+}
+
+pub fn should_collect_interns() -> bool {
+  INTERNS.read().should_collect()
+}
+
+///
+/// Collects all interned Keys which are not in the given set of live Keys: see `Interns::collect`.
+///
+pub fn collect_interns(live_keys: &HashSet<Key, FNV>) -> usize {
+  let mut interns = INTERNS.write();
+  interns.collect(live_keys)
+}
+
 pub fn val_for(key: &Key) -> Value {
   let interns = INTERNS.read();
   interns.get(key).clone()
@@ -441,7 +481,7 @@ pub struct PyResult {
 impl PyResult {
   fn failure_from(v: Value) -> Failure {
     let traceback = project_str(&v, "_formatted_exc");
-    Failure::Throw(v, traceback)
+    Failure::Throw(v, traceback, vec![])
   }
 }
 
@@ -454,8 +494,8 @@ impl From<Result<Value, Failure>> for PyResult {
       },
       Err(f) => {
         let val = match f {
-          f @ Failure::Invalidated => create_exception(&format!("{}", f)),
-          Failure::Throw(exc, _) => exc,
+          f @ Failure::Invalidated | f @ Failure::Cancelled => create_exception(&format!("{}", f)),
+          Failure::Throw(exc, _, _) => exc,
         };
         PyResult {
           is_throw: true,
@@ -0,0 +1,67 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use graph::{EntrySnapshot, EntryStatus};
+use serde_json::json;
+
+use crate::nodes::NodeKey;
+
+///
+/// Writes the given snapshot of the Graph to the given path as JSON, in the form:
+///
+///   {
+///     "nodes": [{"id", "variant", "name", "state", "duration_ms", "generation"}, ...],
+///     "edges": [{"src", "dst"}, ...]
+///   }
+///
+/// where each edge points from a Node to one of its dependencies. Ids are only stable for as long
+/// as a Node remains in the Graph, so graphs from different runs should be compared by "name".
+///
+pub fn write(path: &Path, snapshots: &[EntrySnapshot<NodeKey>]) -> Result<(), String> {
+  let nodes: Vec<_> = snapshots
+    .iter()
+    .map(|snapshot| {
+      json!({
+        "id": snapshot.id,
+        "variant": snapshot.node.variant_name(),
+        "name": snapshot.node.to_string(),
+        "state": status_str(snapshot.status),
+        "duration_ms": snapshot
+          .duration
+          .map(|duration| duration.as_secs_f64() * 1000.0),
+        "generation": snapshot.generation,
+      })
+    })
+    .collect();
+  let edges: Vec<_> = snapshots
+    .iter()
+    .flat_map(|snapshot| {
+      snapshot
+        .dependencies
+        .iter()
+        .map(move |dependency| json!({"src": snapshot.id, "dst": dependency}))
+    })
+    .collect();
+
+  let file =
+    File::create(path).map_err(|e| format!("Failed to create graph file {:?}: {}", path, e))?;
+  serde_json::to_writer(
+    BufWriter::new(file),
+    &json!({"nodes": nodes, "edges": edges}),
+  )
+  .map_err(|e| format!("Failed to write graph to {:?}: {}", path, e))
+}
+
+fn status_str(status: EntryStatus) -> &'static str {
+  match status {
+    EntryStatus::NotStarted => "not_started",
+    EntryStatus::Running => "running",
+    EntryStatus::Completed => "completed",
+    EntryStatus::Failed => "failed",
+    EntryStatus::Dirty => "dirty",
+  }
+}
@@ -1,7 +1,9 @@
 // Copyright 2018 Pants project contributors (see CONTRIBUTORS.md).
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::hash;
 
 use crate::core::{Key, Value, FNV};
@@ -18,24 +20,108 @@ use crate::externs::{self, Ident};
 /// implements `Hash` and `Eq` using the precomputed python `__hash__` for the `Value` and
 /// delegating to python's `__eq__`, respectively.
 ///
-/// Currently `Value`s are interned indefinitely as `Key`s, meaning that they can never
-/// be collected: it's possible that this can eventually be improved by either:
+/// Because `Key`s are `Copy` and are passed across the FFI boundary, they are not reference
+/// counted. Instead, unused `Key`s are collected by `collect`, which is given the set of `Key`s that
+/// are still in use (by Graph entries and Sessions), and removes all other `Key`s except for:
 ///
-///   1) switching to directly linking-against or embedding python, such that the `Value`
-///      type goes away in favor of direct usage of a python object wrapper struct.
-///   2) This structure might begin storing weak-references to `Key`s and/or `Value`s, which
-///      would allow the associated `Value` handles to be dropped when they were no longer used.
-///      The challenge to this approach is that it would make it more difficult to pass
-///      `Key`/`Value` instances across the FFI boundary.
-///   3) `Value` could implement `Eq`/`Hash` directly via extern calls to python (although we've
-///      avoided doing this so far because it would hide a relatively expensive operation behind
-///      those usually-inexpensive traits).
+///   1) pinned `Key`s, which have been handed to python (see `insert_pinned`), and which python
+///      might use at any point in the future.
+///   2) held `Key`s, which are in use until they are released (see `insert_held`): for example, the
+///      roots of an `ExecutionRequest` which has not yet been executed.
+///   3) `Key`s that were interned (whether newly created or returned again for an equal `Value`)
+///      since the previous collection, which might not yet be recorded anywhere that the caller of
+///      `collect` could see them.
+///
+/// Since `Key` ids are never reused, using a `Key` after it has been collected will fail loudly
+/// rather than returning a different `Value`.
 ///
 #[derive(Default)]
 pub struct Interns {
   forward: HashMap<InternKey, Key, FNV>,
   reverse: HashMap<Key, Value, FNV>,
   id_generator: u64,
+  retention: Retention,
+}
+
+// The number of interned Keys below which there is no point in collecting.
+pub(crate) const MIN_COLLECTION_SIZE: usize = 10_000;
+
+///
+/// Decides which Keys survive a collection of `Interns`. Kept separate from the interned Values so
+/// that it can be tested without python.
+///
+#[derive(Default)]
+pub(crate) struct Retention {
+  pinned: HashSet<Key, FNV>,
+  // The number of holds on each held Key.
+  held: HashMap<Key, usize, FNV>,
+  // Keys with ids at or above this were created since the previous collection.
+  collectable_below: u64,
+  // Keys created before the previous collection which have been interned again since it.
+  reinterned: HashSet<Key, FNV>,
+  // The number of Keys that survived the previous collection.
+  retained: usize,
+}
+
+impl Retention {
+  ///
+  /// Records that the given Key was returned by `Interns::insert`.
+  ///
+  pub(crate) fn interned(&mut self, key: Key) {
+    if key.id() < self.collectable_below {
+      self.reinterned.insert(key);
+    }
+  }
+
+  pub(crate) fn pin(&mut self, key: Key) {
+    self.pinned.insert(key);
+  }
+
+  pub(crate) fn hold(&mut self, key: Key) {
+    *self.held.entry(key).or_insert(0) += 1;
+  }
+
+  ///
+  /// Releases one hold on the given Key, which must previously have been passed to `hold`.
+  ///
+  pub(crate) fn release(&mut self, key: Key) {
+    if let Entry::Occupied(mut entry) = self.held.entry(key) {
+      *entry.get_mut() -= 1;
+      if *entry.get() == 0 {
+        entry.remove();
+      }
+    }
+  }
+
+  ///
+  /// True if, given the current number of Keys, enough Keys have been interned since the previous
+  /// collection that it is worth collecting again: i.e., if the number of Keys has at least
+  /// doubled.
+  ///
+  pub(crate) fn should_collect(&self, len: usize) -> bool {
+    len >= cmp::max(MIN_COLLECTION_SIZE, 2 * self.retained)
+  }
+
+  ///
+  /// True if the given Key should survive a collection in which the given Keys are live.
+  ///
+  pub(crate) fn retains(&self, key: &Key, live: &HashSet<Key, FNV>) -> bool {
+    key.id() >= self.collectable_below
+      || self.reinterned.contains(key)
+      || self.pinned.contains(key)
+      || self.held.contains_key(key)
+      || live.contains(key)
+  }
+
+  ///
+  /// Records that a collection has completed, leaving `retained` Keys, and that the next Key to be
+  /// created will have the id `next_id`.
+  ///
+  pub(crate) fn collected(&mut self, next_id: u64, retained: usize) {
+    self.collectable_below = next_id;
+    self.reinterned.clear();
+    self.retained = retained;
+  }
 }
 
 impl Interns {
@@ -61,10 +147,64 @@ impl Interns {
     if inserted {
       self.reverse.insert(key, v);
       self.id_generator += 1;
+    } else {
+      self.retention.interned(key);
     }
     key
   }
 
+  ///
+  /// Interns the given Value as a Key that will never be collected, for use by callers which hold
+  /// Keys in places that `collect` cannot see.
+  ///
+  pub fn insert_pinned(&mut self, v: Value) -> Key {
+    let key = self.insert(v);
+    self.retention.pin(key);
+    key
+  }
+
+  ///
+  /// Interns the given Value as a Key that will not be collected until it is passed to `release`
+  /// (as many times as it was passed to this method).
+  ///
+  pub fn insert_held(&mut self, v: Value) -> Key {
+    let key = self.insert(v);
+    self.retention.hold(key);
+    key
+  }
+
+  pub fn release(&mut self, key: Key) {
+    self.retention.release(key);
+  }
+
+  pub fn len(&self) -> usize {
+    self.reverse.len()
+  }
+
+  ///
+  /// True if enough Keys have been interned since the previous collection that it is worth
+  /// collecting again: see `Retention::should_collect`.
+  ///
+  pub fn should_collect(&self) -> bool {
+    self.retention.should_collect(self.reverse.len())
+  }
+
+  ///
+  /// Removes all Keys that are not pinned, not in the given set of live Keys, and not interned
+  /// since the previous collection. Returns the number of Keys that were removed.
+  ///
+  pub fn collect(&mut self, live: &HashSet<Key, FNV>) -> usize {
+    let retention = &self.retention;
+    let before = self.reverse.len();
+    self.reverse.retain(|k, _| retention.retains(k, live));
+    let reverse = &self.reverse;
+    self.forward.retain(|_, k| reverse.contains_key(k));
+    self
+      .retention
+      .collected(self.id_generator, self.reverse.len());
+    before - self.reverse.len()
+  }
+
   pub fn get(&self, k: &Key) -> &Value {
     self
       .reverse
@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+
+use crate::core::{Key, TypeId, FNV};
+use crate::interning::{Retention, MIN_COLLECTION_SIZE};
+
+fn key(id: u64) -> Key {
+  Key::new(id, TypeId(0))
+}
+
+fn live(ids: &[u64]) -> HashSet<Key, FNV> {
+  ids.iter().map(|&id| key(id)).collect()
+}
+
+#[test]
+fn retains_keys_created_since_previous_collection() {
+  let mut retention = Retention::default();
+  assert!(retention.retains(&key(0), &live(&[])));
+
+  // After a collection, only Keys created since then are retained without being live.
+  retention.collected(10, 10);
+  assert!(!retention.retains(&key(9), &live(&[])));
+  assert!(retention.retains(&key(9), &live(&[9])));
+  assert!(retention.retains(&key(10), &live(&[])));
+}
+
+#[test]
+fn retains_keys_reinterned_since_previous_collection() {
+  let mut retention = Retention::default();
+  retention.collected(10, 10);
+
+  // An existing Key which is interned again might be in use somewhere that the caller of the
+  // collection cannot see, and so survives the next collection.
+  retention.interned(key(3));
+  assert!(retention.retains(&key(3), &live(&[])));
+  assert!(!retention.retains(&key(4), &live(&[])));
+
+  // But not the one after that, unless it is interned again.
+  retention.collected(20, 10);
+  assert!(!retention.retains(&key(3), &live(&[])));
+}
+
+#[test]
+fn retains_pinned_keys() {
+  let mut retention = Retention::default();
+  retention.pin(key(3));
+  retention.collected(10, 10);
+  assert!(retention.retains(&key(3), &live(&[])));
+  retention.collected(20, 10);
+  assert!(retention.retains(&key(3), &live(&[])));
+}
+
+#[test]
+fn should_collect() {
+  let mut retention = Retention::default();
+  assert!(!retention.should_collect(MIN_COLLECTION_SIZE - 1));
+  assert!(retention.should_collect(MIN_COLLECTION_SIZE));
+
+  // Once many Keys are retained, collect again only when their number has doubled.
+  retention.collected(0, MIN_COLLECTION_SIZE * 2);
+  assert!(!retention.should_collect(MIN_COLLECTION_SIZE * 4 - 1));
+  assert!(retention.should_collect(MIN_COLLECTION_SIZE * 4));
+}
+
+#[test]
+fn retains_held_keys_across_collections_until_released() {
+  let mut retention = Retention::default();
+
+  // One Session's request holds Keys for its roots (one of them twice, for two roots), while
+  // another Session's requests complete and collect repeatedly before the first request executes.
+  retention.hold(key(3));
+  retention.hold(key(4));
+  retention.hold(key(4));
+  retention.collected(10, 10);
+  retention.collected(20, 10);
+  assert!(retention.retains(&key(3), &live(&[])));
+  assert!(retention.retains(&key(4), &live(&[])));
+
+  // Once the request is destroyed, its Keys may be collected, unless they are held elsewhere.
+  retention.release(key(3));
+  retention.release(key(4));
+  assert!(!retention.retains(&key(3), &live(&[])));
+  assert!(retention.retains(&key(4), &live(&[])));
+  retention.release(key(4));
+  assert!(!retention.retains(&key(4), &live(&[])));
+}
@@ -33,17 +33,32 @@
 mod context;
 mod core;
 pub mod externs;
+mod graph_json;
 mod handles;
 mod interning;
+mod node_metrics;
 pub mod nodes;
 mod scheduler;
 mod selectors;
 mod tasks;
+mod trace_events;
 mod types;
 
-pub use crate::context::Core;
+pub use crate::context::{Core, RemoteExecutionBackend};
 pub use crate::core::{Function, Key, Params, TypeId, Value};
 pub use crate::handles::Handle;
-pub use crate::scheduler::{ExecutionRequest, RootResult, Scheduler, Session};
+pub use crate::scheduler::{
+  ExecutionRequest, ExecutionStream, GraphFilter, QueryRoots, RootResult, Scheduler, Session,
+  StreamPoll,
+};
 pub use crate::tasks::{Rule, Tasks};
 pub use crate::types::Types;
+
+#[cfg(test)]
+mod interning_tests;
+
+#[cfg(test)]
+mod node_metrics_tests;
+
+#[cfg(test)]
+mod trace_events_tests;
@@ -0,0 +1,246 @@
+// Copyright 2020 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use graph::NodeEvent;
+use process_execution::ProcessResultSource;
+use serde_json::json;
+
+use crate::core::Function;
+use crate::nodes::NodeKey;
+
+///
+/// The outcomes of the Nodes of one kind that were requested within a Session.
+///
+#[derive(Clone, Debug, Default)]
+pub struct NodeCounts {
+  pub created: u64,
+  pub cleaned: u64,
+  pub ran: u64,
+  pub reran: u64,
+  runtimes: RuntimeHistogram,
+}
+
+impl NodeCounts {
+  fn record(&mut self, event: NodeEvent) {
+    match event {
+      NodeEvent::Created => self.created += 1,
+      NodeEvent::Cleaned => self.cleaned += 1,
+      NodeEvent::Ran { reran, duration } => {
+        if reran {
+          self.reran += 1;
+        } else {
+          self.ran += 1;
+        }
+        self.runtimes.record(duration);
+      }
+    }
+  }
+
+  fn merge(&mut self, other: &NodeCounts) {
+    self.created += other.created;
+    self.cleaned += other.cleaned;
+    self.ran += other.ran;
+    self.reran += other.reran;
+    self.runtimes.merge(&other.runtimes);
+  }
+
+  pub fn total_runtime(&self) -> Duration {
+    self.runtimes.total()
+  }
+
+  ///
+  /// The approximate 95th percentile of the runtimes of the Nodes: see
+  /// `RuntimeHistogram::percentile`.
+  ///
+  pub fn p95_runtime(&self) -> Duration {
+    self.runtimes.percentile(95)
+  }
+
+  fn to_json(&self) -> serde_json::Value {
+    json!({
+      "created": self.created,
+      "cleaned": self.cleaned,
+      "ran": self.ran,
+      "reran": self.reran,
+      "total_runtime_ms": self.total_runtime().as_secs_f64() * 1000.0,
+      "p95_runtime_ms": self.p95_runtime().as_secs_f64() * 1000.0,
+    })
+  }
+}
+
+// Each power of two microseconds is split into this many buckets, which bounds the error of a
+// percentile to a quarter of its value.
+const SUB_BUCKET_BITS: u32 = 2;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+// Runtimes longer than this many microseconds (about twelve days) are recorded as this long.
+const MAX_RUNTIME_MICROS: u64 = (1 << 40) - 1;
+
+///
+/// A fixed size histogram of runtimes, bucketed logarithmically by microseconds, from which
+/// percentiles can be approximated without retaining each runtime.
+///
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RuntimeHistogram {
+  // The count of runtimes in each bucket, grown as far as the longest recorded runtime needs.
+  buckets: Vec<u64>,
+  count: u64,
+  total: Duration,
+}
+
+impl RuntimeHistogram {
+  pub(crate) fn record(&mut self, runtime: Duration) {
+    let micros = cmp::min(runtime.as_micros(), u128::from(MAX_RUNTIME_MICROS)) as u64;
+    let index = Self::bucket(micros);
+    if self.buckets.len() <= index {
+      self.buckets.resize(index + 1, 0);
+    }
+    self.buckets[index] += 1;
+    self.count += 1;
+    self.total += runtime;
+  }
+
+  pub(crate) fn merge(&mut self, other: &RuntimeHistogram) {
+    if self.buckets.len() < other.buckets.len() {
+      self.buckets.resize(other.buckets.len(), 0);
+    }
+    for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+      *bucket += count;
+    }
+    self.count += other.count;
+    self.total += other.total;
+  }
+
+  pub(crate) fn total(&self) -> Duration {
+    self.total
+  }
+
+  ///
+  /// The given percentile of the recorded runtimes (by the nearest-rank method), rounded down to
+  /// the lower bound of its bucket, or zero if none have been recorded.
+  ///
+  pub(crate) fn percentile(&self, percentile: u64) -> Duration {
+    if self.count == 0 {
+      return Duration::default();
+    }
+    let rank = cmp::max((self.count * percentile + 99) / 100, 1);
+    let mut seen = 0;
+    for (index, count) in self.buckets.iter().enumerate() {
+      seen += count;
+      if seen >= rank {
+        return Duration::from_micros(Self::lower_bound(index));
+      }
+    }
+    unreachable!("The buckets of a RuntimeHistogram must sum to its count.")
+  }
+
+  fn bucket(micros: u64) -> usize {
+    if micros < SUB_BUCKETS {
+      return micros as usize;
+    }
+    let exponent = 63 - u64::from(micros.leading_zeros());
+    let shift = exponent - u64::from(SUB_BUCKET_BITS);
+    let sub_bucket = (micros >> shift) - SUB_BUCKETS;
+    ((shift + 1) * SUB_BUCKETS + sub_bucket) as usize
+  }
+
+  fn lower_bound(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+      return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    (SUB_BUCKETS + index % SUB_BUCKETS) << shift
+  }
+}
+
+///
+/// Where the results of the processes executed within a Session came from.
+///
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessResultCounts {
+  pub local_cache: u64,
+  pub remote_cache: u64,
+  pub executed: u64,
+}
+
+///
+/// Metrics for the Nodes requested within a Session, broken down by NodeKey variant and (for
+/// Tasks) by @rule.
+///
+#[derive(Clone, Debug, Default)]
+pub struct NodeMetrics {
+  pub by_variant: HashMap<&'static str, NodeCounts>,
+  pub by_rule: HashMap<Function, NodeCounts>,
+  pub process_results: ProcessResultCounts,
+}
+
+impl NodeMetrics {
+  pub fn record(&mut self, node: &NodeKey, event: NodeEvent) {
+    self
+      .by_variant
+      .entry(node.variant_name())
+      .or_insert_with(NodeCounts::default)
+      .record(event);
+    if let Some(func) = node.rule_func() {
+      self
+        .by_rule
+        .entry(func)
+        .or_insert_with(NodeCounts::default)
+        .record(event);
+    }
+  }
+
+  pub fn record_process_result(&mut self, source: ProcessResultSource) {
+    let count = match source {
+      ProcessResultSource::LocalCache => &mut self.process_results.local_cache,
+      ProcessResultSource::RemoteCache => &mut self.process_results.remote_cache,
+      ProcessResultSource::Executed => &mut self.process_results.executed,
+    };
+    *count += 1;
+  }
+
+  ///
+  /// Renders these metrics as JSON, in the form:
+  ///
+  ///   {
+  ///     "variants": {variant: counts, ...},
+  ///     "rules": {rule: counts, ...},
+  ///     "processes": {"local_cache", "remote_cache", "executed"}
+  ///   }
+  ///
+  /// where counts are {"created", "cleaned", "ran", "reran", "total_runtime_ms", "p95_runtime_ms"}.
+  /// Rules are named by their function names, and the counts of rules with the same name are
+  /// merged.
+  ///
+  pub fn to_json(&self) -> serde_json::Value {
+    let variants: BTreeMap<_, _> = self
+      .by_variant
+      .iter()
+      .map(|(variant, counts)| (variant.to_string(), counts.to_json()))
+      .collect();
+    let mut counts_by_rule_name: BTreeMap<String, NodeCounts> = BTreeMap::new();
+    for (func, counts) in &self.by_rule {
+      counts_by_rule_name
+        .entry(func.to_string())
+        .or_insert_with(NodeCounts::default)
+        .merge(counts);
+    }
+    let rules: BTreeMap<_, _> = counts_by_rule_name
+      .iter()
+      .map(|(rule_name, counts)| (rule_name.clone(), counts.to_json()))
+      .collect();
+    json!({
+      "variants": variants,
+      "rules": rules,
+      "processes": {
+        "local_cache": self.process_results.local_cache,
+        "remote_cache": self.process_results.remote_cache,
+        "executed": self.process_results.executed,
+      },
+    })
+  }
+}
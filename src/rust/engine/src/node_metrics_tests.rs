@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use crate::node_metrics::RuntimeHistogram;
+
+fn histogram(millis: &[u64]) -> RuntimeHistogram {
+  let mut histogram = RuntimeHistogram::default();
+  for &millis in millis {
+    histogram.record(Duration::from_millis(millis));
+  }
+  histogram
+}
+
+#[test]
+fn percentile_of_empty_histogram() {
+  assert_eq!(
+    RuntimeHistogram::default().percentile(95),
+    Duration::default()
+  );
+}
+
+#[test]
+fn percentile_is_within_a_quarter() {
+  let runtimes: Vec<u64> = (1..=100).collect();
+  let histogram = histogram(&runtimes);
+  for &(percentile, exact) in &[(1, 1), (50, 50), (95, 95), (100, 100)] {
+    let approximate = histogram.percentile(percentile);
+    assert!(approximate <= Duration::from_millis(exact));
+    assert!(approximate * 4 > Duration::from_millis(exact) * 3);
+  }
+}
+
+#[test]
+fn percentile_of_small_runtimes_is_exact() {
+  let histogram = histogram(&[0, 0, 0]);
+  assert_eq!(histogram.percentile(95), Duration::default());
+
+  let mut histogram = RuntimeHistogram::default();
+  histogram.record(Duration::from_micros(3));
+  assert_eq!(histogram.percentile(95), Duration::from_micros(3));
+}
+
+#[test]
+fn long_runtimes_are_bounded() {
+  let histogram = histogram(&[u64::max_value()]);
+  assert!(histogram.percentile(95) > Duration::from_secs(60 * 60 * 24));
+}
+
+#[test]
+fn merge() {
+  let mut merged = histogram(&[1, 2]);
+  merged.merge(&histogram(&[1000, 1000]));
+  let all = histogram(&[1, 2, 1000, 1000]);
+  assert_eq!(merged.percentile(50), all.percentile(50));
+  assert_eq!(merged.percentile(95), all.percentile(95));
+  assert_eq!(merged.total(), Duration::from_millis(2003));
+}
@@ -1,9 +1,11 @@
 // Copyright 2017 Pants project contributors (see CONTRIBUTORS.md).
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+use std::cmp;
 use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::fmt::Display;
+use std::hash::{Hash, Hasher};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
@@ -11,12 +13,13 @@ use std::time::Duration;
 use std::{self, fmt};
 
 use concrete_time::TimeSpan;
+use fnv::FnvHasher;
 use futures::future::{self, Future};
 use futures::Stream;
 use url::Url;
 
 use crate::context::{Context, Core};
-use crate::core::{throw, Failure, Key, Params, TypeId, Value};
+use crate::core::{throw, Failure, Function, Key, Params, RuleFrame, TypeId, Value};
 use crate::externs;
 use crate::selectors;
 use crate::tasks::{self, Intrinsic, Rule};
@@ -63,6 +66,7 @@ impl VFS<Failure> for Context {
     Failure::Throw(
       externs::create_exception(msg),
       "<pants native internals>".to_string(),
+      vec![],
     )
   }
 }
@@ -399,12 +403,44 @@ pub fn lift_digest(digest: &Value) -> Result<hashing::Digest, String> {
   ))
 }
 
+///
+/// The identity of a process that is used to remember its priority between runs: see
+/// `MultiPlatformExecuteProcess::identity`.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ProcessIdentity(u64);
+
 /// A Node that represents a set of processes to execute on specific platforms.
 ///
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub struct MultiPlatformExecuteProcess(MultiPlatformExecuteProcessRequest);
 
 impl MultiPlatformExecuteProcess {
+  ///
+  /// An identity for the process which is stable across changes to its inputs: a hash of the
+  /// platforms, arguments and environment of each of its requests.
+  ///
+  pub fn identity(&self) -> ProcessIdentity {
+    let mut hasher = FnvHasher::default();
+    for (platforms, req) in &(self.0).0 {
+      platforms.hash(&mut hasher);
+      req.argv.hash(&mut hasher);
+      req.env.hash(&mut hasher);
+    }
+    ProcessIdentity(hasher.finish())
+  }
+
+  ///
+  /// Combines the length (in milliseconds) of the longest chain of work through a process when it
+  /// last ran with the number of Nodes waiting on it, into a priority which orders processes by the
+  /// former and breaks ties with the latter.
+  ///
+  fn priority(chain_millis: u64, dependents: usize) -> u64 {
+    let chain_millis = cmp::min(chain_millis, u64::max_value() >> 16);
+    let dependents = cmp::min(dependents as u64, u64::from(u16::max_value()));
+    (chain_millis << 16) | dependents
+  }
+
   fn lift_execute_process(
     value: &Value,
     target_platform: Platform,
@@ -455,6 +491,46 @@ impl MultiPlatformExecuteProcess {
 
     let is_nailgunnable = externs::project_bool(&value, "is_nailgunnable");
 
+    let cache_failures = externs::project_bool(&value, "cache_failures");
+
+    let retry_max_attempts = externs::project_str(&value, "retry_max_attempts")
+      .parse::<usize>()
+      .map_err(|err| format!("retry_max_attempts was not a non-negative int: {:?}", err))?;
+    let retry_policy = if retry_max_attempts > 1 {
+      let retryable_exit_codes = externs::project_multi_strs(&value, "retryable_exit_codes")
+        .into_iter()
+        .map(|code| {
+          code
+            .parse::<i32>()
+            .map_err(|err| format!("Retryable exit code was not an int: {:?}", err))
+        })
+        .collect::<Result<_, _>>()?;
+      Some(process_execution::RetryPolicy {
+        max_attempts: retry_max_attempts,
+        retryable_exit_codes,
+      })
+    } else {
+      None
+    };
+
+    let execution_priority = {
+      let val = externs::project_str(&value, "execution_priority");
+      if val.is_empty() {
+        None
+      } else {
+        Some(
+          val
+            .parse::<i32>()
+            .map_err(|err| format!("execution_priority was not an int: {:?}", err))?,
+        )
+      }
+    };
+
+    let cache_policy = process_execution::CachePolicy {
+      skip_cache_lookup: externs::project_bool(&value, "skip_cache_lookup"),
+      do_not_cache: externs::project_bool(&value, "do_not_cache"),
+    };
+
     let unsafe_local_only_files_because_we_favor_speed_over_correctness_for_this_rule =
       lift_digest(&externs::project_ignoring_type(
         &value,
@@ -475,6 +551,10 @@ impl MultiPlatformExecuteProcess {
       jdk_home,
       target_platform,
       is_nailgunnable,
+      cache_failures,
+      retry_policy,
+      execution_priority,
+      cache_policy,
     })
   }
 
@@ -524,10 +604,21 @@ impl WrappedNode for MultiPlatformExecuteProcess {
   type Item = ProcessResult;
 
   fn run(self, context: Context) -> NodeFuture<ProcessResult> {
-    let request = self.0;
+    // Prefer to run processes which were on a long chain of work the last time they ran. Processes
+    // which have not run before are ordered by the number of Nodes waiting on them instead, which
+    // is only counted in that case, because it requires the Graph lock.
+    let priority = match context.core.process_priority(self.identity()) {
+      Some(chain_millis) => Self::priority(chain_millis, 0),
+      None => Self::priority(0, context.core.graph.dependent_count(context.entry_id)),
+    };
+    let request = self.0.with_defaults(
+      context.session.process_execution_priority(),
+      context.session.process_execution_cache_policy(),
+    );
     let execution_context = process_execution::Context {
       workunit_store: context.session.workunit_store(),
       build_id: context.session.build_id().to_string(),
+      priority,
     };
     if context
       .core
@@ -535,11 +626,15 @@ impl WrappedNode for MultiPlatformExecuteProcess {
       .extract_compatible_request(&request)
       .is_some()
     {
+      let session = context.session.clone();
       context
         .core
         .command_runner
         .run(request, execution_context)
-        .map(ProcessResult)
+        .map(move |result| {
+          session.record_process_result(result.source());
+          ProcessResult(result)
+        })
         .map_err(|e| throw(&format!("Failed to execute process: {}", e)))
         .to_boxed()
     } else {
@@ -1075,6 +1170,7 @@ impl WrappedNode for Task {
     let func = self.task.func;
     let entry = self.entry;
     let product = self.product;
+    let frame_params = params.clone();
     deps
       .then(move |deps_result| match deps_result {
         Ok(deps) => externs::call(&externs::val_for(&func.0), &deps),
@@ -1091,6 +1187,13 @@ impl WrappedNode for Task {
         },
         Err(failure) => err(failure),
       })
+      .map_err(move |failure| {
+        failure.with_rule_frame(|| RuleFrame {
+          rule: func.to_string(),
+          product: product.to_string(),
+          params: frame_params.to_string(),
+        })
+      })
       .to_boxed()
   }
 }
@@ -1116,7 +1219,7 @@ impl NodeVisualizer<NodeKey> for Visualizer {
     match entry.peek() {
       None => "white".to_string(),
       Some(Err(Failure::Throw(..))) => "4".to_string(),
-      Some(Err(Failure::Invalidated)) => "12".to_string(),
+      Some(Err(Failure::Invalidated)) | Some(Err(Failure::Cancelled)) => "12".to_string(),
       Some(Ok(_)) => {
         let viz_colors_len = self.viz_colors.len();
         self
@@ -1134,7 +1237,7 @@ pub struct Tracer;
 impl NodeTracer<NodeKey> for Tracer {
   fn is_bottom(result: Option<Result<NodeResult, Failure>>) -> bool {
     match result {
-      Some(Err(Failure::Invalidated)) => false,
+      Some(Err(Failure::Invalidated)) | Some(Err(Failure::Cancelled)) => false,
       Some(Err(Failure::Throw(..))) => false,
       Some(Ok(_)) => true,
       None => {
@@ -1150,7 +1253,7 @@ impl NodeTracer<NodeKey> for Tracer {
     match result {
       None => "<None>".to_string(),
       Some(Ok(ref x)) => format!("{:?}", x),
-      Some(Err(Failure::Throw(ref x, ref traceback))) => format!(
+      Some(Err(Failure::Throw(ref x, ref traceback, _))) => format!(
         "Throw({})\n{}",
         externs::val_to_str(x),
         traceback
@@ -1160,6 +1263,7 @@ impl NodeTracer<NodeKey> for Tracer {
           .join("\n")
       ),
       Some(Err(Failure::Invalidated)) => "Invalidated".to_string(),
+      Some(Err(Failure::Cancelled)) => "Cancelled".to_string(),
     }
   }
 }
@@ -1193,6 +1297,51 @@ impl NodeKey {
     }
   }
 
+  ///
+  /// The name of this NodeKey's variant, which allows for filtering Nodes by kind.
+  ///
+  pub fn variant_name(&self) -> &'static str {
+    match self {
+      &NodeKey::DigestFile(..) => "DigestFile",
+      &NodeKey::DownloadedFile(..) => "DownloadedFile",
+      &NodeKey::MultiPlatformExecuteProcess(..) => "MultiPlatformExecuteProcess",
+      &NodeKey::ReadLink(..) => "ReadLink",
+      &NodeKey::Scandir(..) => "Scandir",
+      &NodeKey::Select(..) => "Select",
+      &NodeKey::Snapshot(..) => "Snapshot",
+      &NodeKey::Task(..) => "Task",
+    }
+  }
+
+  ///
+  /// If this Node runs a @rule, the function of the rule.
+  ///
+  pub fn rule_func(&self) -> Option<Function> {
+    match self {
+      &NodeKey::Task(ref s) => Some(s.task.func),
+      _ => None,
+    }
+  }
+
+  ///
+  /// The Nodes which might operate on the given path (i.e., those for which `fs_subject` would
+  /// return it), without consulting the filesystem.
+  ///
+  pub fn fs_nodes(path: &Path) -> Vec<NodeKey> {
+    vec![
+      NodeKey::DigestFile(DigestFile(File {
+        path: path.to_owned(),
+        is_executable: false,
+      })),
+      NodeKey::DigestFile(DigestFile(File {
+        path: path.to_owned(),
+        is_executable: true,
+      })),
+      NodeKey::ReadLink(ReadLink(Link(path.to_owned()))),
+      NodeKey::Scandir(Scandir(Dir(path.to_owned()))),
+    ]
+  }
+
   pub fn fs_subject(&self) -> Option<&Path> {
     match self {
       &NodeKey::DigestFile(ref s) => Some(s.0.path.as_path()),
@@ -1210,6 +1359,30 @@ impl NodeKey {
       | &NodeKey::DownloadedFile { .. } => None,
     }
   }
+
+  ///
+  /// The interned Keys that this NodeKey refers to, which must not be collected while it is in use.
+  ///
+  pub fn keys(&self) -> Vec<Key> {
+    match self {
+      &NodeKey::Select(ref s) => s.params.keys().cloned().collect(),
+      &NodeKey::Task(ref t) => t
+        .params
+        .keys()
+        .cloned()
+        .chain(std::iter::once(t.task.func.0))
+        .collect(),
+      &NodeKey::Snapshot(ref s) => vec![s.0],
+      &NodeKey::DownloadedFile(ref d) => vec![d.0],
+
+      // Explicitly listed so that if people add new NodeKeys they need to consider whether they
+      // refer to interned Keys.
+      &NodeKey::DigestFile { .. }
+      | &NodeKey::MultiPlatformExecuteProcess { .. }
+      | &NodeKey::ReadLink { .. }
+      | &NodeKey::Scandir { .. } => vec![],
+    }
+  }
 }
 
 impl Node for NodeKey {
@@ -1299,6 +1472,24 @@ impl Node for NodeKey {
       NodeKey::Select(..) => None,
     }
   }
+
+  fn describe_dependency(&self, dependency: &NodeKey) -> Option<String> {
+    match (self, dependency) {
+      (&NodeKey::Task(ref task), &NodeKey::Select(ref select)) => {
+        let get = task.task.gets.iter().find(|get| {
+          get.product == select.product && select.params.type_ids().any(|ty| ty == get.subject)
+        });
+        Some(match get {
+          Some(get) => format!("requested via {} by rule {}", get, task.task.func),
+          None => format!("requested as a parameter of rule {}", task.task.func),
+        })
+      }
+      (&NodeKey::Select(..), &NodeKey::Task(ref task)) => {
+        Some(format!("computed by rule {}", task.task.func))
+      }
+      _ => None,
+    }
+  }
 }
 
 impl Display for NodeKey {
@@ -1323,15 +1514,35 @@ impl NodeError for Failure {
     Failure::Invalidated
   }
 
-  fn cyclic(mut path: Vec<String>) -> Failure {
-    let path_len = path.len();
-    if path_len > 1 {
-      path[0] += " <-";
-      path[path_len - 1] += " <-"
+  fn cancelled() -> Failure {
+    Failure::Cancelled
+  }
+
+  fn cyclic(paths: Vec<Vec<String>>) -> Failure {
+    let render = |mut path: Vec<String>, indent: &str| {
+      let path_len = path.len();
+      if path_len > 1 {
+        path[0] += " <-";
+        path[path_len - 1] += " <-"
+      }
+      path.join(&format!("\n{}", indent))
+    };
+    if paths.len() == 1 {
+      let path = paths.into_iter().next().unwrap();
+      return throw(&format!(
+        "Dep graph contained a cycle:\n  {}",
+        render(path, "  ")
+      ));
     }
+    let rendered = paths
+      .into_iter()
+      .enumerate()
+      .map(|(index, path)| format!("Cycle {}:\n    {}", index + 1, render(path, "    ")))
+      .collect::<Vec<_>>();
     throw(&format!(
-      "Dep graph contained a cycle:\n  {}",
-      path.join("\n  ")
+      "Dep graph contained {} cycles:\n  {}",
+      rendered.len(),
+      rendered.join("\n  ")
     ))
   }
 }
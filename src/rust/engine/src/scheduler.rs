@@ -1,24 +1,37 @@
 // Copyright 2017 Pants project contributors (see CONTRIBUTORS.md).
 // Licensed under the Apache License, Version 2.0 (see LICENSE).
 
-use std::collections::{HashMap, HashSet};
+use std::cmp;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryInto;
-use std::io;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::mem;
 use std::path::{Path, PathBuf};
-use std::sync::{mpsc, Arc};
-use std::time::Duration;
+use std::sync::{mpsc, Arc, Weak};
+use std::time::{Duration, Instant};
 
-use futures::future::{self, Future};
+use futures::future::{self, Either, Future, Shared};
+use futures::sync::oneshot;
 
 use crate::context::{Context, Core};
-use crate::core::{Failure, Params, TypeId, Value};
-use crate::nodes::{NodeKey, Select, Tracer, Visualizer};
-use graph::{EntryId, Graph, InvalidationResult, NodeContext};
+use crate::core::{throw, Failure, Key, Params, TypeId, Value, FNV};
+use crate::externs;
+use crate::graph_json;
+use crate::node_metrics::NodeMetrics;
+use crate::nodes::{NodeKey, ProcessIdentity, Select, Tracer, Visualizer};
+use crate::trace_events;
+use graph::{
+  EntryId, EvictionBudget, Graph, InvalidatedNode, InvalidationResult, InvalidationState,
+  NodeContext, NodeEvent,
+};
 use indexmap::IndexMap;
 use log::{debug, info, warn};
 use logging::logger::LOGGER;
 use parking_lot::Mutex;
+use process_execution::{CachePolicy, ProcessResultSource};
 use ui::EngineDisplay;
+use uuid::Uuid;
 use workunit_store::WorkUnitStore;
 
 ///
@@ -42,6 +55,18 @@ struct InnerSession {
   workunit_store: WorkUnitStore,
   build_id: String,
   should_report_workunits: bool,
+  // The priority for processes executed in this Session which do not set their own.
+  process_execution_priority: Option<i32>,
+  // A cache policy which is combined with that of each process executed in this Session.
+  process_execution_cache_policy: CachePolicy,
+  // Sent a value when the Session is cancelled.
+  cancel_sender: Mutex<Option<oneshot::Sender<()>>>,
+  cancelled: Shared<oneshot::Receiver<()>>,
+  // Metrics for the Nodes that have completed on behalf of this Session.
+  node_metrics: Mutex<NodeMetrics>,
+  // The process Nodes which have run on behalf of this Session since their priorities were last
+  // recorded.
+  ran_processes: Mutex<Vec<NodeKey>>,
 }
 
 #[derive(Clone)]
@@ -55,6 +80,8 @@ impl Session {
     ui_worker_count: usize,
     build_id: String,
     should_report_workunits: bool,
+    process_execution_priority: Option<i32>,
+    process_execution_cache_policy: CachePolicy,
   ) -> Session {
     let display = if should_render_ui && EngineDisplay::stdout_is_tty() {
       let mut display = EngineDisplay::new(0);
@@ -64,6 +91,7 @@ impl Session {
       None
     };
 
+    let (cancel_sender, cancelled) = oneshot::channel();
     let inner_session = InnerSession {
       preceding_graph_size: scheduler.core.graph.len(),
       roots: Mutex::new(HashSet::new()),
@@ -72,8 +100,22 @@ impl Session {
       workunit_store: WorkUnitStore::new(),
       build_id,
       should_report_workunits,
+      process_execution_priority,
+      process_execution_cache_policy,
+      cancel_sender: Mutex::new(Some(cancel_sender)),
+      cancelled: cancelled.shared(),
+      node_metrics: Mutex::new(NodeMetrics::default()),
+      ran_processes: Mutex::new(Vec::new()),
     };
-    Session(Arc::new(inner_session))
+    let session = Session(Arc::new(inner_session));
+    {
+      let mut sessions = scheduler.sessions.lock();
+      // Forget Sessions which have since been dropped, so that the list does not grow without bound
+      // when the Graph is never evicted.
+      sessions.retain(|session| session.upgrade().is_some());
+      sessions.push(Arc::downgrade(&session.0));
+    }
+    session
   }
 
   fn extend(&self, new_roots: &[Root]) {
@@ -110,6 +152,50 @@ impl Session {
     &self.0.build_id
   }
 
+  pub fn process_execution_priority(&self) -> Option<i32> {
+    self.0.process_execution_priority
+  }
+
+  pub fn process_execution_cache_policy(&self) -> CachePolicy {
+    self.0.process_execution_cache_policy
+  }
+
+  pub fn record_node_event(&self, node: &NodeKey, event: NodeEvent) {
+    if let (NodeKey::MultiPlatformExecuteProcess(_), NodeEvent::Ran { .. }) = (node, event) {
+      self.0.ran_processes.lock().push(node.clone());
+    }
+    self.0.node_metrics.lock().record(node, event);
+  }
+
+  pub fn record_process_result(&self, source: ProcessResultSource) {
+    self.0.node_metrics.lock().record_process_result(source);
+  }
+
+  pub fn node_metrics(&self) -> NodeMetrics {
+    self.0.node_metrics.lock().clone()
+  }
+
+  fn take_ran_processes(&self) -> Vec<NodeKey> {
+    mem::replace(&mut *self.0.ran_processes.lock(), Vec::new())
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.0.cancel_sender.lock().is_none()
+  }
+
+  fn cancel(&self) {
+    if let Some(cancel_sender) = self.0.cancel_sender.lock().take() {
+      let _ = cancel_sender.send(());
+    }
+  }
+
+  ///
+  /// A Future that completes when this Session is cancelled.
+  ///
+  fn cancelled(&self) -> impl Future<Item = (), Error = ()> {
+    self.0.cancelled.clone().map(|_| ()).map_err(|_| ())
+  }
+
   pub fn write_stdout(&self, msg: &str) {
     if let Some(display) = self.maybe_display() {
       let mut d = display.lock();
@@ -145,11 +231,28 @@ impl Session {
 pub struct ExecutionRequest {
   // Set of roots for an execution, in the order they were declared.
   pub roots: Vec<Root>,
+  // If set, roots which have not completed this long after `Scheduler::execute` is called fail.
+  pub timeout: Option<Duration>,
+  // Keys used by the roots which must not be collected until the request is dropped: until it is
+  // executed, nothing else records that they are in use.
+  held_keys: Vec<Key>,
 }
 
 impl ExecutionRequest {
   pub fn new() -> ExecutionRequest {
-    ExecutionRequest { roots: Vec::new() }
+    ExecutionRequest {
+      roots: Vec::new(),
+      timeout: None,
+      held_keys: Vec::new(),
+    }
+  }
+
+  ///
+  /// Takes ownership of holds on the given Keys (see `externs::held_key_for`), which are released
+  /// when this request is dropped.
+  ///
+  pub fn hold_keys(&mut self, keys: Vec<Key>) {
+    self.held_keys.extend(keys);
   }
 
   ///
@@ -161,17 +264,110 @@ impl ExecutionRequest {
   }
 }
 
+impl Drop for ExecutionRequest {
+  fn drop(&mut self) {
+    if !self.held_keys.is_empty() {
+      externs::release_keys(&self.held_keys);
+    }
+  }
+}
+
+///
+/// The outcome of polling an ExecutionStream.
+///
+pub enum StreamPoll {
+  /// The root at the given index in the ExecutionRequest completed.
+  Completed(usize, RootResult),
+  /// No root completed while waiting.
+  Pending,
+  /// Every root has completed, and its result has been returned by a previous poll.
+  Done,
+}
+
+type StopSender = Arc<Mutex<Option<oneshot::Sender<()>>>>;
+
+///
+/// The results of the roots of an ExecutionRequest, which are returned in the order that the roots
+/// complete. Created by `Scheduler::execute_streaming`, polled with `Scheduler::poll_stream`, and
+/// consumed by `Scheduler::finish_stream`.
+///
+pub struct ExecutionStream {
+  request: ExecutionRequest,
+  session: Session,
+  receiver: mpsc::Receiver<(usize, RootResult)>,
+  // Results which are ready to be returned by the next poll, in the order that they completed.
+  ready: VecDeque<(usize, RootResult)>,
+  // Whether the result of each root has been made ready or returned. Results received for roots
+  // which have already been returned (because they timed out) are ignored.
+  delivered: Vec<bool>,
+  deadline: Option<Instant>,
+  // Sent a value (or dropped) to cause any roots which have not completed to stop waiting.
+  stop_sender: StopSender,
+  // True if the roots were started, and so the request must be recorded as complete.
+  started: bool,
+  display_handle: Option<Uuid>,
+  tasks_to_display: IndexMap<String, Duration>,
+}
+
+impl ExecutionStream {
+  fn deliver(&mut self, index: usize, result: RootResult) {
+    if !self.delivered[index] {
+      self.delivered[index] = true;
+      self.ready.push_back((index, result));
+    }
+  }
+
+  fn stop(&self) {
+    Scheduler::stop_roots(&self.stop_sender);
+  }
+}
+
+///
+/// The Nodes from which a query over the Graph begins.
+///
+#[derive(Clone, Copy)]
+pub enum QueryRoots<'a> {
+  /// The roots of an ExecutionRequest.
+  Request(&'a ExecutionRequest),
+  /// The Nodes which operate on the given paths: see `NodeKey::fs_nodes`.
+  Paths(&'a HashSet<PathBuf>),
+}
+
+///
+/// Filters for the Nodes that are included by `Scheduler::write_graph_json`.
+///
+#[derive(Clone, Debug, Default)]
+pub struct GraphFilter {
+  /// If non-empty, only Nodes with these `NodeKey::variant_name`s are included.
+  pub variants: HashSet<String>,
+  /// If set, only Nodes which have run for at least this long are included.
+  pub min_duration: Option<Duration>,
+}
+
+// The number of the longest running Nodes to name when a request times out.
+const TIMEOUT_REPORTED_NODES: usize = 10;
+
 ///
 /// Represents the state of an execution of a Graph.
 ///
 pub struct Scheduler {
   pub core: Arc<Core>,
+  // The number of calls to `execute` that are in progress, across all Sessions. Interned Keys are
+  // only collected while holding this lock with no requests in progress.
+  executing_requests: Mutex<usize>,
+  // The Sessions that are still alive: Graph entries reachable from their roots are not evicted.
+  sessions: Mutex<Vec<Weak<InnerSession>>>,
+  // The size beyond which unreachable Graph entries are evicted after each request.
+  graph_eviction_budget: EvictionBudget,
 }
 
 impl Scheduler {
-  pub fn new(core: Core) -> Scheduler {
+  pub fn new(core: Core, graph_eviction_budget: EvictionBudget) -> Scheduler {
     Scheduler {
       core: Arc::new(core),
+      executing_requests: Mutex::new(0),
+      sessions: Mutex::new(Vec::new()),
+      graph_eviction_budget,
     }
   }
 
@@ -182,6 +378,68 @@ impl Scheduler {
       .visualize(Visualizer::default(), &session.root_nodes(), path)
   }
 
+  ///
+  /// Writes the Nodes below the roots of the given Session (or, if a subgraph request is given,
+  /// below the roots of that request) which match the given filter to the given path as JSON (see
+  /// `graph_json::write`). Filtered out Nodes are elided, with their dependents connected to their
+  /// nearest matching dependencies.
+  ///
+  pub fn write_graph_json(
+    &self,
+    session: &Session,
+    subgraph_request: Option<&ExecutionRequest>,
+    filter: &GraphFilter,
+    path: &Path,
+  ) -> Result<(), String> {
+    let roots = match subgraph_request {
+      Some(request) => request.root_nodes(),
+      None => session.root_nodes(),
+    };
+    let snapshots = self.core.graph.snapshot(&roots, |snapshot| {
+      let matches_variant =
+        filter.variants.is_empty() || filter.variants.contains(snapshot.node.variant_name());
+      let matches_duration = match (filter.min_duration, snapshot.duration) {
+        (Some(min_duration), Some(duration)) => duration >= min_duration,
+        (Some(_), None) => false,
+        (None, _) => true,
+      };
+      matches_variant && matches_duration
+    });
+    graph_json::write(path, &snapshots)
+  }
+
+  ///
+  /// Writes a Chrome `trace_event` profile of the Nodes that have run for the given Session, and of
+  /// its workunits, to the given path.
+  ///
+  pub fn write_trace_events(&self, session: &Session, path: &Path) -> Result<(), String> {
+    let roots = session.root_nodes();
+    let timings = self.core.graph.completed_timings(&roots);
+    let (_, critical_path) = self.core.graph.critical_path(&roots, &|entry| {
+      entry
+        .completed_span()
+        .map_or_else(|| Duration::from_secs(0), |(_, duration)| duration)
+    });
+    let critical_path = critical_path
+      .into_iter()
+      .map(|entry| entry.node().clone())
+      .collect();
+    let workunits = session.workunit_store().get_workunits();
+    let workunits = workunits.lock();
+    trace_events::write(path, &timings, &critical_path, &workunits.workunits)
+  }
+
+  ///
+  /// Writes the Node metrics of the given Session to the given path as JSON (see
+  /// `NodeMetrics::to_json`).
+  ///
+  pub fn write_node_metrics(&self, session: &Session, path: &Path) -> Result<(), String> {
+    let file = File::create(path)
+      .map_err(|e| format!("Failed to create metrics report {:?}: {}", path, e))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &session.node_metrics().to_json())
+      .map_err(|e| format!("Failed to write metrics report to {:?}: {}", path, e))
+  }
+
   pub fn trace(&self, request: &ExecutionRequest, path: &Path) -> Result<(), String> {
     self
       .core
@@ -243,6 +501,75 @@ impl Scheduler {
     cleared + dirtied
   }
 
+  ///
+  /// Explains the most recent invalidation that affected each root of the given request, by
+  /// rendering the chains from each invalidated Node (generally: a changed file) through its
+  /// dependents to the root, including whether each of them re-ran or was cleaned.
+  ///
+  pub fn invalidation_chains(&self, request: &ExecutionRequest) -> Vec<String> {
+    request
+      .root_nodes()
+      .iter()
+      .flat_map(|root| self.core.graph.invalidation_chains(root))
+      .map(|chain| {
+        chain
+          .into_iter()
+          .map(|InvalidatedNode { node, state }| {
+            let state_str = match state {
+              InvalidationState::Cleared => "changed",
+              InvalidationState::Dirtied => "dirty",
+              InvalidationState::Cleaned => "cleaned",
+              InvalidationState::Reran => "re-ran",
+            };
+            format!("{} ({})", node, state_str)
+          })
+          .collect::<Vec<_>>()
+          .join(" -> ")
+      })
+      .collect()
+  }
+
+  ///
+  /// Returns the transitive dependencies (or if `dependents` is true, the transitive dependents)
+  /// of the given roots which are currently in the Graph, along with their distance from the
+  /// nearest root, ordered by distance.
+  ///
+  /// If `max_depth` is set, the query stops at that distance from the roots. If `variants` is
+  /// non-empty, only Nodes whose `NodeKey::variant_name` is in `variants` are returned (although
+  /// the query still walks through other Nodes).
+  ///
+  pub fn query_graph(
+    &self,
+    roots: QueryRoots<'_>,
+    dependents: bool,
+    max_depth: Option<usize>,
+    variants: &HashSet<String>,
+  ) -> Vec<(NodeKey, usize)> {
+    let root_nodes: Vec<NodeKey> = match roots {
+      QueryRoots::Request(request) => request.root_nodes(),
+      QueryRoots::Paths(paths) => paths
+        .iter()
+        .flat_map(|path| NodeKey::fs_nodes(path))
+        .collect(),
+    };
+    let mut matches = if dependents {
+      self
+        .core
+        .graph
+        .transitive_dependents(&root_nodes, max_depth)
+    } else {
+      self
+        .core
+        .graph
+        .transitive_dependencies(&root_nodes, max_depth)
+    };
+    if !variants.is_empty() {
+      matches.retain(|(node, _)| variants.contains(node.variant_name()));
+    }
+    matches.sort_by_cached_key(|(node, depth)| (*depth, node.to_string()));
+    matches
+  }
+
   ///
   /// Return Scheduler and per-Session metrics.
   ///
@@ -260,15 +587,131 @@ impl Scheduler {
       session.preceding_graph_size() as i64,
     );
     m.insert("resulting_graph_size", self.core.graph.len() as i64);
+    m.insert("evicted_node_count", self.core.graph.evicted_count() as i64);
+    m.insert(
+      "live_interned_key_count",
+      externs::interned_key_count() as i64,
+    );
     m
   }
 
+  ///
+  /// Returns the roots of all Sessions that are still alive, and forgets any that are not.
+  ///
+  fn live_session_roots(&self) -> Vec<NodeKey> {
+    let mut roots = Vec::new();
+    self.sessions.lock().retain(|session| {
+      if let Some(session) = session.upgrade() {
+        roots.extend(Session(session).root_nodes());
+        true
+      } else {
+        false
+      }
+    });
+    roots
+  }
+
+  ///
+  /// If the Graph has grown beyond the eviction budget, evicts entries that are not reachable from
+  /// the roots of any live Session.
+  ///
+  fn evict_graph(&self) {
+    if self.graph_eviction_budget == EvictionBudget::default() {
+      return;
+    }
+    let roots = self.live_session_roots();
+    self.core.graph.evict(&roots, &self.graph_eviction_budget);
+  }
+
+  ///
+  /// If enough values have been interned since the last collection, collects the interned Keys
+  /// which are no longer used by any entry in the Graph or by the roots of any live Session.
+  ///
+  /// Must only be called while no requests are executing.
+  ///
+  fn collect_interns(&self) {
+    if !externs::should_collect_interns() {
+      return;
+    }
+    let mut live_keys: HashSet<Key, FNV> = HashSet::default();
+    for root in self.live_session_roots() {
+      live_keys.extend(root.keys());
+    }
+    self
+      .core
+      .graph
+      .visit_nodes(|node| live_keys.extend(node.keys()));
+    let collected = externs::collect_interns(&live_keys);
+    debug!(
+      "Collected {} interned keys, leaving {}.",
+      collected,
+      externs::interned_key_count()
+    );
+  }
+
+  ///
+  /// Records the duration of the longest chain of work through each process that has run on behalf
+  /// of the given Session since this was last called (i.e.: the length of the critical path through
+  /// it, from the start of the process to the completion of its furthest dependent), to be used as
+  /// the priority of the process the next time it runs.
+  ///
+  fn record_process_priorities(&self, session: &Session) {
+    let processes = session.take_ran_processes();
+    if processes.is_empty() {
+      return;
+    }
+    // Only the processes and their transitive dependents can be on a chain through a process.
+    let timings = self.core.graph.completed_dependent_timings(&processes);
+    let indexes: HashMap<&NodeKey, usize> = timings
+      .iter()
+      .enumerate()
+      .map(|(index, timing)| (&timing.node, index))
+      .collect();
+
+    // Visit each Node after all of its dependents, accumulating the longest chain of dependents
+    // above each Node.
+    let mut pending_dependents = vec![0; timings.len()];
+    for timing in &timings {
+      for dependency in &timing.dependencies {
+        if let Some(&index) = indexes.get(dependency) {
+          pending_dependents[index] += 1;
+        }
+      }
+    }
+    let mut dependent_chains = vec![Duration::default(); timings.len()];
+    let mut ready: Vec<usize> = (0..timings.len())
+      .filter(|&index| pending_dependents[index] == 0)
+      .collect();
+    let mut priorities: HashMap<ProcessIdentity, u64> = HashMap::new();
+    while let Some(index) = ready.pop() {
+      let timing = &timings[index];
+      let chain = dependent_chains[index] + timing.duration;
+      if let NodeKey::MultiPlatformExecuteProcess(ref process) = timing.node {
+        let priority = priorities.entry(process.identity()).or_insert(0);
+        *priority = (*priority).max(chain.as_millis() as u64);
+      }
+      for dependency in &timing.dependencies {
+        if let Some(&dep_index) = indexes.get(dependency) {
+          dependent_chains[dep_index] = dependent_chains[dep_index].max(chain);
+          pending_dependents[dep_index] -= 1;
+          if pending_dependents[dep_index] == 0 {
+            ready.push(dep_index);
+          }
+        }
+      }
+    }
+    self.core.record_process_priorities(priorities);
+  }
+
   ///
   /// Attempts to complete all of the given roots, retrying the entire set (up to `count`
   /// times) if any of them fail with `Failure::Invalidated`. Sends the result on the given
   /// mpsc Sender, which allows the caller to poll a channel for the result without blocking
   /// uninterruptibly on a Future.
   ///
+  /// If the Session is cancelled, stops waiting for the roots and sends `Failure::Cancelled` for
+  /// each of them.
+  ///
   /// In common usage, graph entries won't be repeatedly invalidated, but in a case where they
   /// were (say by an automated process changing files under pants), we'd want to eventually
   /// give up.
@@ -276,6 +719,7 @@ impl Scheduler {
   fn execute_helper(
     context: RootContext,
     sender: mpsc::Sender<Vec<Result<Value, Failure>>>,
+    timed_out: Shared<oneshot::Receiver<()>>,
     roots: Vec<Root>,
     count: usize,
   ) {
@@ -313,22 +757,309 @@ impl Scheduler {
         .collect::<Vec<_>>(),
     );
 
+    // If the Session is cancelled, stop waiting for the roots.
+    let roots_len = roots.len();
+    let roots_res = roots_res
+      .select2(context.session.cancelled())
+      .then(move |res| match res {
+        Ok(Either::A((res, _))) => Ok(res),
+        Err(Either::A((failure, _))) => Err(failure),
+        Ok(Either::B(_)) | Err(Either::B(_)) => {
+          Ok((0..roots_len).map(|_| Err(Failure::Cancelled)).collect())
+        }
+      });
+
+    // If the request times out, stop waiting for the roots (which cancels any Nodes that are
+    // running only on their behalf): the caller will already have reported the timeout.
+    let roots_res = roots_res.select2(timed_out.clone()).then(|res| match res {
+      Ok(Either::A((res, _))) => Ok(Some(res)),
+      Err(Either::A((failure, _))) => Err(failure),
+      Ok(Either::B(_)) | Err(Either::B(_)) => Ok(None),
+    });
+
     // If the join failed (due to `Invalidated`, since that is the only error we propagate), retry
     // the entire set of roots.
-    core.executor.spawn_and_ignore(roots_res.then(move |res| {
-      if let Ok(res) = res {
-        sender.send(res).map_err(|_| ())
-      } else {
-        Scheduler::execute_helper(context, sender, roots, count - 1);
-        Ok(())
-      }
-    }));
+    core
+      .executor
+      .spawn_and_ignore(roots_res.then(move |res| match res {
+        Ok(Some(res)) => sender.send(res).map_err(|_| ()),
+        Ok(None) => Ok(()),
+        Err(_) => {
+          Scheduler::execute_helper(context, sender, timed_out, roots, count - 1);
+          Ok(())
+        }
+      }));
+  }
+
+  ///
+  /// Cancels the given Session: in-flight and subsequent calls to `execute` for the Session will
+  /// return `Failure::Cancelled` for all of their roots.
+  ///
+  /// Cancelling a Session stops it from waiting for its roots. The Graph cancels each running Node
+  /// once nothing is waiting for it, so Nodes which were running only on behalf of this Session are
+  /// cancelled (killing their processes), while Nodes which another Session is also waiting for
+  /// continue to run.
+  ///
+  pub fn cancel(&self, session: &Session) {
+    session.cancel();
   }
 
   ///
   /// Compute the results for roots in the given request.
   ///
   pub fn execute(&self, request: &ExecutionRequest, session: &Session) -> Vec<RootResult> {
+    if session.is_cancelled() {
+      return request
+        .roots
+        .iter()
+        .map(|_| Err(Failure::Cancelled))
+        .collect();
+    }
+
+    self.begin_execution();
+    let results = self.execute_and_wait(request, session);
+    self.end_execution(session);
+    results
+  }
+
+  fn begin_execution(&self) {
+    *self.executing_requests.lock() += 1;
+  }
+
+  fn end_execution(&self, session: &Session) {
+    self.record_process_priorities(session);
+    self.evict_graph();
+    let mut executing_requests = self.executing_requests.lock();
+    *executing_requests -= 1;
+    if *executing_requests == 0 {
+      // Nodes running for another request might hold Keys that are not yet recorded in the Graph,
+      // so collect only when no other request is in progress (and hold the lock to keep one from
+      // starting).
+      self.collect_interns();
+    }
+  }
+
+  ///
+  /// Starts computing the results for the roots in the given request, which may then be polled
+  /// from the returned stream (with `poll_stream`) as each root completes. Unlike `execute`, a
+  /// root which fails with `Failure::Invalidated` is retried on its own.
+  ///
+  /// If `fail_fast` is set, the first root to fail causes any roots which have not yet completed
+  /// to stop waiting and fail with `Failure::Cancelled`.
+  ///
+  /// The stream must be passed to `finish_stream` once the caller is done with it.
+  ///
+  pub fn execute_streaming(
+    &self,
+    request: &ExecutionRequest,
+    session: &Session,
+    fail_fast: bool,
+  ) -> ExecutionStream {
+    let (sender, receiver) = mpsc::channel();
+    let (stop_sender, stopped) = oneshot::channel();
+    let mut stream = ExecutionStream {
+      // The roots of the copy do not need to be held, because once the roots are started they are
+      // recorded as the roots of the Session.
+      request: ExecutionRequest {
+        roots: request.roots.clone(),
+        timeout: request.timeout,
+        held_keys: Vec::new(),
+      },
+      session: session.clone(),
+      receiver,
+      ready: VecDeque::new(),
+      delivered: vec![false; request.roots.len()],
+      deadline: request.timeout.map(|timeout| Instant::now() + timeout),
+      stop_sender: Arc::new(Mutex::new(Some(stop_sender))),
+      started: false,
+      display_handle: None,
+      tasks_to_display: IndexMap::new(),
+    };
+    if session.is_cancelled() {
+      for index in 0..request.roots.len() {
+        stream.deliver(index, Err(Failure::Cancelled));
+      }
+      return stream;
+    }
+
+    self.begin_execution();
+    stream.started = true;
+    debug!("Launching {} roots.", request.roots.len());
+    session.extend(&request.roots);
+    if let Some(display) = session.maybe_display() {
+      display.lock().start();
+      stream.display_handle = Some(LOGGER.register_engine_display(display.clone()));
+    }
+
+    let context = RootContext {
+      core: self.core.clone(),
+      session: session.clone(),
+    };
+    let stopped = stopped.shared();
+    for (index, root) in request.roots.iter().enumerate() {
+      Scheduler::stream_root(
+        context.clone(),
+        index,
+        root.clone(),
+        sender.clone(),
+        stopped.clone(),
+        if fail_fast {
+          Some(stream.stop_sender.clone())
+        } else {
+          None
+        },
+        8,
+      );
+    }
+    stream
+  }
+
+  ///
+  /// Attempts to complete the given root, retrying it (up to `count` times) if it fails with
+  /// `Failure::Invalidated`, and then sends its result on the given Sender. Stops waiting for the
+  /// root if `stopped` completes or the Session is cancelled, in which case it fails with
+  /// `Failure::Cancelled`.
+  ///
+  /// If `fail_fast` is set and the root fails, stops all other roots which share it.
+  ///
+  fn stream_root(
+    context: RootContext,
+    index: usize,
+    root: Root,
+    sender: mpsc::Sender<(usize, RootResult)>,
+    stopped: Shared<oneshot::Receiver<()>>,
+    fail_fast: Option<StopSender>,
+    count: usize,
+  ) {
+    let core = context.core.clone();
+    let root_res = context
+      .core
+      .graph
+      .create(root.clone().into(), &context)
+      .select2(stopped.clone())
+      .then(|res| match res {
+        Ok(Either::A((res, _))) => Ok(res),
+        Err(Either::A((failure, _))) => Err(failure),
+        Ok(Either::B(_)) | Err(Either::B(_)) => Err(Failure::Cancelled),
+      })
+      .select2(context.session.cancelled())
+      .then(|res| match res {
+        Ok(Either::A((res, _))) => Ok(res),
+        Err(Either::A((failure, _))) => Err(failure),
+        Ok(Either::B(_)) | Err(Either::B(_)) => Err(Failure::Cancelled),
+      });
+
+    core.executor.spawn_and_ignore(root_res.then(move |res| {
+      if let (Err(Failure::Invalidated), true) = (&res, count > 0) {
+        Scheduler::stream_root(context, index, root, sender, stopped, fail_fast, count - 1);
+        return Ok(());
+      }
+      debug!("Root {} completed.", NodeKey::Select(Box::new(root)));
+      let res: RootResult = res.map(|res| {
+        res
+          .try_into()
+          .unwrap_or_else(|_| panic!("A Node implementation was ambiguous."))
+      });
+      let failed = res.is_err();
+      let sent = sender.send((index, res)).map_err(|_| ());
+      // Stop the other roots only after sending, so that the failure is received before any of the
+      // cancellations that it causes.
+      if let (true, Some(fail_fast)) = (failed, fail_fast) {
+        Scheduler::stop_roots(&fail_fast);
+      }
+      sent
+    }));
+  }
+
+  fn stop_roots(stop_sender: &StopSender) {
+    if let Some(stop_sender) = stop_sender.lock().take() {
+      let _ = stop_sender.send(());
+    }
+  }
+
+  ///
+  /// Waits up to `wait` for the next root of the given stream to complete. If the deadline of its
+  /// request passes while waiting, all roots which have not completed fail (see
+  /// `timed_out_results`).
+  ///
+  pub fn poll_stream(&self, stream: &mut ExecutionStream, wait: Duration) -> StreamPoll {
+    loop {
+      if let Some((index, result)) = stream.ready.pop_front() {
+        return StreamPoll::Completed(index, result);
+      }
+      if stream.delivered.iter().all(|delivered| *delivered) {
+        return StreamPoll::Done;
+      }
+
+      let wait = match stream.deadline {
+        Some(deadline) => {
+          let now = Instant::now();
+          if now >= deadline {
+            let roots = stream.request.root_nodes();
+            for (index, result) in self
+              .timed_out_results(&stream.request, &roots)
+              .into_iter()
+              .enumerate()
+            {
+              stream.deliver(index, result);
+            }
+            stream.deadline = None;
+            stream.stop();
+            continue;
+          }
+          cmp::min(wait, deadline - now)
+        }
+        None => wait,
+      };
+
+      match stream.receiver.recv_timeout(wait) {
+        Ok((index, result)) => stream.deliver(index, result),
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+          if stream
+            .deadline
+            .map_or(false, |deadline| Instant::now() >= deadline)
+          {
+            // Fail the outstanding roots on the next iteration.
+            continue;
+          }
+          if let Some(display) = stream.session.maybe_display() {
+            Scheduler::display_ongoing_tasks(
+              &self.core.graph,
+              &stream.request.root_nodes(),
+              display,
+              &mut stream.tasks_to_display,
+            );
+          }
+          return StreamPoll::Pending;
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+          // Every root has sent its result (or been dropped by the executor without completing).
+          for index in 0..stream.delivered.len() {
+            stream.deliver(index, Err(Failure::Cancelled));
+          }
+        }
+      }
+    }
+  }
+
+  ///
+  /// Stops waiting for any roots of the given stream which have not completed (which cancels any
+  /// Nodes that are running only on their behalf), and records the completion of its request.
+  ///
+  pub fn finish_stream(&self, stream: ExecutionStream) {
+    stream.stop();
+    if let Some(display_handle) = stream.display_handle {
+      LOGGER.deregister_engine_display(display_handle);
+      if let Some(display) = stream.session.maybe_display() {
+        display.lock().finish();
+      }
+    }
+    if stream.started {
+      self.end_execution(&stream.session);
+    }
+  }
+
+  fn execute_and_wait(&self, request: &ExecutionRequest, session: &Session) -> Vec<RootResult> {
     // Bootstrap tasks for the roots, and then wait for all of them.
     debug!("Launching {} roots.", request.roots.len());
 
@@ -341,14 +1072,39 @@ impl Scheduler {
       session: session.clone(),
     };
     let (sender, receiver) = mpsc::channel();
+    // Dropped when this method returns, which causes the roots to stop waiting if they have not
+    // already completed.
+    let (_timeout_sender, timed_out) = oneshot::channel::<()>();
+    let deadline = request.timeout.map(|timeout| Instant::now() + timeout);
 
-    Scheduler::execute_helper(context, sender, request.roots.clone(), 8);
+    Scheduler::execute_helper(
+      context,
+      sender,
+      timed_out.shared(),
+      request.roots.clone(),
+      8,
+    );
     let roots: Vec<NodeKey> = request
       .roots
       .clone()
       .into_iter()
       .map(NodeKey::from)
       .collect();
+    // Waits for the results for up to the refresh interval, or until the deadline has passed (in
+    // which case the outstanding roots fail).
+    let recv_results = |refresh_interval: Duration| {
+      let wait = match deadline {
+        Some(deadline) => {
+          let now = Instant::now();
+          if now >= deadline {
+            return Some(self.timed_out_results(request, &roots));
+          }
+          cmp::min(refresh_interval, deadline - now)
+        }
+        None => refresh_interval,
+      };
+      receiver.recv_timeout(wait).ok()
+    };
 
     // This map keeps the k most relevant jobs in assigned possitions.
     // Keys are positions in the display (display workers) and the values are the actual jobs to print.
@@ -364,7 +1120,7 @@ impl Scheduler {
         let unique_handle = LOGGER.register_engine_display(display.clone());
 
         let results = loop {
-          if let Ok(res) = receiver.recv_timeout(refresh_interval) {
+          if let Some(res) = recv_results(refresh_interval) {
             break res;
           } else {
             Scheduler::display_ongoing_tasks(
@@ -383,13 +1139,49 @@ impl Scheduler {
         results
       }
       None => loop {
-        if let Ok(res) = receiver.recv_timeout(refresh_interval) {
+        if let Some(res) = recv_results(refresh_interval) {
           break res;
         }
       },
     }
   }
 
+  ///
+  /// Called when a request has passed its deadline: returns the results of the roots which have
+  /// completed, and fails the rest with an error naming the longest running Nodes below them.
+  ///
+  fn timed_out_results(&self, request: &ExecutionRequest, roots: &[NodeKey]) -> Vec<RootResult> {
+    let mut longest_running: Vec<_> = self
+      .core
+      .graph
+      .heavy_hitters(roots, TIMEOUT_REPORTED_NODES)
+      .into_iter()
+      .collect();
+    longest_running.sort_by(|(_, d1), (_, d2)| d2.cmp(d1));
+    let longest_running = longest_running
+      .into_iter()
+      .map(|(node, duration)| format!("{:.2}s {}", duration.as_secs_f64(), node))
+      .collect::<Vec<_>>()
+      .join("\n  ");
+
+    roots
+      .iter()
+      .map(|root| match self.core.graph.peek(root) {
+        Some(result) => result.map(|res| {
+          res
+            .try_into()
+            .unwrap_or_else(|_| panic!("A Node implementation was ambiguous."))
+        }),
+        None => Err(throw(&format!(
+          "Timed out after {:?} waiting for {}. The longest running nodes were:\n  {}",
+          request.timeout.unwrap_or_default(),
+          root,
+          longest_running
+        ))),
+      })
+      .collect()
+  }
+
   fn display_ongoing_tasks(
     graph: &Graph<NodeKey>,
     roots: &[NodeKey],
@@ -482,4 +1274,8 @@ impl NodeContext for RootContext {
   {
     self.core.executor.spawn_and_ignore(future);
   }
+
+  fn record_event(&self, node: &NodeKey, event: NodeEvent) {
+    self.session.record_node_event(node, event);
+  }
 }
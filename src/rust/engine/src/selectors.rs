@@ -73,6 +73,16 @@ impl rule_graph::DependencyKey for DependencyKey {
       DependencyKey::JustSelect(_) => None,
     }
   }
+
+  fn for_union_member(&self, member: TypeId) -> DependencyKey {
+    match self {
+      DependencyKey::JustGet(ref g) => DependencyKey::JustGet(Get {
+        product: g.product,
+        subject: member,
+      }),
+      DependencyKey::JustSelect(_) => panic!("A Select cannot provide a union: {}", self),
+    }
+  }
 }
 
 impl fmt::Display for DependencyKey {
@@ -45,6 +45,13 @@ impl rule_graph::Rule for Rule {
       &Rule::Intrinsic(_) => false,
     }
   }
+
+  fn priority(&self) -> i32 {
+    match self {
+      &Rule::Task(ref task) => task.priority,
+      &Rule::Intrinsic(_) => 0,
+    }
+  }
 }
 
 impl fmt::Display for Rule {
@@ -94,6 +101,7 @@ pub struct Task {
   pub func: Function,
   pub cacheable: bool,
   pub display_info: Option<String>,
+  pub priority: i32,
 }
 
 ///
@@ -103,6 +111,8 @@ pub struct Task {
 pub struct Tasks {
   // output product type -> list of rules providing it
   rules: HashMap<TypeId, Vec<Rule>>,
+  // union base type -> list of member types
+  unions: HashMap<TypeId, Vec<TypeId>>,
   // Used during the construction of the tasks map.
   preparing: Option<Task>,
 }
@@ -121,6 +131,7 @@ impl Tasks {
   pub fn new() -> Tasks {
     Tasks {
       rules: HashMap::default(),
+      unions: HashMap::default(),
       preparing: None,
     }
   }
@@ -129,6 +140,25 @@ impl Tasks {
     &self.rules
   }
 
+  pub fn unions(&self) -> &HashMap<TypeId, Vec<TypeId>> {
+    &self.unions
+  }
+
+  ///
+  /// Registers a union base type, which may have zero or more members. A Get whose subject is the
+  /// union base is expanded in the RuleGraph to a Get for each member of the union.
+  ///
+  pub fn add_union(&mut self, union_base: TypeId) {
+    self.unions.entry(union_base).or_insert_with(Vec::new);
+  }
+
+  pub fn add_union_member(&mut self, union_base: TypeId, member: TypeId) {
+    let members = self.unions.entry(union_base).or_insert_with(Vec::new);
+    if !members.contains(&member) {
+      members.push(member);
+    }
+  }
+
   pub fn intrinsics_set(&mut self, types: &Types) {
     let intrinsics = vec![
       Intrinsic {
@@ -177,7 +207,7 @@ impl Tasks {
   ///
   /// The following methods define the Task registration lifecycle.
   ///
-  pub fn task_begin(&mut self, func: Function, product: TypeId, cacheable: bool) {
+  pub fn task_begin(&mut self, func: Function, product: TypeId, cacheable: bool, priority: i32) {
     assert!(
       self.preparing.is_none(),
       "Must `end()` the previous task creation before beginning a new one!"
@@ -190,6 +220,7 @@ impl Tasks {
       gets: Vec::new(),
       func: func,
       display_info: None,
+      priority: priority,
     });
   }
 
@@ -0,0 +1,220 @@
+// Copyright 2019 Pants project contributors (see CONTRIBUTORS.md).
+// Licensed under the Apache License, Version 2.0 (see LICENSE).
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use graph::{EntryTiming, Node};
+use serde_json::{json, Value as JsonValue};
+use workunit_store::WorkUnit;
+
+use crate::nodes::NodeKey;
+
+// The process ids under which Nodes and workunits are rendered.
+const NODES_PID: usize = 1;
+const WORKUNITS_PID: usize = 2;
+
+// A color name reserved by the trace viewer, used to highlight the critical path.
+const CRITICAL_PATH_COLOR: &str = "terrible";
+
+///
+/// Writes the given Node timings and workunits to the given path in the Chrome `trace_event`
+/// format, which can be viewed in `about:tracing` or Perfetto.
+///
+/// Because Nodes are not bound to threads, each is assigned to one of a set of "slots", such that
+/// no two Nodes in a slot overlap, and likewise for workunits. Nodes on the critical path are
+/// highlighted, and each dependency edge is rendered as a flow event from the end of the dependency
+/// into its dependent.
+///
+pub fn write(
+  path: &Path,
+  timings: &[EntryTiming<NodeKey>],
+  critical_path: &HashSet<NodeKey>,
+  workunits: &[WorkUnit],
+) -> Result<(), String> {
+  let mut events = vec![
+    process_name(NODES_PID, "Nodes"),
+    process_name(WORKUNITS_PID, "Workunits"),
+  ];
+
+  // Lay out the Nodes.
+  let clock = Clock::new();
+  let node_spans: Vec<_> = timings
+    .iter()
+    .map(|timing| {
+      let start = clock.micros_since_epoch(timing.start_time);
+      (start, start + timing.duration.as_micros() as u64)
+    })
+    .collect();
+  let node_slots = assign_slots(&node_spans);
+  let mut node_indexes = HashMap::new();
+  for (index, timing) in timings.iter().enumerate() {
+    let (start, end) = node_spans[index];
+    let mut event = json!({
+      "name": timing.node.user_facing_name().unwrap_or_else(|| timing.node.to_string()),
+      "cat": "node",
+      "ph": "X",
+      "ts": start,
+      "dur": end - start,
+      "pid": NODES_PID,
+      "tid": node_slots[index],
+      "args": {"node": timing.node.to_string()},
+    });
+    if critical_path.contains(&timing.node) {
+      event["cat"] = json!("critical_path");
+      event["cname"] = json!(CRITICAL_PATH_COLOR);
+    }
+    events.push(event);
+    node_indexes.insert(&timing.node, index);
+  }
+
+  // Render dependency edges for dependencies which completed while their dependent was running.
+  let mut flow_id = 0;
+  for (index, timing) in timings.iter().enumerate() {
+    let (start, end) = node_spans[index];
+    for dependency in &timing.dependencies {
+      let dep_index = match node_indexes.get(dependency) {
+        Some(&dep_index) => dep_index,
+        None => continue,
+      };
+      let (_, dep_end) = node_spans[dep_index];
+      if dep_end < start || dep_end > end {
+        continue;
+      }
+      flow_id += 1;
+      events.push(json!({
+        "name": "dependency",
+        "cat": "dependency",
+        "ph": "s",
+        "id": flow_id,
+        "ts": dep_end,
+        "pid": NODES_PID,
+        "tid": node_slots[dep_index],
+      }));
+      events.push(json!({
+        "name": "dependency",
+        "cat": "dependency",
+        "ph": "f",
+        "bp": "e",
+        "id": flow_id,
+        "ts": dep_end,
+        "pid": NODES_PID,
+        "tid": node_slots[index],
+      }));
+    }
+  }
+
+  // Lay out the workunits.
+  let workunit_spans: Vec<_> = workunits
+    .iter()
+    .map(|workunit| {
+      let start: Duration = workunit.time_span.start.into();
+      let duration: Duration = workunit.time_span.duration.into();
+      (
+        start.as_micros() as u64,
+        (start + duration).as_micros() as u64,
+      )
+    })
+    .collect();
+  let workunit_slots = assign_slots(&workunit_spans);
+  for (index, workunit) in workunits.iter().enumerate() {
+    let (start, end) = workunit_spans[index];
+    events.push(json!({
+      "name": workunit.name,
+      "cat": "workunit",
+      "ph": "X",
+      "ts": start,
+      "dur": end - start,
+      "pid": WORKUNITS_PID,
+      "tid": workunit_slots[index],
+      "args": {"span_id": workunit.span_id, "parent_id": workunit.parent_id},
+    }));
+  }
+
+  let file = File::create(path)
+    .map_err(|e| format!("Failed to create trace events file {:?}: {}", path, e))?;
+  serde_json::to_writer(
+    BufWriter::new(file),
+    &json!({"traceEvents": events, "displayTimeUnit": "ms"}),
+  )
+  .map_err(|e| format!("Failed to write trace events to {:?}: {}", path, e))
+}
+
+fn process_name(pid: usize, name: &str) -> JsonValue {
+  json!({
+    "name": "process_name",
+    "ph": "M",
+    "pid": pid,
+    "args": {"name": name},
+  })
+}
+
+///
+/// Assigns each of the given (start, end) spans to the lowest numbered slot which is free when the
+/// span starts, and returns the slot for each span.
+///
+pub(crate) fn assign_slots(spans: &[(u64, u64)]) -> Vec<usize> {
+  let mut order: Vec<usize> = (0..spans.len()).collect();
+  order.sort_by_key(|&index| spans[index]);
+
+  let mut slots = vec![0; spans.len()];
+  // The slots which are free, and the slots which are busy along with the time they become free.
+  let mut free_slots: BinaryHeap<Reverse<usize>> = BinaryHeap::new();
+  let mut busy_slots: BinaryHeap<Reverse<(u64, usize)>> = BinaryHeap::new();
+  let mut slot_count = 0;
+  for index in order {
+    let (start, end) = spans[index];
+    while let Some(&Reverse((busy_until, slot))) = busy_slots.peek() {
+      if busy_until > start {
+        break;
+      }
+      busy_slots.pop();
+      free_slots.push(Reverse(slot));
+    }
+    let slot = if let Some(Reverse(slot)) = free_slots.pop() {
+      slot
+    } else {
+      slot_count += 1;
+      slot_count - 1
+    };
+    busy_slots.push(Reverse((end, slot)));
+    slots[index] = slot;
+  }
+  slots
+}
+
+///
+/// Converts `Instant`s (which are opaque) into times since the UNIX epoch, which is what workunits
+/// are recorded relative to.
+///
+struct Clock {
+  now: Instant,
+  now_since_epoch: Duration,
+}
+
+impl Clock {
+  fn new() -> Clock {
+    Clock {
+      now: Instant::now(),
+      now_since_epoch: SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default(),
+    }
+  }
+
+  fn micros_since_epoch(&self, instant: Instant) -> u64 {
+    let since_epoch = if instant <= self.now {
+      self
+        .now_since_epoch
+        .checked_sub(self.now.duration_since(instant))
+        .unwrap_or_default()
+    } else {
+      self.now_since_epoch + instant.duration_since(self.now)
+    };
+    since_epoch.as_micros() as u64
+  }
+}
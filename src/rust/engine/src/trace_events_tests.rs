@@ -0,0 +1,87 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::time::Duration;
+
+use concrete_time::TimeSpan;
+use serde_json::{json, Value as JsonValue};
+use tempfile::TempDir;
+use workunit_store::WorkUnit;
+
+use crate::trace_events::{assign_slots, write};
+
+#[test]
+fn slots_are_reused_once_free() {
+  assert_eq!(assign_slots(&[(0, 10), (10, 20)]), vec![0, 0]);
+}
+
+#[test]
+fn overlapping_spans_are_assigned_distinct_slots() {
+  assert_eq!(assign_slots(&[(0, 10), (5, 15), (12, 20)]), vec![0, 1, 0]);
+}
+
+#[test]
+fn spans_are_assigned_the_lowest_free_slot() {
+  // Spans are laid out in order of their starts, regardless of the order they are given in.
+  assert_eq!(
+    assign_slots(&[(6, 8), (0, 20), (1, 5), (2, 20)]),
+    vec![1, 0, 1, 2]
+  );
+}
+
+fn workunit(
+  name: &str,
+  start_millis: u64,
+  duration_millis: u64,
+  parent_id: Option<&str>,
+) -> WorkUnit {
+  WorkUnit {
+    name: name.to_owned(),
+    time_span: TimeSpan {
+      start: Duration::from_millis(start_millis).into(),
+      duration: Duration::from_millis(duration_millis).into(),
+    },
+    span_id: format!("{}_span", name),
+    parent_id: parent_id.map(str::to_owned),
+  }
+}
+
+#[test]
+fn write_workunits() {
+  let dir = TempDir::new().unwrap();
+  let path = dir.path().join("trace.json");
+  let workunits = vec![
+    workunit("parent", 1, 10, None),
+    workunit("child", 2, 5, Some("parent_span")),
+  ];
+  write(&path, &[], &HashSet::new(), &workunits).unwrap();
+
+  let trace: JsonValue = serde_json::from_reader(File::open(&path).unwrap()).unwrap();
+  assert_eq!(trace["displayTimeUnit"], json!("ms"));
+  assert_eq!(
+    trace["traceEvents"],
+    json!([
+      {"name": "process_name", "ph": "M", "pid": 1, "args": {"name": "Nodes"}},
+      {"name": "process_name", "ph": "M", "pid": 2, "args": {"name": "Workunits"}},
+      {
+        "name": "parent",
+        "cat": "workunit",
+        "ph": "X",
+        "ts": 1000,
+        "dur": 10000,
+        "pid": 2,
+        "tid": 0,
+        "args": {"span_id": "parent_span", "parent_id": null},
+      },
+      {
+        "name": "child",
+        "cat": "workunit",
+        "ph": "X",
+        "ts": 2000,
+        "dur": 5000,
+        "pid": 2,
+        "tid": 1,
+        "args": {"span_id": "child_span", "parent_id": "parent_span"},
+      },
+    ])
+  );
+}
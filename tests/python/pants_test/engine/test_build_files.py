@@ -340,7 +340,7 @@ class InlinedGraphTest(GraphTestBase):
     self.do_test_trace_message(
         scheduler,
         parsed_address,
-        f'(?ms)Dep graph contained a cycle:.*{cyclic_address_str}.* <-.*{cyclic_address_str}.* <-'
+        f'(?ms)Dep graph contained (a cycle|\\d+ cycles):.*{cyclic_address_str}.* <-.*{cyclic_address_str}.* <-'
       )
 
   def assert_throws_are_leaves(self, error_msg, throw_name) -> None:
@@ -369,6 +369,18 @@ class InlinedGraphTest(GraphTestBase):
   def test_cycle_indirect(self) -> None:
     self.do_test_cycle('graph_test:indirect_cycle', 'graph_test:one')
 
+  def test_cycle_describes_dependencies(self) -> None:
+    # Each Node in a cycle is rendered along with the reason that the previous Node depends on it:
+    # rules request their dependencies via Gets, and Selects are computed by rules.
+    scheduler = self.create_json()
+    self.do_test_trace_message(
+        scheduler,
+        Address.parse('graph_test:direct_cycle'),
+        r'(?ms)Dep graph contained'
+        r'(?=.*\(requested via Get\(HydratedStruct, Address\) by rule [^)]*hydrate_struct)'
+        r'(?=.*\(computed by rule [^)]*hydrate_struct)'
+      )
+
   def test_type_mismatch_error(self) -> None:
     scheduler = self.create_json()
     mismatch = Address.parse('graph_test:type_mismatch')
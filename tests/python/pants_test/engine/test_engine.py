@@ -1,6 +1,9 @@
 # Copyright 2015 Pants project contributors (see CONTRIBUTORS.md).
 # Licensed under the Apache License, Version 2.0 (see LICENSE).
 
+import json
+import threading
+import time
 import unittest
 from dataclasses import dataclass, field
 from textwrap import dedent
@@ -11,6 +14,7 @@ from pants.engine.scheduler import ExecutionError
 from pants.engine.selectors import Get, MultiGet
 from pants.reporting.streaming_workunit_handler import StreamingWorkunitHandler
 from pants.testutil.engine.util import assert_equal_with_printing, remove_locations_from_traceback
+from pants.util.contextutil import temporary_file_path
 from pants_test.engine.scheduler_test_base import SchedulerTestBase
 
 
@@ -39,6 +43,19 @@ def nested_raise(x: B) -> A:  # type: ignore[return]
   fn_raises(x)
 
 
+@dataclass(frozen=True)
+class Sleep:
+  seconds: float
+  # May be set to end the sleep early.
+  wake: threading.Event = field(default_factory=threading.Event, compare=False)
+
+
+@rule
+def slow(sleep: Sleep) -> A:
+  sleep.wake.wait(sleep.seconds)
+  return A()
+
+
 @dataclass(frozen=True)
 class Fib:
   val: int
@@ -85,6 +102,173 @@ class EngineTest(unittest.TestCase, SchedulerTestBase):
 
     self.assertEqual(55, fib_10.val)
 
+  def test_node_metrics(self):
+    rules = [
+      fib,
+      RootRule(int),
+    ]
+    scheduler = self.mk_scheduler(rules=rules)
+
+    scheduler.product_request(Fib, subjects=[10])
+    node_metrics = scheduler.node_metrics(scheduler.metrics())
+
+    # Each of the eleven distinct Fibs is computed by a Task which runs exactly once.
+    fib_counts = node_metrics["rules"]["fib()"]
+    self.assertEqual(11, fib_counts["created"])
+    self.assertEqual(11, fib_counts["ran"])
+    self.assertEqual(0, fib_counts["reran"])
+    self.assertEqual(0, fib_counts["cleaned"])
+    self.assertGreaterEqual(fib_counts["total_runtime_ms"], fib_counts["p95_runtime_ms"])
+    self.assertEqual(fib_counts, node_metrics["variants"]["Task"])
+    self.assertEqual(
+      {"local_cache": 0, "remote_cache": 0, "executed": 0},
+      node_metrics["processes"],
+    )
+
+  def test_query_graph(self):
+    rules = [
+      fib,
+      RootRule(int),
+    ]
+    scheduler = self.mk_scheduler(rules=rules)
+    scheduler.product_request(Fib, subjects=[5])
+
+    # The Select for Fib(3) is already in the graph, because Fib(4) and Fib(5) requested it.
+    fib_3_request = scheduler.execution_request([Fib], [3])
+
+    def depths(**kwargs):
+      return [result["depth"] for result in scheduler.query_graph(fib_3_request, **kwargs)]
+
+    # Each Fib is computed by a Task, which requests its dependencies via Selects.
+    self.assertEqual([1, 3, 3, 5], depths(variants=["Task"]))
+    self.assertEqual([1, 3, 3], depths(variants=["Task"], max_depth=3))
+    self.assertEqual([1, 2, 2, 3, 3, 4, 5], depths())
+    self.assertEqual(
+      {"Select", "Task"},
+      {result["variant"] for result in scheduler.query_graph(fib_3_request)},
+    )
+    # Fib(3) was requested by the Tasks for both Fib(4) and Fib(5).
+    self.assertEqual([1, 1], depths(dependents=True, variants=["Task"]))
+    self.assertEqual([1, 1, 2, 2], depths(dependents=True))
+
+  def test_visualize_graph_to_json_file(self):
+    rules = [
+      fib,
+      RootRule(int),
+    ]
+    scheduler = self.mk_scheduler(rules=rules)
+
+    fib_3_request = scheduler.execution_request([Fib], [3])
+    scheduler.execute(fib_3_request)
+    scheduler.product_request(Fib, subjects=[10])
+
+    def task_names(**kwargs):
+      with temporary_file_path() as path:
+        scheduler.visualize_graph_to_json_file(path, variants=["Task"], **kwargs)
+        with open(path, 'r') as f:
+          return [node["name"] for node in json.load(f)["nodes"]]
+
+    # Below the roots of the session, each of the eleven distinct Fibs is computed by a Task.
+    self.assertEqual(11, len(task_names()))
+    # Below the roots of a request, only the Tasks for the Fibs that it depends on are included.
+    self.assertEqual(4, len(task_names(subgraph_root=fib_3_request)))
+    # Minimum durations are applied with sub-millisecond precision.
+    self.assertEqual(11, len(task_names(min_duration=0.0000001)))
+    self.assertEqual([], task_names(min_duration=3600))
+
+  def test_execute_streaming(self):
+    rules = [
+      fib,
+      RootRule(int),
+    ]
+    scheduler = self.mk_scheduler(rules=rules)
+
+    request = scheduler.execution_request([Fib], [3, 5, 8])
+    results = dict(scheduler.execute_streaming(request))
+
+    self.assertEqual(set(request.roots), set(results))
+    self.assertEqual(
+      {(3, 2), (5, 5), (8, 21)},
+      {(subject, state.value.val) for (subject, _), state in results.items()},
+    )
+
+  def test_execute_streaming_fail_fast(self):
+    rules = [
+      RootRule(B),
+      RootRule(Sleep),
+      nested_raise,
+      slow,
+    ]
+    scheduler = self.scheduler(rules, include_trace_on_error=False)
+
+    # The failure of the first root cancels the second, which would otherwise sleep for a minute.
+    sleep = Sleep(60)
+    request = scheduler.execution_request([A], [B(), sleep])
+    try:
+      results = list(scheduler.execute_streaming(request, fail_fast=True))
+    finally:
+      # The rule for the second root keeps running after it is cancelled: end it.
+      sleep.wake.set()
+
+    self.assertEqual(2, len(results))
+    (first_root, first), (second_root, second) = results
+    self.assertIsInstance(first_root[0], B)
+    self.assertEqual('An exception for B', str(first.exc))
+    self.assertEqual(Sleep(60), second_root[0])
+    self.assertEqual('The request was cancelled.', str(second.exc))
+
+  def test_cancel_with_shared_node(self):
+    rules = [
+      RootRule(Sleep),
+      slow,
+    ]
+    session = self.scheduler(rules, include_trace_on_error=False)
+    other_session = session._scheduler.new_session(zipkin_trace_v2=False, build_id="other")
+
+    # Both Sessions wait for the same Node, and one of them is cancelled while it runs.
+    other_results = []
+    other = threading.Thread(
+      target=lambda: other_results.append(
+        other_session.execute(other_session.execution_request([A], [Sleep(1)]))
+      )
+    )
+    other.start()
+    threading.Timer(0.2, session.cancel).start()
+    returns, throws = session.execute(session.execution_request([A], [Sleep(1)]))
+    other.join()
+
+    # The cancelled Session fails, but the Node keeps running for the other Session.
+    self.assertEqual((), returns)
+    self.assertEqual('The request was cancelled.', str(throws[0][1].exc))
+    (other_returns, other_throws), = other_results
+    self.assertEqual((), other_throws)
+    self.assertIsInstance(other_returns[0][1].value, A)
+
+  def test_execution_timeout(self):
+    rules = [
+      RootRule(Sleep),
+      slow,
+    ]
+    scheduler = self.scheduler(rules, include_trace_on_error=False)
+
+    # The first root outlives the timeout, while the second completes before it.
+    sleep = Sleep(60)
+    request = scheduler.execution_request([A], [sleep, Sleep(0)], timeout=0.5)
+    try:
+      returns, throws = scheduler.execute(request)
+    finally:
+      sleep.wake.set()
+
+    self.assertEqual(1, len(returns))
+    (returned_root, _), returned = returns[0]
+    self.assertEqual(Sleep(0), returned_root)
+    self.assertIsInstance(returned.value, A)
+    self.assertEqual(1, len(throws))
+    (thrown_root, _), thrown = throws[0]
+    self.assertEqual(sleep, thrown_root)
+    self.assertIn('Timed out after', str(thrown.exc))
+    self.assertIn('The longest running nodes were:', str(thrown.exc))
+
   def test_no_include_trace_error_raises_boring_error(self):
     rules = [
       RootRule(B),
@@ -96,7 +280,11 @@ class EngineTest(unittest.TestCase, SchedulerTestBase):
     with self.assertRaises(ExecutionError) as cm:
       list(scheduler.product_request(A, subjects=[(B())]))
 
-    self.assert_equal_with_printing('1 Exception encountered:\n  Exception: An exception for B', str(cm.exception))
+    self.assert_equal_with_printing(dedent('''
+      1 Exception encountered:
+        Exception: An exception for B
+          while computing A for <pants_test.engine.test_engine.B object at 0xEEEEEEEEE> in nested_raise()''').lstrip(),
+      remove_locations_from_traceback(str(cm.exception)))
 
   def test_no_include_trace_error_multiple_paths_raises_executionerror(self):
     rules = [
@@ -112,8 +300,39 @@ class EngineTest(unittest.TestCase, SchedulerTestBase):
     self.assert_equal_with_printing(dedent('''
       2 Exceptions encountered:
         Exception: An exception for B
-        Exception: An exception for B''').lstrip(),
-      str(cm.exception))
+          while computing A for <pants_test.engine.test_engine.B object at 0xEEEEEEEEE> in nested_raise()
+        Exception: An exception for B
+          while computing A for <pants_test.engine.test_engine.B object at 0xEEEEEEEEE> in nested_raise()''').lstrip(),
+      remove_locations_from_traceback(str(cm.exception)))
+
+  def test_rule_trace(self):
+    @rule
+    def c_from_b_nested_raise(b: B) -> C:  # type: ignore[return]
+      fn_raises(b)
+
+    @rule
+    def a_from_c(c: C) -> A:
+      return A()
+
+    rules = [
+      RootRule(B),
+      c_from_b_nested_raise,
+      a_from_c,
+    ]
+
+    scheduler = self.scheduler(rules, include_trace_on_error=False)
+    _, throws = scheduler.execute(scheduler.execution_request([A], [B()]))
+
+    _, state = throws[0]
+    self.assertEqual(
+      [('c_from_b_nested_raise()', 'C'), ('a_from_c()', 'A')],
+      [(frame.rule, frame.product) for frame in state.rule_trace],
+    )
+    self.assert_equal_with_printing(dedent('''
+      Exception: An exception for B
+        while computing C for <pants_test.engine.test_engine.B object at 0xEEEEEEEEE> in c_from_b_nested_raise()
+        needed by A for <pants_test.engine.test_engine.B object at 0xEEEEEEEEE> in a_from_c()''').lstrip(),
+      remove_locations_from_traceback(state.render()))
 
   def test_include_trace_error_raises_error_with_trace(self):
     rules = [
@@ -134,6 +134,17 @@ class RuleArgumentAnnotationTest(unittest.TestCase):
       def named_rule(a: int, b: str) -> bool:
         return False
 
+  def test_priority_kwarg(self):
+    @rule(priority=1)
+    def prioritized_rule(a: int, b: str) -> bool:
+      return False
+    self.assertEqual(prioritized_rule.rule.priority, 1)
+
+    @rule
+    def unprioritized_rule(a: int, b: str) -> bool:
+      return False
+    self.assertEqual(unprioritized_rule.rule.priority, 0)
+
   def test_goal_rule_automatically_gets_name_from_goal(self):
     @goal_rule
     def some_goal_rule() -> Example:
@@ -257,6 +268,36 @@ class RuleGraphTest(TestBase):
                      """).strip(),
       str(cm.exception))
 
+  def test_ruleset_with_priority(self):
+    @rule
+    def a_from_b(b: B) -> A:
+      pass
+
+    # Chosen over a_from_b despite consuming more Params, because it has a higher priority.
+    @rule(priority=1)
+    def a_from_b_and_c(b: B, c: C) -> A:
+      pass
+
+    rules = [
+        a_from_b,
+        a_from_b_and_c,
+        RootRule(B),
+        RootRule(C),
+      ]
+    scheduler = create_scheduler(rules)
+
+    fullgraph = "\n".join(scheduler.rule_graph_visualization())
+    self.assertIn("a_from_b_and_c()", fullgraph)
+    self.assertNotIn("a_from_b()", fullgraph)
+
+    diagnostics = scheduler.rule_graph_diagnostics()
+    self.assertEqual(1, len(diagnostics))
+    self.assertIn("a_from_b()", diagnostics[0]["rule"])
+    [shadowed] = diagnostics[0]["diagnostics"]
+    self.assertEqual("shadowed", shadowed["kind"])
+    self.assertEqual(1, len(shadowed["candidates"]))
+    self.assertIn("a_from_b_and_c()", shadowed["candidates"][0])
+
   def test_ruleset_with_rule_with_two_missing_selects(self):
     @rule
     def a_from_b_and_c(b: B, c: C) -> A: